@@ -0,0 +1,131 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Read-only HTTP API listing the Records and Zones managed by ARES, for dashboards and for
+//! operators inspecting ARES without going through every Record with kubectl. `/api/records`
+//! lists every Record with its desired values, the values observed at each provider and the
+//! result of its last sync, while `/api/zones` lists every Zone with the number of Records in it.
+
+// {{{ imports
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use kube_runtime::controller::Context;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+use tracing_futures::Instrument;
+
+use crate::cli::Opts;
+use crate::controller::Data;
+use crate::record_spec::Record;
+use crate::zone_spec::find_zone;
+// }}}
+
+/// Get the values a Record should have: the values given in the Record, or otherwise the values
+/// collected for the Record when it was last synced.
+fn desired_values(record: &Record) -> Option<Vec<String>> {
+    record.spec.value.clone().or_else(|| {
+        record.status
+            .as_ref()
+            .and_then(|status| status.history.as_ref())
+            .and_then(|history| history.last())
+            .map(|applied| applied.values.clone())
+    })
+}
+
+/// List every Record with its desired and observed values and the result of its last sync. The
+/// observed values are only known when the state cache is enabled.
+fn records(data: &Data) -> Value {
+    let records = data.records().into_iter().map(|record| {
+        let status = record.status.clone().unwrap_or_default();
+        json!({
+            "namespace": record.metadata.namespace,
+            "name": record.metadata.name,
+            "fqdn": record.spec.fqdn,
+            "type": record.spec.type_,
+            "ttl": record.spec.ttl,
+            "desired": desired_values(&record),
+            "observed": data.cached_values(&record),
+            "lastSync": {
+                "ready": status.ready,
+                "failures": status.failures,
+                "lastError": status.last_error,
+                "providers": status.providers,
+            },
+        })
+    });
+    Value::Array(records.collect())
+}
+
+/// List every Zone with the number of Records in the zone and how many of them are ready.
+fn zones(data: &Data) -> Value {
+    let zones = data.zones();
+    let records = data.records();
+    let zones = zones.iter().map(|zone| {
+        let in_zone: Vec<&Record> = records
+            .iter()
+            .filter(|record| {
+                find_zone(&zones, &record.spec.fqdn)
+                    .map(|found| found.metadata.name == zone.metadata.name)
+                    .unwrap_or(false)
+            })
+            .collect();
+        let ready = in_zone
+            .iter()
+            .filter(|record| record.status.as_ref().and_then(|x| x.ready) == Some(true))
+            .count();
+        json!({
+            "name": zone.metadata.name,
+            "zone": zone.spec.zone,
+            "providerRef": zone.spec.provider_ref,
+            "defaultTtl": zone.spec.default_ttl,
+            "allowedTypes": zone.spec.allowed_types,
+            "records": in_zone.len(),
+            "ready": ready,
+        })
+    });
+    Value::Array(zones.collect())
+}
+
+/// Respond to a request to the API. Only GET requests are answered, as the API is read-only.
+async fn handle(context: Context<Data>, request: Request<Body>) ->
+        Result<Response<Body>, Infallible> {
+    let data = context.get_ref();
+    let (status, body) = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/api/records") => (StatusCode::OK, records(data)),
+        (&Method::GET, "/api/zones") => (StatusCode::OK, zones(data)),
+        (&Method::GET, _) => (StatusCode::NOT_FOUND, json!({"error": "not found"})),
+        _ => (StatusCode::METHOD_NOT_ALLOWED, json!({"error": "method not allowed"})),
+    };
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response.headers_mut().insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    Ok(response)
+}
+
+/// Start serving the API on the address given on the command line. Nothing is served if no
+/// address is given.
+pub async fn spawn(context: Context<Data>, opts: &Opts) -> Result<()> {
+    let address: SocketAddr = match &opts.api_address {
+        Some(address) => address.parse()?,
+        None => return Ok(()),
+    };
+    let server = Server::try_bind(&address)?.serve(make_service_fn(move |_| {
+        let context = context.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| handle(context.clone(), request)))
+        }
+    }));
+    info!(address = %address, "Serving API");
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            warn!("Error serving API: {}", e);
+        }
+    }.in_current_span());
+    Ok(())
+}
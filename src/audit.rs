@@ -0,0 +1,171 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Audit log of every record created or deleted at a provider, written as a stream of JSON
+//! objects to stdout and optionally kept in a ConfigMap, so that changes to DNS can be reviewed
+//! after the fact.
+
+// {{{ imports
+use std::collections::{BTreeMap, VecDeque};
+
+use anyhow::Result;
+use chrono::Utc;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{
+    api::{Api, ObjectMeta, PatchParams, PostParams},
+    Client,
+};
+use serde::Serialize;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tracing::warn;
+use tracing_futures::Instrument;
+
+use crate::cli::Opts;
+use crate::providers::util::{
+    Change, ChangeKind, FullDomainName, RecordType, ZoneDomainName,
+};
+// }}}
+
+/// Key of the ConfigMap holding the audit log, with one JSON object per line.
+static CONFIGMAP_KEY: &str = "audit.jsonl";
+
+/// A record created or deleted at a provider, and the Record it was changed for.
+#[derive(Serialize, Debug)]
+#[serde(rename_all="camelCase")]
+struct AuditEntry {
+    time: String,
+    action: ChangeKind,
+    zone: ZoneDomainName,
+    fqdn: FullDomainName,
+    #[serde(rename="type")]
+    type_: RecordType,
+    ttl: u64,
+    old_value: Option<String>,
+    new_value: Option<String>,
+    owner: Option<String>,
+    cluster: Option<String>,
+    resource: Option<String>,
+    uid: Option<String>,
+}
+
+impl AuditEntry {
+    fn new(change: Change) -> AuditEntry {
+        let (old_value, new_value) = match change.kind {
+            ChangeKind::Create => (None, Some(change.record.value)),
+            ChangeKind::Delete => (Some(change.record.value), None),
+        };
+        AuditEntry {
+            time: Utc::now().to_rfc3339(),
+            action: change.kind,
+            zone: change.record.zone,
+            fqdn: change.record.fqdn,
+            type_: change.record.record_type,
+            ttl: change.record.ttl,
+            old_value: old_value,
+            new_value: new_value,
+            owner: change.heritage.owner,
+            cluster: change.heritage.cluster,
+            resource: change.heritage.resource,
+            uid: change.heritage.uid,
+        }
+    }
+}
+
+/// Ring buffer of the latest audit entries, stored in a ConfigMap.
+struct ConfigMapSink {
+    api: Api<ConfigMap>,
+    name: String,
+    size: usize,
+    entries: Option<VecDeque<String>>,
+}
+
+impl ConfigMapSink {
+    /// Load the entries already in the ConfigMap, creating the ConfigMap if it does not exist.
+    async fn load(&self) -> Result<VecDeque<String>> {
+        match self.api.get(&self.name).await {
+            Ok(configmap) => {
+                let entries = configmap
+                    .data
+                    .and_then(|mut data| data.remove(CONFIGMAP_KEY))
+                    .unwrap_or_default();
+                Ok(entries.lines().map(str::to_string).collect())
+            },
+            Err(kube::Error::Api(e)) if e.code == 404 => {
+                let configmap = ConfigMap {
+                    metadata: ObjectMeta {
+                        name: Some(self.name.clone()),
+                        ..ObjectMeta::default()
+                    },
+                    data: Some(BTreeMap::new()),
+                    ..ConfigMap::default()
+                };
+                self.api.create(&PostParams::default(), &configmap).await?;
+                Ok(VecDeque::new())
+            },
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Add an entry to the ConfigMap, dropping the oldest entries past the size of the buffer.
+    async fn append(&mut self, line: String) -> Result<()> {
+        let mut entries = match self.entries.take() {
+            Some(entries) => entries,
+            None => self.load().await?,
+        };
+        entries.push_back(line);
+        while entries.len() > self.size {
+            entries.pop_front();
+        }
+        let content: Vec<&str> = entries.iter().map(String::as_str).collect();
+        let patch = serde_json::json!({
+            "data": {
+                CONFIGMAP_KEY: content.join("\n"),
+            },
+        });
+        let patched = self
+            .api
+            .patch(&self.name, &PatchParams::default(), serde_json::to_vec(&patch)?)
+            .await;
+        // The entries are reloaded from the ConfigMap if it could not be written.
+        if patched.is_ok() {
+            self.entries = Some(entries);
+        }
+        patched?;
+        Ok(())
+    }
+}
+
+/// Start writing the audit log, returning the channel that changes should be reported to.
+/// `None` is returned if the audit log is disabled.
+pub fn spawn(client: Client, opts: &Opts) -> Option<UnboundedSender<Change>> {
+    if !opts.audit_log && opts.audit_configmap.is_none() {
+        return None
+    }
+    let stdout = opts.audit_log;
+    let mut configmap = opts.audit_configmap.clone().map(|name| ConfigMapSink {
+        api: Api::namespaced(client, &opts.secret_namespace),
+        name: name,
+        size: opts.audit_configmap_size,
+        entries: None,
+    });
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Change>();
+    tokio::spawn(async move {
+        while let Some(change) = receiver.recv().await {
+            let line = match serde_json::to_string(&AuditEntry::new(change)) {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("Unable to serialize audit entry: {}", e);
+                    continue
+                },
+            };
+            if stdout {
+                println!("{}", line);
+            }
+            if let Some(sink) = configmap.as_mut() {
+                if let Err(e) = sink.append(line).await {
+                    warn!("Unable to write audit entry to ConfigMap: {}", e);
+                }
+            }
+        }
+    }.in_current_span());
+    Some(sender)
+}
@@ -1,7 +1,7 @@
 use clap::Clap;
 
 #[derive(Clap, Debug)]
-#[clap(version="1.0", author="Ryan H. <ryan@hashbang.sh>")]
+#[clap(version=env!("CARGO_PKG_VERSION"), author="Ryan H. <ryan@hashbang.sh>")]
 pub struct Opts {
     /// Name of Secret to load configuration from.
     #[clap(long, env="SECRET")]
@@ -17,4 +17,348 @@ pub struct Opts {
     #[clap(long, env="SECRET_NAMESPACE")]
     #[clap(default_value="default")]
     pub secret_namespace: String,
+
+    /// Seconds between full syncs of every Record, repairing changes made at
+    /// the provider outside of ARES. Each sync is jittered by up to 10% so
+    /// that Records are not all synced at once.
+    #[clap(long, env="RESYNC_INTERVAL")]
+    #[clap(default_value="900")]
+    pub resync_interval: u64,
+
+    /// Seconds to wait for in-flight syncs to finish when shutting down.
+    #[clap(long, env="DRAIN_TIMEOUT")]
+    #[clap(default_value="30")]
+    pub drain_timeout: u64,
+
+    /// Index of this instance when splitting Records across multiple
+    /// instances of ARES by the hash of their zone.
+    #[clap(long, env="SHARD_INDEX")]
+    #[clap(default_value="0")]
+    pub shard_index: u64,
+
+    /// Total number of instances of ARES that Records are split across.
+    #[clap(long, env="SHARD_COUNT")]
+    #[clap(default_value="1")]
+    pub shard_count: u64,
+
+    /// Maximum number of Records synced at the same time. Further Records are
+    /// queued until a sync finishes.
+    #[clap(long, env="MAX_CONCURRENT_RECONCILES")]
+    #[clap(default_value="8")]
+    pub max_concurrent_reconciles: usize,
+
+    /// Only manage Records matching this label selector, such as
+    /// `ares.syntixi.io/instance=prod`, so that multiple independent
+    /// instances of ARES can run in the same cluster.
+    #[clap(long, env="RECORD_LABEL_SELECTOR")]
+    pub record_label_selector: Option<String>,
+
+    /// Namespaces to watch Records in, separated by commas. Records are
+    /// watched in every namespace if no namespaces are given.
+    #[clap(long, env="WATCH_NAMESPACES", use_delimiter=true)]
+    pub watch_namespaces: Vec<String>,
+
+    /// Namespaces to never watch Records in, separated by commas.
+    #[clap(long, env="EXCLUDE_NAMESPACES", use_delimiter=true)]
+    pub exclude_namespaces: Vec<String>,
+
+    /// Maximum number of records that a single sync of a Record may add or
+    /// remove. Syncs that would make more changes are aborted, protecting
+    /// zones from mistakes such as a selector that no longer matches any
+    /// Pods. Unlimited if not given.
+    #[clap(long, env="MAX_CHANGES_PER_SYNC")]
+    pub max_changes_per_sync: Option<usize>,
+
+    /// Seconds between sweeps removing records at the provider whose Record
+    /// no longer exists, such as Records deleted while ARES was not running.
+    /// Sweeps are disabled when set to 0, or when Records are filtered by
+    /// label or namespace.
+    #[clap(long, env="ORPHAN_GC_INTERVAL")]
+    #[clap(default_value="3600")]
+    pub orphan_gc_interval: u64,
+
+    /// ID recorded as the owner of every domain name created by this
+    /// instance. Domain names of other owners are never modified, so that
+    /// multiple instances of ARES can share a zone.
+    #[clap(long, env="OWNER_ID")]
+    #[clap(default_value="default")]
+    pub owner_id: String,
+
+    /// Take ownership of records owned by external-dns with this owner ID,
+    /// replacing the heritage records of external-dns with tracking records.
+    /// Records owned by external-dns are otherwise never modified.
+    #[clap(long, env="IMPORT_EXTERNAL_DNS_OWNER")]
+    pub import_external_dns_owner: Option<String>,
+
+    /// ID of the cluster that ARES runs in, recorded in tracking records and
+    /// logs so that the cluster that created a record can be identified when
+    /// several clusters publish into one zone.
+    #[clap(long, env="CLUSTER_ID")]
+    pub cluster_id: Option<String>,
+
+    /// Write every record created or deleted at a provider to stdout as a
+    /// JSON object, for an audit log of DNS changes.
+    #[clap(long, env="AUDIT_LOG")]
+    pub audit_log: bool,
+
+    /// Name of a ConfigMap in the namespace of the ARES Secret to keep the
+    /// latest entries of the audit log in. Created if it does not exist.
+    #[clap(long, env="AUDIT_CONFIGMAP")]
+    pub audit_configmap: Option<String>,
+
+    /// Number of entries of the audit log kept in the audit ConfigMap.
+    #[clap(long, env="AUDIT_CONFIGMAP_SIZE")]
+    #[clap(default_value="500")]
+    pub audit_configmap_size: usize,
+
+    /// URLs to POST a notification to whenever records are created or
+    /// deleted at a provider, separated by commas.
+    #[clap(long, env="NOTIFY_WEBHOOKS", use_delimiter=true)]
+    pub notify_webhooks: Vec<String>,
+
+    /// Format of notifications: "json" posts the change as a JSON object,
+    /// and "slack" posts a message for Slack-compatible incoming webhooks.
+    #[clap(long, env="NOTIFY_FORMAT", possible_values=&["json", "slack"])]
+    #[clap(default_value="json")]
+    pub notify_format: String,
+
+    /// Seconds after which posting a notification to a webhook fails, so
+    /// that a webhook that stops responding doesn't hold back notifications.
+    #[clap(long, env="NOTIFY_TIMEOUT")]
+    #[clap(default_value="10")]
+    pub notify_timeout: u64,
+
+    /// Number of previous generations of each Record to keep the synced
+    /// values of in the status of the Record, for use with the
+    /// `ares.syntixi.io/rollback` annotation.
+    #[clap(long, env="HISTORY_SIZE")]
+    #[clap(default_value="5")]
+    pub history_size: usize,
+
+    /// Name of a ConfigMap in the namespace of the ARES Secret to persist
+    /// the records last synced to each provider in, so that Records that
+    /// have not changed are not synced again when ARES restarts.
+    #[clap(long, env="STATE_CACHE_CONFIGMAP")]
+    pub state_cache_configmap: Option<String>,
+
+    /// Path of a file to persist the records last synced to each provider
+    /// in, such as on a persistent volume, instead of a ConfigMap.
+    #[clap(long, env="STATE_CACHE_FILE")]
+    pub state_cache_file: Option<String>,
+
+    /// Seconds between writes of the state cache.
+    #[clap(long, env="STATE_CACHE_INTERVAL")]
+    #[clap(default_value="30")]
+    pub state_cache_interval: u64,
+
+    /// Address to serve the defaulting webhook for Records on, such as
+    /// "0.0.0.0:8443". The webhook is not served if not given.
+    #[clap(long, env="WEBHOOK_ADDRESS")]
+    pub webhook_address: Option<String>,
+
+    /// Path to the PEM certificate the defaulting webhook is served with.
+    #[clap(long, env="WEBHOOK_CERT")]
+    pub webhook_cert: Option<String>,
+
+    /// Path to the PEM PKCS #8 private key of the webhook certificate.
+    #[clap(long, env="WEBHOOK_KEY")]
+    pub webhook_key: Option<String>,
+
+    /// Create or update the CustomResourceDefinitions of ARES on startup.
+    #[clap(long, env="INSTALL_CRDS")]
+    pub install_crds: bool,
+
+    /// Lowest level of log messages to write.
+    #[clap(long, env="LOG_LEVEL")]
+    #[clap(possible_values=&["trace", "debug", "info", "warn", "error"])]
+    #[clap(default_value="info")]
+    pub log_level: String,
+
+    /// Format of log messages: "term" for human-readable lines, and "json"
+    /// for a JSON object per line, for log collectors.
+    #[clap(long, env="LOG_FORMAT", possible_values=&["term", "json"])]
+    #[clap(default_value="term")]
+    pub log_format: String,
+
+    /// Path of a kubeconfig to connect to the cluster with, such as to run
+    /// ARES locally against a remote cluster. Defaults to the environment of
+    /// the Pod, or to the default kubeconfig when running outside a cluster.
+    #[clap(long, env="KUBECONFIG")]
+    pub kubeconfig: Option<String>,
+
+    /// Context of the kubeconfig to connect to the cluster with, rather than
+    /// the current context.
+    #[clap(long, env="KUBE_CONTEXT")]
+    pub context: Option<String>,
+
+    /// Only manage records of these domains and their subdomains, separated
+    /// by commas, regardless of the selectors of the configuration.
+    #[clap(long, env="DOMAIN_FILTER", use_delimiter=true)]
+    pub domain_filter: Vec<String>,
+
+    /// Never manage records of these domains and their subdomains, separated
+    /// by commas, regardless of the selectors of the configuration.
+    #[clap(long, env="EXCLUDE_DOMAINS", use_delimiter=true)]
+    pub exclude_domains: Vec<String>,
+
+    /// Path of a file to load further configuration from, in the format of
+    /// the Secret, such as a mounted Secret or ConfigMap. ARES restarts when
+    /// the file changes.
+    #[clap(long, env="CONFIG_FILE")]
+    pub config_file: Option<String>,
+
+    /// Name of a ConfigMap in the namespace of the ARES Secret to load further
+    /// configuration from, stored under the same key as in the Secret.
+    #[clap(long, env="CONFIG_CONFIGMAP")]
+    pub config_configmap: Option<String>,
+
+    /// Shut down when a Record fails to sync with an error that retrying won't
+    /// fix, after marking the Record failed, so that Kubernetes restarts ARES.
+    /// Failed Records are otherwise retried with an exponential backoff while
+    /// other Records keep syncing.
+    #[clap(long, env="FAIL_FAST")]
+    pub fail_fast: bool,
+
+    /// Address to serve the `/healthz` and `/readyz` endpoints on, such as
+    /// "0.0.0.0:8080", for the probes of a Deployment, along with Prometheus
+    /// metrics at `/metrics`. The endpoints are not served if not given.
+    #[clap(long, env="HEALTH_ADDRESS")]
+    pub health_address: Option<String>,
+
+    /// Interval in seconds within which identical errors, such as those of a
+    /// Record failing every retry, are only logged once, along with the number
+    /// of times they were suppressed. Every error is logged if set to 0.
+    #[clap(long, env="LOG_RATE_LIMIT")]
+    #[clap(default_value="60")]
+    pub log_rate_limit: u64,
+
+    /// Endpoint of an OpenTelemetry collector to export spans to over OTLP,
+    /// such as "http://localhost:4317", tracing the sync of each Record
+    /// along with the requests made to its providers. Spans are not exported
+    /// if not given.
+    #[clap(long, env="OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// Sentry DSN to report panics to, along with Records that keep failing
+    /// to sync. Errors are not reported if not given.
+    #[clap(long, env="SENTRY_DSN")]
+    pub sentry_dsn: Option<String>,
+
+    /// Number of consecutive failures to sync a Record after which the
+    /// failure is reported to Sentry. Each streak of failures is reported
+    /// once.
+    #[clap(long, env="SENTRY_FAILURE_THRESHOLD")]
+    #[clap(default_value="3")]
+    pub sentry_failure_threshold: u32,
+
+    /// Address to serve the read-only API on, such as "0.0.0.0:8081", listing
+    /// every Record at `/api/records` and every Zone at `/api/zones`. The API
+    /// is not served if not given.
+    #[clap(long, env="API_ADDRESS")]
+    pub api_address: Option<String>,
+
+    /// Log the changes that would be made to the records of providers
+    /// instead of making them, while reading from providers as usual. Every
+    /// skipped change is counted in the `ares_dry_run_operations_total`
+    /// metric.
+    #[clap(long, env="DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Time in seconds for which the zone of a domain name at a provider is
+    /// cached, shared by every Record, rather than looked up by every sync.
+    /// Zones are looked up by every sync if set to 0.
+    #[clap(long, env="ZONE_CACHE_TTL")]
+    #[clap(default_value="300")]
+    pub zone_cache_ttl: u64,
+
+    /// How Records are resynced: "record" resyncs each Record on its own,
+    /// and "zone" lists the records of each zone once per resync and resyncs
+    /// every Record in the zone at once, applying every change in one call.
+    #[clap(long, env="RECONCILE_STRATEGY", possible_values=&["record", "zone"])]
+    #[clap(default_value="record")]
+    pub reconcile_strategy: String,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Subcommands of ARES. The flags of `Opts` are shared by every subcommand, and ARES runs the
+/// controller when no subcommand is given.
+#[derive(Clap, Debug)]
+pub enum Command {
+    /// Run the controller, syncing Records until terminated.
+    Run,
+    /// Print the version of ARES and exit.
+    Version,
+    /// Print the CustomResourceDefinitions of ARES as YAML and exit.
+    Crd,
+    /// Sync every Record once and exit, failing if any Record could not be synced.
+    Sync,
+    /// Check the configuration and print a report, failing if any problem is found.
+    Validate(Validate),
+    /// Print the changes that syncing every Record would make, without making them.
+    Plan,
+    /// Remove the records of every domain name owned by ARES that no Record exists for.
+    CleanupOrphans(CleanupOrphans),
+    /// Print every record of a zone at a provider as Records, or adopt them with `--apply`.
+    Import(Import),
+    /// Print every Record along with its values, records and owner at each provider.
+    Export(Export),
+    /// Print the Records of a domain name, their records at each provider and the DNS answer.
+    Query(Query),
+    /// Check the permissions of ARES, the credentials of every provider and every zone.
+    Doctor,
+}
+
+#[derive(Clap, Debug)]
+pub struct Validate {
+    /// Path of a configuration file to check instead of the configuration
+    /// in the Secret and Providers of the cluster.
+    #[clap(long)]
+    pub file: Option<String>,
+
+    /// Check the credentials of every provider by finding the zone of every
+    /// selector at the provider.
+    #[clap(long)]
+    pub check_credentials: bool,
+}
+
+#[derive(Clap, Debug)]
+pub struct CleanupOrphans {
+    /// Print the orphaned domain names without removing their records.
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Clap, Debug)]
+pub struct Import {
+    /// Name of the AresConfig of the provider hosting the zone.
+    #[clap(long)]
+    pub provider: String,
+
+    /// Domain name of the zone to import.
+    #[clap(long)]
+    pub zone: String,
+
+    /// Namespace of the imported Records.
+    #[clap(long, default_value="default")]
+    pub namespace: String,
+
+    /// Create the Records and take ownership of their records, instead of
+    /// printing the Records.
+    #[clap(long)]
+    pub apply: bool,
+}
+
+#[derive(Clap, Debug)]
+pub struct Export {
+    /// Format to print the Records in.
+    #[clap(long, possible_values=&["yaml", "json"], default_value="yaml")]
+    pub format: String,
+}
+
+#[derive(Clap, Debug)]
+pub struct Query {
+    /// Domain name to look up the Records of.
+    pub fqdn: String,
 }
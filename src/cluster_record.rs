@@ -0,0 +1,41 @@
+//! CRD and Code for clusterrecords.syntixi.io/v1alpha1
+
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+// {{{ imports
+use k8s_openapi::Resource;
+use kube_derive::CustomResource;
+use serde::{Serialize, Deserialize};
+
+use crate::record_spec::{Record, RecordSpec, RecordStatus};
+// }}}
+
+/// A Record that is not tied to any namespace, such as the apex or mail records of a zone.
+/// ClusterRecords are synced exactly like Records, except that values can't be collected from
+/// Pods or ConfigMaps in the namespace of the Record, and ZonePolicies do not apply to them.
+#[derive(CustomResource, Clone, Deserialize, Serialize, Debug)]
+#[kube(group="syntixi.io", version="v1alpha1", status="RecordStatus")]
+#[kube(shortname="crec")]
+#[kube(printcolumn=r#"{"name":"FQDN", "type":"string", "jsonPath":".spec.fqdn"}"#)]
+#[kube(printcolumn=r#"{"name":"Type", "type":"string", "jsonPath":".spec.type"}"#)]
+#[kube(printcolumn=r#"{"name":"TTL", "type":"integer", "jsonPath":".spec.ttl"}"#)]
+#[kube(printcolumn=r#"{"name":"Ready", "type":"boolean", "jsonPath":".status.ready"}"#)]
+pub struct ClusterRecordSpec {
+    #[serde(flatten)]
+    pub record: RecordSpec,
+}
+
+impl ClusterRecord {
+    /// Convert the ClusterRecord into a Record without a namespace, which the controller syncs
+    /// like any other Record. The kind of the Record is kept as ClusterRecord, so that the
+    /// Record can be told apart from Records when publishing Events.
+    pub fn to_record(&self) -> Record {
+        let mut record = Record::new(&self.metadata.name.clone().unwrap_or_default(),
+                                     self.spec.record.clone());
+        record.kind = ClusterRecord::KIND.to_string();
+        record.metadata = self.metadata.clone();
+        record.metadata.namespace = None;
+        record.status = self.status.clone();
+        record
+    }
+}
@@ -0,0 +1,78 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Clients for the cluster that ARES runs against, and for the additional clusters that values
+//! are collected from, connected to with kubeconfigs stored in Secrets.
+
+// {{{ imports
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::Api,
+    config::{Config, KubeConfigOptions, Kubeconfig},
+    Client,
+};
+
+use crate::cli::Opts;
+use crate::program_config::{AresConfig, ClusterRef};
+// }}}
+
+/// Build the Client for the cluster that ARES runs against: from the kubeconfig and context given
+/// with `--kubeconfig` and `--context`, if any, and otherwise from the environment of the Pod or
+/// the default kubeconfig.
+pub async fn local(opts: &Opts) -> Result<Client> {
+    if opts.kubeconfig.is_none() && opts.context.is_none() {
+        return Ok(Client::try_default().await?)
+    }
+    let options = KubeConfigOptions {
+        context: opts.context.clone(),
+        ..KubeConfigOptions::default()
+    };
+    let config = match &opts.kubeconfig {
+        Some(path) => {
+            Config::from_custom_kubeconfig(Kubeconfig::read_from(path)?, &options).await?
+        },
+        None => Config::from_kubeconfig(&options).await?,
+    };
+    Ok(Client::try_from(config)?)
+}
+
+/// Build a Client for a cluster from the kubeconfig stored in the Secret of the ClusterRef.
+async fn connect(client: &Client, cluster: &ClusterRef, default_namespace: &str) ->
+        Result<Client> {
+    let namespace = cluster
+        .secret_namespace
+        .as_ref()
+        .map(String::as_str)
+        .unwrap_or(default_namespace);
+    let secrets: Api<Secret> = Api::namespaced(client.clone(), namespace);
+    let secret = secrets.get(&cluster.secret_name).await?;
+    let kubeconfig = secret
+        .data
+        .as_ref()
+        .and_then(|data| data.get(&cluster.secret_key))
+        .ok_or(anyhow!("Unable to get key {} from Secret {}",
+                       cluster.secret_key, cluster.secret_name))?;
+    let kubeconfig: Kubeconfig = serde_yaml::from_slice(&kubeconfig.0[..])?;
+    let config = Config::from_custom_kubeconfig(kubeconfig, &KubeConfigOptions::default()).await?;
+    Ok(Client::try_from(config)?)
+}
+
+/// Connect to every additional cluster referenced by an AresConfig, returning Clients by the
+/// name of the cluster.
+pub async fn connect_all(client: &Client, config: &[Arc<AresConfig>], default_namespace: &str) ->
+        Result<HashMap<String, Client>> {
+    let mut clusters = HashMap::new();
+    for cluster in config.iter().flat_map(|ares| ares.clusters.iter()) {
+        if !clusters.contains_key(&cluster.name) {
+            let cluster_client = connect(client, cluster, default_namespace)
+                .await
+                .map_err(|e| anyhow!("Unable to connect to cluster {}: {}", cluster.name, e))?;
+            clusters.insert(cluster.name.clone(), cluster_client);
+        }
+    }
+    Ok(clusters)
+}
@@ -0,0 +1,2366 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Reconciliation of Records through a kube_runtime applier.
+//!
+//! Every Record is synced with the provider of each matching AresConfig when the Record
+//! changes, when an object that the values of the Record are collected from changes, and
+//! periodically after a successful sync to repair any drift at the provider.
+
+// {{{ imports
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use std::ops::Deref;
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use futures::{
+    future::{self, BoxFuture, FutureExt},
+    stream::{self, BoxStream, Stream},
+    StreamExt, TryStreamExt,
+};
+use k8s_openapi::{
+    Resource,
+    api::core::v1::{ConfigMap, Namespace, Node, Pod},
+};
+use kube::{
+    api::{Api, ListParams, Meta, ObjectMeta, PatchParams},
+    Client,
+};
+use kube_runtime::{
+    controller::{applier, trigger_self, trigger_with, Context, ReconcilerAction},
+    reflector::{reflector, store::Writer, ObjectRef, Store},
+    utils::{try_flatten_applied, try_flatten_touched},
+    watcher,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    Notify, Semaphore,
+};
+use tracing::{debug, error, field, info, info_span, warn, Span};
+use tracing_futures::Instrument;
+use trust_dns_resolver::TokioAsyncResolver;
+use trust_dns_resolver::proto::{rr::RecordType as DnsRecordType, xfer::DnsRequestOptions};
+
+use crate::cli::Opts;
+use crate::cluster_record::ClusterRecord;
+use crate::correlation;
+use crate::error::{AresError, ProviderError};
+use crate::events::{self, EventType};
+use crate::log_limit::LogLimiter;
+use crate::metrics;
+use crate::program_config::{AresConfig, DomainFilter};
+use crate::providers::{dry_run, ProviderConfig};
+use crate::providers::util::{
+    external_dns_owner, tracked_domain, tracking_domain, FullDomainName, Heritage, Policy,
+    ProviderBackend, Record as RecordObject, Change, ChangeKind, RecordBuilder, RecordType,
+    SyncPlan, TooManyChanges, ZoneDomainName, normalize_domain, normalize_values,
+};
+use crate::record_spec::{
+    AppliedValues, CollectorContext, LastError, ProviderStatus, Record, RecordPlan, RecordSpec,
+    RecordStatus, RecordValueCollector,
+};
+use crate::reporting;
+use crate::state_cache::StateCache;
+use crate::zone_cache::ZoneCache;
+use crate::zone_policy::{self, ZonePolicy};
+use crate::zone_spec::{find_zone, Zone};
+// }}}
+
+/// Finalizer added to every Record, so that the records at the provider can be removed before
+/// the Record itself is deleted.
+pub static FINALIZER: &str = "ares.syntixi.io/finalizer";
+
+/// Annotation approving the pending plan of a Record, set to the hash of the plan.
+pub static APPROVE_ANNOTATION: &str = "ares.syntixi.io/approve";
+
+/// Annotation syncing the values last synced for a previous generation of a Record, set to the
+/// generation, instead of the values of the current spec of the Record.
+pub static ROLLBACK_ANNOTATION: &str = "ares.syntixi.io/rollback";
+
+/// Annotation pausing the management of a Record when set to "true". Neither syncs nor deletes
+/// are made until the annotation is removed.
+pub static IGNORE_ANNOTATION: &str = "ares.syntixi.io/ignore";
+
+/// Time to wait before trying again to reconcile a Record that is already being reconciled.
+const CLAIM_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Time to wait before retrying a Record after the first failed sync; the time is doubled for
+/// every consecutive failure.
+const BACKOFF_BASE: Duration = Duration::from_secs(5);
+
+/// Longest time to wait before retrying a Record after a failed sync.
+const BACKOFF_MAX: Duration = Duration::from_secs(600);
+
+/// Time to wait before polling a watcher again after an error.
+const WATCH_ERROR_DELAY: Duration = Duration::from_secs(5);
+
+/// Consecutive failures to sync a Record, used to back off from retrying the Record.
+struct Backoff {
+    failures: u32,
+    retry_at: Instant,
+    generation: Option<i64>,
+}
+
+/// Get the time to wait before retrying a Record after a number of consecutive failures. The
+/// time is jittered so that Records that failed at the same time (such as when the provider is
+/// unreachable) are not all retried at the same time.
+fn backoff_delay(failures: u32) -> Duration {
+    let delay = BACKOFF_BASE
+        .checked_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .unwrap_or(BACKOFF_MAX)
+        .min(BACKOFF_MAX);
+    let half = delay / 2;
+    half + half.mul_f64(rand::random::<f64>())
+}
+
+/// Get the time to wait before syncing a Record again after a successful sync, jittered by up
+/// to 10% of the resync interval in either direction.
+fn resync_delay(interval: Duration) -> Duration {
+    interval.mul_f64(0.9 + rand::random::<f64>() * 0.2)
+}
+
+/// Hash a zone with 64-bit FNV-1a. Unlike the hasher of the standard library, the result is
+/// guaranteed to be the same for every build of ARES, so that instances of different versions
+/// can agree on which instance manages a zone.
+fn zone_hash(zone: &ZoneDomainName) -> u64 {
+    zone.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// State shared between every reconciliation of a Record.
+pub struct Data {
+    pub client: Client,
+    pub collector_context: CollectorContext,
+    pub config: Vec<Arc<AresConfig>>,
+    zones: Store<Zone>,
+    zone_policies: Store<ZonePolicy>,
+    namespaces: Store<Namespace>,
+    clusters: HashMap<String, Client>,
+    changes: Vec<UnboundedSender<Change>>,
+    state_cache: Option<StateCache>,
+    cache_checked: Mutex<HashSet<String>>,
+    zone_cache: ZoneCache,
+    resync_interval: Duration,
+    zone_sync: bool,
+    max_changes: Option<usize>,
+    history_size: usize,
+    owner_id: String,
+    cluster_id: Option<String>,
+    import_external_dns: Option<String>,
+    domain_filter: DomainFilter,
+    fail_fast: bool,
+    failure: Notify,
+    failure_threshold: u32,
+    shard_index: u64,
+    shard_count: u64,
+    backoff: Mutex<HashMap<String, Backoff>>,
+    drifted: Mutex<HashSet<(String, ZoneDomainName)>>,
+    log_limiter: LogLimiter,
+    shutting_down: AtomicBool,
+    records_listed: AtomicBool,
+    in_flight: AtomicUsize,
+    permits: Semaphore,
+    in_progress: Mutex<HashSet<String>>,
+}
+
+/// Guard marking a Record as being reconciled until it is dropped, so that the same Record is
+/// never reconciled more than once at a time.
+struct Claim<'a> {
+    in_progress: &'a Mutex<HashSet<String>>,
+    uid: String,
+}
+
+impl<'a> Drop for Claim<'a> {
+    fn drop(&mut self) {
+        self.in_progress.lock().unwrap().remove(&self.uid);
+    }
+}
+
+/// Guard counting a reconciliation as in-flight until it is dropped.
+struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    fn new(counter: &'a AtomicUsize) -> InFlight<'a> {
+        counter.fetch_add(1, Ordering::SeqCst);
+        InFlight(counter)
+    }
+}
+
+impl<'a> Drop for InFlight<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Data {
+    pub fn new(client: Client, config: Vec<Arc<AresConfig>>,
+               clusters: HashMap<String, Client>, changes: Vec<UnboundedSender<Change>>,
+               state_cache: Option<StateCache>, collector_context: CollectorContext,
+               zones: Store<Zone>, zone_policies: Store<ZonePolicy>,
+               namespaces: Store<Namespace>, opts: &Opts) -> Result<Data> {
+        if opts.shard_count == 0 || opts.shard_index >= opts.shard_count {
+            return Err(anyhow!("Invalid shard {} of {}", opts.shard_index, opts.shard_count));
+        }
+        if opts.max_concurrent_reconciles == 0 {
+            return Err(anyhow!("At least one concurrent reconcile must be allowed"));
+        }
+        Ok(Data {
+            client: client,
+            collector_context: collector_context,
+            config: config,
+            zones: zones,
+            zone_policies: zone_policies,
+            namespaces: namespaces,
+            clusters: clusters,
+            changes: changes,
+            state_cache: state_cache,
+            cache_checked: Mutex::new(HashSet::new()),
+            zone_cache: ZoneCache::new(Duration::from_secs(opts.zone_cache_ttl)),
+            resync_interval: Duration::from_secs(opts.resync_interval),
+            zone_sync: opts.reconcile_strategy == "zone",
+            max_changes: opts.max_changes_per_sync,
+            history_size: opts.history_size,
+            owner_id: opts.owner_id.clone(),
+            cluster_id: opts.cluster_id.clone(),
+            import_external_dns: opts.import_external_dns_owner.clone(),
+            domain_filter: DomainFilter::new(&opts.domain_filter, &opts.exclude_domains),
+            fail_fast: opts.fail_fast,
+            failure: Notify::new(),
+            failure_threshold: opts.sentry_failure_threshold,
+            shard_index: opts.shard_index,
+            shard_count: opts.shard_count,
+            backoff: Mutex::new(HashMap::new()),
+            drifted: Mutex::new(HashSet::new()),
+            log_limiter: LogLimiter::new(Duration::from_secs(opts.log_rate_limit)),
+            shutting_down: AtomicBool::new(false),
+            records_listed: AtomicBool::new(false),
+            in_flight: AtomicUsize::new(0),
+            permits: Semaphore::new(opts.max_concurrent_reconciles),
+            in_progress: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Claim a Record for reconciliation. `None` is returned if the Record is already being
+    /// reconciled.
+    fn claim(&self, record: &Record) -> Option<Claim> {
+        let uid = record.metadata.uid.clone().unwrap_or_default();
+        if !self.in_progress.lock().unwrap().insert(uid.clone()) {
+            return None
+        }
+        Some(Claim {
+            in_progress: &self.in_progress,
+            uid: uid,
+        })
+    }
+
+    /// Find every AresConfig that a Record is synced with, along with the position of each
+    /// AresConfig: the AresConfig named by the `providerRef` of the Record if given, and
+    /// otherwise those with a Selector matching the Record, restricted to the AresConfig named
+    /// by the `providerRef` of the Zone of the Record, if any.
+    fn matching_configs(&self, record: &Record) -> Vec<(usize, &AresConfig)> {
+        if let Some(provider_ref) = &record.spec.provider_ref {
+            return self.config
+                .iter()
+                .enumerate()
+                .filter(|(index, ares)| ares.display_name(*index) == *provider_ref)
+                .map(|(index, ares)| (index, ares.as_ref()))
+                .collect()
+        }
+        let zones = self.zones.state();
+        let provider_ref = find_zone(&zones, &record.spec.fqdn)
+            .and_then(|zone| zone.spec.provider_ref.clone());
+        self.config
+            .iter()
+            .enumerate()
+            .filter(|(_, ares)| ares.matches_selector(&record.spec.fqdn))
+            .filter(|(index, ares)| match &provider_ref {
+                Some(provider_ref) => ares.display_name(*index) == *provider_ref,
+                None => true,
+            })
+            .map(|(index, ares)| (index, ares.as_ref()))
+            .collect()
+    }
+
+    /// Check whether a Record is managed by this instance. When sharding, a Record is managed
+    /// by the instance selected by the hash of the zone of the first matching AresConfig.
+    async fn owns_record(&self, record: &Record) -> Result<bool> {
+        if self.shard_count == 1 {
+            return Ok(true)
+        }
+        let zone = match self.matching_configs(record).first() {
+            Some((_, ares)) => {
+                self.zone_cache.get_config_zone(ares, &zone_domain(record)).await?
+            },
+            None => return Ok(self.shard_index == 0),
+        };
+        Ok(zone_hash(&zone) % self.shard_count == self.shard_index)
+    }
+
+    /// Stop starting new reconciliations, and wait for in-flight reconciliations to finish so
+    /// that no set of records is left half-applied. Returns false if reconciliations were still
+    /// in-flight when the timeout was reached.
+    pub async fn drain(&self, timeout: Duration) -> bool {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        let deadline = Instant::now() + timeout;
+        while self.in_flight.load(Ordering::SeqCst) > 0 {
+            if Instant::now() >= deadline {
+                return false
+            }
+            tokio::time::delay_for(Duration::from_millis(100)).await;
+        }
+        true
+    }
+
+    /// Wait until a Record fails to sync with an error that retrying won't fix, when
+    /// `--fail-fast` is given, after which ARES shuts down.
+    pub async fn failed(&self) {
+        self.failure.notified().await
+    }
+
+    /// Persist the state cache, if any.
+    pub async fn flush_state_cache(&self) -> Result<()> {
+        match &self.state_cache {
+            Some(state_cache) => state_cache.flush().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Get every Record watched by the controller.
+    pub fn records(&self) -> Vec<Record> {
+        self.collector_context.records.state()
+    }
+
+    /// Get every Zone.
+    pub fn zones(&self) -> Vec<Zone> {
+        self.zones.state()
+    }
+
+    /// Get the values last synced for a Record with each matching AresConfig according to the
+    /// state cache, by the name of the AresConfig. `None` is returned if the state cache is
+    /// disabled.
+    pub fn cached_values(&self, record: &Record) -> Option<BTreeMap<String, Vec<String>>> {
+        let state_cache = self.state_cache.as_ref()?;
+        Some(self.matching_configs(record)
+            .into_iter()
+            .filter_map(|(index, ares)| {
+                let name = ares.display_name(index);
+                state_cache
+                    .values(&name, &record.spec.fqdn, &record.spec.type_)
+                    .map(|values| (name, values))
+            })
+            .collect())
+    }
+
+    /// Get the time to wait before resyncing a Record on its own after a successful sync.
+    /// Records resynced with their zone are not resynced on their own.
+    fn resync_after(&self, record: &Record) -> Option<Duration> {
+        if self.zone_sync && zone_syncable(record, self) {
+            return None
+        }
+        Some(resync_delay(self.resync_interval))
+    }
+
+    /// Get the time left before a failed Record should be retried. Changes to the Record itself
+    /// are retried immediately, but changes to other objects (such as Pods) are not, as the
+    /// failure is most likely caused by the provider.
+    fn retry_remaining(&self, record: &Record) -> Option<Duration> {
+        let uid = record.metadata.uid.as_ref()?;
+        let backoff = self.backoff.lock().unwrap();
+        let entry = backoff.get(uid)?;
+        if entry.generation != record.metadata.generation {
+            return None
+        }
+        let now = Instant::now();
+        if entry.retry_at > now {
+            Some(entry.retry_at - now)
+        } else {
+            None
+        }
+    }
+
+    /// Register a failed sync of a Record, returning the number of consecutive failures along
+    /// with the time to wait before retrying the Record.
+    fn register_failure(&self, record: &Record) -> (u32, Duration) {
+        let uid = record.metadata.uid.clone().unwrap_or_default();
+        let mut backoff = self.backoff.lock().unwrap();
+        let failures = backoff.get(&uid).map(|entry| entry.failures).unwrap_or(0) + 1;
+        let delay = backoff_delay(failures);
+        backoff.insert(uid, Backoff {
+            failures: failures,
+            retry_at: Instant::now() + delay,
+            generation: record.metadata.generation,
+        });
+        (failures, delay)
+    }
+
+    /// Register a successful sync of a Record, resetting the backoff of the Record.
+    fn register_success(&self, record: &Record) {
+        if let Some(uid) = &record.metadata.uid {
+            self.backoff.lock().unwrap().remove(uid);
+        }
+    }
+
+    /// Register the changes made by a resync of a Record whose values had not changed since its
+    /// last sync, which corrected drift at the provider, in the drift metrics of each zone. A
+    /// Record is counted as drifted in a zone until a resync makes no changes to the zone.
+    /// Changes skipped by dry runs corrected nothing, and are not registered.
+    fn register_drift(&self, record: &Record, changes: &[Change]) {
+        if dry_run::enabled() {
+            return
+        }
+        let uid = record.metadata.uid.clone().unwrap_or_default();
+        // Whether records were created and deleted in each zone.
+        let mut zones: BTreeMap<ZoneDomainName, (bool, bool)> = BTreeMap::new();
+        for change in changes {
+            let entry = zones.entry(change.record.zone.clone()).or_default();
+            match change.kind {
+                ChangeKind::Create => entry.0 = true,
+                ChangeKind::Delete => entry.1 = true,
+            }
+        }
+        let mut drifted = self.drifted.lock().unwrap();
+        drifted.retain(|(drifted_uid, zone)| {
+            if *drifted_uid != uid || zones.contains_key(zone) {
+                return true
+            }
+            metrics::DRIFTED_RECORDS.with_label_values(&[zone.as_str()]).dec();
+            false
+        });
+        for (zone, created_deleted) in zones {
+            let kind = match created_deleted {
+                (true, true) => "corrected",
+                (true, false) => "created",
+                _ => "deleted",
+            };
+            warn!(zone = %zone, kind = kind, "Corrected drift at the provider");
+            metrics::DRIFT_CORRECTIONS.with_label_values(&[zone.as_str(), kind]).inc();
+            if drifted.insert((uid.clone(), zone.clone())) {
+                metrics::DRIFTED_RECORDS.with_label_values(&[zone.as_str()]).inc();
+            }
+        }
+    }
+}
+
+/// An error returned while reconciling a Record, along with the time to wait before retrying
+/// the Record. kube_runtime requires errors to implement `std::error::Error`, which
+/// `anyhow::Error` does not, so the error is wrapped.
+#[derive(Debug)]
+pub struct ReconcileError {
+    error: AresError,
+    retry_after: Duration,
+}
+
+impl fmt::Display for ReconcileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)
+    }
+}
+
+impl std::error::Error for ReconcileError {}
+
+/// Get the domain name to look up the zone of a Record with. NS records are only ever managed to
+/// delegate a subdomain, and belong in the parent zone, even when the delegated subdomain is
+/// itself a zone hosted by the same provider.
+fn zone_domain(record: &Record) -> FullDomainName {
+    let fqdn = &record.spec.fqdn;
+    match (&record.spec.type_, fqdn.find('.')) {
+        (RecordType::NS, Some(offset)) => fqdn[offset + 1..].to_string(),
+        _ => fqdn.clone(),
+    }
+}
+
+/// Build the Heritage recorded in the tracking records created for a Record.
+fn heritage(record: &Record, data: &Data) -> Heritage {
+    let resource = match (&record.metadata.namespace, &record.metadata.name) {
+        (Some(namespace), Some(name)) => Some(format!("{}/{}", namespace, name)),
+        // ClusterRecords have no namespace.
+        (None, Some(name)) => Some(name.clone()),
+        _ => None,
+    };
+    Heritage {
+        owner: Some(data.owner_id.clone()),
+        cluster: data.cluster_id.clone(),
+        resource: resource,
+        uid: record.metadata.uid.clone(),
+    }
+}
+
+/// Get the TTL of a Record: the TTL given in the Record, or otherwise the default TTL of the
+/// AresConfig, or the automatic TTL of the provider.
+fn record_ttl(record: &Record, ares: &AresConfig) -> u64 {
+    u64::from(record.spec.ttl.or(ares.default_ttl).unwrap_or(1))
+}
+
+/// Get a warning if the TTL of a Record is not accepted by a provider, and is clamped to the
+/// TTLs that the provider accepts.
+fn ttl_warning(record: &Record, ares: &AresConfig, provider: &dyn ProviderBackend) ->
+        Option<String> {
+    let ttl = record_ttl(record, ares);
+    let clamped = provider.clamp_ttl(ttl);
+    if clamped == ttl {
+        return None
+    }
+    Some(format!("Provider {} does not support a TTL of {}, using {}", provider.kind(), ttl,
+                 clamped))
+}
+
+/// Build the RecordBuilder for syncing a Record with a provider of an AresConfig in a zone.
+fn record_builder(record: &Record, data: &Data, ares: &AresConfig,
+                  provider: &dyn ProviderBackend, zone: ZoneDomainName) -> RecordBuilder {
+    RecordObject::builder(record.spec.fqdn.clone(), zone, record.spec.type_.clone())
+        .reverse(record.spec.reverse)
+        .max_changes(data.max_changes)
+        .policy(record.spec.policy.unwrap_or(ares.policy))
+        .heritage(heritage(record, data))
+        .ttl(provider.clamp_ttl(record_ttl(record, ares)))
+        .import_external_dns(data.import_external_dns.clone())
+        .proxied(record.spec.proxied)
+        .changes(data.changes.clone())
+}
+
+/// Get the CollectorContext for collecting values to sync with an AresConfig, including the
+/// Clients of the additional clusters of the AresConfig.
+fn collector_context(data: &Data, ares: &AresConfig) -> CollectorContext {
+    let mut collector_context = data.collector_context.clone();
+    collector_context.clusters = ares
+        .clusters
+        .iter()
+        .filter_map(|cluster| data.clusters.get(&cluster.name).cloned())
+        .collect();
+    collector_context
+}
+
+/// Sync a Record with a provider of an AresConfig, returning the values that were synced. The
+/// values are recorded in the state cache under the name of the AresConfig.
+async fn sync_provider(record: &Record, data: &Data, ares: &AresConfig, name: &str,
+                       provider_config: &ProviderConfig,
+                       changes: &[UnboundedSender<Change>]) -> Result<Vec<String>> {
+    provider_config.check_record(&record.spec.type_)?;
+    let zone = match data.zone_cache.get_zone(provider_config, &zone_domain(record)).await {
+        Ok(zone) => zone,
+        Err(e) => {
+            let zone = match (ares.create_zones, ares.selector_domain(&record.spec.fqdn)) {
+                (true, Some(zone)) => zone,
+                _ => return Err(e),
+            };
+            info!(zone = %zone, "Creating zone");
+            provider_config.create_zone(&zone).await?;
+            zone
+        },
+    };
+    Span::current().record("zone", &zone.as_str());
+    let mut builder = record_builder(record, data, ares, provider_config.deref(), zone);
+    builder.changes.extend(changes.iter().cloned());
+    let values = match (&record.spec.value_from, &record.spec.value) {
+        (Some(collector_obj), _) => {
+            let collector = collector_obj.deref();
+            collector
+                .sync(&collector_context(data, ares), &record.metadata, provider_config,
+                      &mut builder)
+                .await?
+        },
+        (None, Some(values)) => {
+            let provider: &dyn ProviderBackend = provider_config.deref();
+            provider.sync_records(&builder, values).await?;
+            values.clone()
+        },
+        (None, None) => vec![],
+    };
+    if let Some(state_cache) = &data.state_cache {
+        state_cache.update(name, &builder, &values);
+    }
+    Ok(values)
+}
+
+/// Get the values last synced for the current generation of a Record, if any.
+fn last_values(record: &Record) -> Option<&Vec<String>> {
+    let generation = record.metadata.generation.unwrap_or_default();
+    record
+        .status
+        .as_ref()
+        .and_then(|status| status.history.as_ref())
+        .and_then(|history| history.iter().find(|applied| applied.generation == generation))
+        .map(|applied| &applied.values)
+}
+
+/// Get the values of a Record to sync with an AresConfig.
+async fn record_values(record: &Record, data: &Data, ares: &AresConfig) -> Result<Vec<String>> {
+    match (&record.spec.value_from, &record.spec.value) {
+        (Some(collector_obj), _) => {
+            let collector = collector_obj.deref();
+            collector.get_values(&collector_context(data, ares), &record.metadata).await
+        },
+        (None, Some(values)) => Ok(values.clone()),
+        (None, None) => Ok(vec![]),
+    }
+}
+
+/// Check whether the values of a Record match the values last synced with every matching
+/// AresConfig according to the state cache, in which case the Record does not need to be synced.
+async fn matches_state_cache(record: &Record, data: &Data) -> Result<bool> {
+    let state_cache = match &data.state_cache {
+        Some(state_cache) => state_cache,
+        None => return Ok(false),
+    };
+    let matching = data.matching_configs(record);
+    for (index, ares) in matching {
+        let cached = state_cache.values(&ares.display_name(index), &record.spec.fqdn,
+                                        &record.spec.type_);
+        let mut cached = match cached {
+            Some(cached) => cached,
+            None => return Ok(false),
+        };
+        let mut values = record_values(record, data, ares).await?;
+        cached.sort();
+        values.sort();
+        values.dedup();
+        if cached != values {
+            return Ok(false)
+        }
+    }
+    Ok(true)
+}
+
+/// Compute the records that syncing a Record with an AresConfig would remove and add, along with
+/// the RecordBuilder of the Record.
+async fn plan_record(record: &Record, data: &Data, ares: &AresConfig) ->
+        Result<(RecordBuilder, Vec<RecordObject>, Vec<String>)> {
+    ares.provider.check_record(&record.spec.type_)?;
+    let values = record_values(record, data, ares).await?;
+    let zone = data.zone_cache.get_zone(&ares.provider, &zone_domain(record)).await?;
+    let builder = record_builder(record, data, ares, ares.provider.deref(), zone);
+    let (stale, missing) = ares.provider.plan_records(&builder, &values).await?;
+    Ok((builder, stale, missing))
+}
+
+/// Plan the records that `delete_record()` would remove with an AresConfig: every value of the
+/// domain name of the Record, if it is owned by ARES and synced with the `sync` policy.
+async fn plan_deletion(record: &Record, data: &Data, ares: &AresConfig) ->
+        Result<Vec<RecordObject>> {
+    let zone = data.zone_cache.get_zone(&ares.provider, &zone_domain(record)).await?;
+    let builder = record_builder(record, data, ares, ares.provider.deref(), zone);
+    if builder.policy != Policy::Sync {
+        return Ok(vec![])
+    }
+    let tracking_records = ares
+        .provider
+        .get_records(&builder.zone, &tracking_domain(&builder.fqdn))
+        .await?;
+    if builder.heritage.find_owned(&tracking_records).is_none() {
+        return Ok(vec![])
+    }
+    Ok(ares.provider.plan_records(&builder, &vec![]).await?.0)
+}
+
+/// Compute the changes that syncing a Record would make with every matching AresConfig that
+/// requires approval, including the deletion of the records of its previous FQDN or type when
+/// either was changed. `None` is returned if no changes require approval.
+async fn pending_plan(record: &Record, data: &Data) -> Result<Option<RecordPlan>> {
+    let mut changes = vec![];
+    if let Some(previous) = previous_record(record) {
+        let matching = data.matching_configs(&previous);
+        for (index, ares) in matching.into_iter().filter(|(_, ares)| ares.require_approval) {
+            let name = ares.display_name(index);
+            changes.extend(plan_deletion(&previous, data, ares).await?.iter().map(|x| {
+                format!("{}: remove {:?} {} {}", name, x.record_type, x.fqdn, x.value)
+            }));
+        }
+    }
+    let matching = data.matching_configs(record);
+    for (index, ares) in matching.into_iter().filter(|(_, ares)| ares.require_approval) {
+        if record.spec.value_from.is_none() && record.spec.value.is_none() {
+            continue
+        }
+        let (builder, stale, missing) = plan_record(record, data, ares).await?;
+        let name = ares.display_name(index);
+        changes.extend(stale.iter().map(|x| {
+            format!("{}: remove {:?} {} {}", name, x.record_type, x.fqdn, x.value)
+        }));
+        changes.extend(missing.iter().map(|value| {
+            format!("{}: add {:?} {} {}", name, builder.record_type, builder.fqdn, value)
+        }));
+    }
+    if changes.is_empty() {
+        return Ok(None)
+    }
+    let hash = Sha256::digest(changes.join("\n").as_bytes())
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok(Some(RecordPlan {
+        hash: hash,
+        changes: changes,
+    }))
+}
+
+/// Sync a Record with an AresConfig, failing over to each standby provider in order while the
+/// provider fails. Returns the result of syncing with the provider, so that writes to the
+/// provider are retried even when served by a standby provider, along with the position in the
+/// failover chain of the provider that the records were written to and the values written.
+async fn sync_failover(record: &Record, data: &Data, ares: &AresConfig, name: &str,
+                       changes: &[UnboundedSender<Change>]) ->
+        (Result<()>, Option<(usize, Vec<String>)>) {
+    let mut result = Ok(());
+    for (position, provider_config) in ares.providers().enumerate() {
+        let span = info_span!("provider", provider = name,
+                              served_by = %AresConfig::provider_name(position),
+                              zone = field::Empty);
+        let synced = sync_provider(record, data, ares, name, provider_config, changes);
+        match synced.instrument(span).await {
+            Ok(values) => return (result, Some((position, values))),
+            // Only an unavailable provider is failed over from; other errors, such as aborted
+            // syncs or records owned by someone else, are caused by the Record rather than the
+            // provider, and would be made at the standby providers as well.
+            Err(e) if !ProviderError::unavailable(&e) => {
+                return (result.and(Err(e)), None)
+            },
+            Err(e) => {
+                if ares.standby.len() > position {
+                    let message = format!("Failing over from {}: {}",
+                                          AresConfig::provider_name(position), e);
+                    if let Some(suppressed) = data.log_limiter.check(&message) {
+                        warn!(suppressed = suppressed, "{}", message);
+                    }
+                }
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            },
+        }
+    }
+    (result, None)
+}
+
+/// Sync a Record with every AresConfig that has a Selector matching the Record. Every AresConfig
+/// is synced even when another fails, so that the status of each provider can be reported
+/// independently; the first error is returned alongside the statuses and the values synced.
+async fn sync_record(record: &Record, data: &Data, changes: &[UnboundedSender<Change>]) ->
+        (Vec<ProviderStatus>, Vec<String>, Result<()>) {
+    let mut providers = vec![];
+    let mut synced_values: Vec<String> = vec![];
+    let mut result = Ok(());
+    let matching = data.matching_configs(record);
+    for (index, ares) in matching {
+        let name = ares.display_name(index);
+        let (synced, served_by) = sync_failover(record, data, ares, &name, changes).await;
+        let warning = served_by
+            .as_ref()
+            .and_then(|(position, _)| ares.providers().nth(*position))
+            .and_then(|provider| ttl_warning(record, ares, provider.deref()));
+        if let Some(warning) = &warning {
+            if let Some(suppressed) = data.log_limiter.check(warning) {
+                warn!(suppressed = suppressed, "{}", warning);
+            }
+        }
+        providers.push(ProviderStatus {
+            name: name,
+            synced: synced.is_ok(),
+            error: synced.as_ref().err().map(|e| e.to_string()),
+            served_by: served_by
+                .as_ref()
+                .map(|(position, _)| AresConfig::provider_name(*position)),
+            warning: warning,
+        });
+        for value in served_by.into_iter().flat_map(|(_, values)| values) {
+            if !synced_values.contains(&value) {
+                synced_values.push(value);
+            }
+        }
+        if let Err(e) = synced {
+            if result.is_ok() {
+                result = Err(e);
+            }
+        }
+    }
+    (providers, synced_values, result)
+}
+
+/// Remove the records of a Record from the provider of every AresConfig that has a Selector
+/// matching the Record. Records with a policy other than `sync` are released rather than
+/// removed, so that they are left in place.
+async fn delete_record(record: &Record, data: &Data) -> Result<()> {
+    let matching = data.matching_configs(record);
+    for (index, ares) in matching {
+        // Records may have been written to any provider in the failover chain.
+        for provider_config in ares.providers() {
+            let zone = data.zone_cache.get_zone(provider_config, &zone_domain(record)).await?;
+            let builder = record_builder(record, data, ares, provider_config.deref(), zone);
+            if builder.policy == Policy::Sync {
+                provider_config.delete_records(&builder).await?;
+            } else {
+                provider_config.release_records(&builder).await?;
+            }
+            if let Some(state_cache) = &data.state_cache {
+                state_cache.update(&ares.display_name(index), &builder, &[]);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Build a copy of a Record with the FQDN and type that were last synced to the provider, if
+/// they differ from the current spec of the Record.
+fn previous_record(record: &Record) -> Option<Record> {
+    let status = record.status.as_ref()?;
+    let fqdn = status.fqdn.as_ref()?;
+    let type_ = status.type_.as_ref()?;
+    if *fqdn == record.spec.fqdn && *type_ == record.spec.type_ {
+        return None
+    }
+    let mut previous = record.clone();
+    previous.spec.fqdn = fqdn.clone();
+    previous.spec.type_ = type_.clone();
+    Some(previous)
+}
+
+/// Get the values to roll a Record back to, if the Record has the rollback annotation.
+fn rollback_values(record: &Record) -> Result<Option<Vec<String>>> {
+    let generation = match record
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(ROLLBACK_ANNOTATION)) {
+        Some(generation) => generation.parse::<i64>()?,
+        None => return Ok(None),
+    };
+    record
+        .status
+        .as_ref()
+        .and_then(|status| status.history.as_ref())
+        .and_then(|history| history.iter().find(|applied| applied.generation == generation))
+        .map(|applied| Some(applied.values.clone()))
+        .ok_or(anyhow!("No values were synced for generation {}", generation))
+}
+
+/// Add the values synced for the current generation of a Record to the history of the Record,
+/// keeping only the latest values of each of the last `size` generations.
+fn updated_history(record: &Record, values: Vec<String>, size: usize) ->
+        Option<Vec<AppliedValues>> {
+    let mut history = record
+        .status
+        .as_ref()
+        .and_then(|status| status.history.clone())
+        .unwrap_or_default();
+    let generation = record.metadata.generation.unwrap_or_default();
+    history.retain(|applied| applied.generation != generation);
+    history.push(AppliedValues {
+        generation: generation,
+        values: values,
+    });
+    if history.len() > size {
+        history.drain(..history.len() - size);
+    }
+    if history.is_empty() {
+        None
+    } else {
+        Some(history)
+    }
+}
+
+/// Merge patch a Record, or the status of the Record if `status` is set. A Record without a
+/// namespace is a ClusterRecord, and is patched as one.
+async fn patch_record(client: &Client, record: &Record, patch: serde_json::Value, status: bool) ->
+        Result<()> {
+    let name = record.metadata.name.clone().ok_or(anyhow!("Missing record.meta.name"))?;
+    let (params, patch) = (PatchParams::default(), serde_json::to_vec(&patch)?);
+    match &record.metadata.namespace {
+        Some(namespace) => {
+            let records: Api<Record> = Api::namespaced(client.clone(), namespace);
+            if status {
+                records.patch_status(&name, &params, patch).await?;
+            } else {
+                records.patch(&name, &params, patch).await?;
+            }
+        },
+        None => {
+            let records: Api<ClusterRecord> = Api::all(client.clone());
+            if status {
+                records.patch_status(&name, &params, patch).await?;
+            } else {
+                records.patch(&name, &params, patch).await?;
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Replace the finalizers of a Record.
+async fn patch_finalizers(client: &Client, record: &Record, finalizers: Vec<String>) ->
+        Result<()> {
+    let patch = serde_json::json!({
+        "metadata": {
+            "finalizers": finalizers,
+        },
+    });
+    patch_record(client, record, patch, false).await
+}
+
+/// Record the FQDN and type synced to the provider in the status of a Record, so that the
+/// records can be migrated if either is changed.
+async fn patch_synced_status(client: &Client, record: &Record, providers: Vec<ProviderStatus>,
+                             history: Option<Vec<AppliedValues>>) -> Result<()> {
+    let patch = serde_json::json!({
+        "status": RecordStatus {
+            fqdn: Some(record.spec.fqdn.clone()),
+            type_: Some(record.spec.type_.clone()),
+            failures: None,
+            providers: Some(providers),
+            plan: None,
+            history: history,
+            ready: Some(true),
+            // The last error is left in place, as the patch skips it.
+            last_error: None,
+        },
+    });
+    patch_record(client, record, patch, true).await
+}
+
+/// Record the result of the last sync with each provider in the status of a Record.
+async fn patch_providers_status(client: &Client, record: &Record, providers: &[ProviderStatus]) ->
+        Result<()> {
+    let patch = serde_json::json!({
+        "status": {
+            "providers": providers,
+        },
+    });
+    patch_record(client, record, patch, true).await
+}
+
+/// Record the changes waiting for approval in the status of a Record.
+async fn patch_plan_status(client: &Client, record: &Record, plan: &RecordPlan) -> Result<()> {
+    let patch = serde_json::json!({
+        "status": {
+            "plan": plan,
+            "ready": false,
+        },
+    });
+    patch_record(client, record, patch, true).await
+}
+
+/// Record the number of consecutive failed syncs in the status of a Record, along with the error
+/// of the failed sync.
+async fn patch_failures_status(client: &Client, record: &Record, failures: u32,
+                               error: &AresError) -> Result<()> {
+    let patch = serde_json::json!({
+        "status": {
+            "failures": failures,
+            "ready": false,
+            "lastError": LastError {
+                message: format!("{:#}", error),
+                time: Utc::now().to_rfc3339(),
+            },
+        },
+    });
+    patch_record(client, record, patch, true).await
+}
+
+/// Check whether a Record is ignored with the `ares.syntixi.io/ignore` annotation.
+fn is_ignored(record: &Record) -> bool {
+    record
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(IGNORE_ANNOTATION))
+        .map(|value| value == "true")
+        .unwrap_or(false)
+}
+
+/// Check whether a Record can be resynced with its zone: a Record whose last sync succeeded with
+/// the primary provider of every matching AresConfig, without a pending change of its FQDN or
+/// type, a rollback, PTR records or an AresConfig requiring approval. Other Records are resynced
+/// on their own.
+fn zone_syncable(record: &Record, data: &Data) -> bool {
+    let status = match &record.status {
+        Some(status) => status,
+        None => return false,
+    };
+    let settled = status.fqdn.as_ref() == Some(&record.spec.fqdn)
+        && status.type_.as_ref() == Some(&record.spec.type_)
+        && status.failures.is_none()
+        && status.plan.is_none()
+        && status.providers.iter().flatten().all(|provider| {
+            provider.served_by == Some(AresConfig::provider_name(0))
+        });
+    let rollback = record
+        .metadata
+        .annotations
+        .as_ref()
+        .map(|annotations| annotations.contains_key(ROLLBACK_ANNOTATION))
+        .unwrap_or(false);
+    let matching = data.matching_configs(record);
+    settled
+        && !rollback
+        && !is_ignored(record)
+        && record.metadata.deletion_timestamp.is_none()
+        && !record.spec.reverse
+        && !matching.is_empty()
+        && matching.iter().all(|(_, ares)| !ares.require_approval)
+}
+
+async fn reconcile_record(record: &Record, data: &Data) -> Result<ReconcilerAction> {
+    let record = record.clone().normalized()?;
+    let finalizers = record.metadata.finalizers.clone().unwrap_or_default();
+    let has_finalizer = finalizers.iter().any(|finalizer| finalizer == FINALIZER);
+    let left_alone = if is_ignored(&record) {
+        debug!("Record is ignored");
+        true
+    } else if !data.domain_filter.allows(&record.spec.fqdn) {
+        debug!("Record is excluded by the domain filter");
+        true
+    } else {
+        false
+    };
+    if left_alone {
+        // The finalizer may have been added before the Record was left alone; it is removed
+        // without deleting the records, so that the deletion of the Record is not blocked.
+        if record.metadata.deletion_timestamp.is_some() && has_finalizer {
+            info!("Removing finalizer without deleting records");
+            patch_finalizers(&data.client, &record, finalizers
+                .into_iter()
+                .filter(|finalizer| finalizer != FINALIZER)
+                .collect()).await?;
+        }
+        return Ok(ReconcilerAction {
+            requeue_after: None,
+        })
+    }
+    if !data.owns_record(&record).await? {
+        debug!("Record is managed by another shard");
+        return Ok(ReconcilerAction {
+            requeue_after: None,
+        })
+    }
+
+    if record.metadata.deletion_timestamp.is_some() {
+        if has_finalizer {
+            info!("Deleting");
+            let mut deleted = delete_record(&record, data).await;
+            if let Some(previous) = previous_record(&record) {
+                if deleted.is_ok() {
+                    deleted = delete_record(&previous, data).await;
+                }
+            }
+            if let Err(e) = deleted {
+                events::publish(&data.client, &record, EventType::Warning,
+                                events::RECORD_SYNC_FAILED,
+                                format!("Unable to delete records: {}", e)).await;
+                return Err(e)
+            }
+            // The records were left in place by the dry run, so the Record is kept until ARES
+            // deletes them without `--dry-run`.
+            if dry_run::enabled() {
+                info!("Dry run: keeping finalizer");
+                return Ok(ReconcilerAction {
+                    requeue_after: None,
+                })
+            }
+            events::publish(&data.client, &record, EventType::Normal,
+                            events::RECORD_DELETED,
+                            format!("Deleted records for {}", record.spec.fqdn)).await;
+            patch_finalizers(&data.client, &record, finalizers
+                .into_iter()
+                .filter(|finalizer| finalizer != FINALIZER)
+                .collect()).await?;
+            data.register_drift(&record, &[]);
+            info!("Finished deleting");
+        }
+        return Ok(ReconcilerAction {
+            requeue_after: None,
+        })
+    }
+
+    if !has_finalizer {
+        debug!("Adding finalizer");
+        let mut finalizers = finalizers;
+        finalizers.push(FINALIZER.to_string());
+        patch_finalizers(&data.client, &record, finalizers).await?;
+    }
+
+    let zones = data.zones.state();
+    let record = match find_zone(&zones, &record.spec.fqdn).map(|zone| zone.apply(&record)) {
+        Some(Ok(defaulted)) => defaulted,
+        Some(Err(e)) => {
+            events::publish(&data.client, &record, EventType::Warning,
+                            events::RECORD_REJECTED, e.to_string()).await;
+            return Ok(ReconcilerAction {
+                requeue_after: None,
+            })
+        },
+        None => record,
+    };
+    // ZonePolicies restrict namespaces, and do not apply to ClusterRecords.
+    if let Some(namespace_name) = &record.metadata.namespace {
+        let namespace = data.namespaces.get(&ObjectRef::new(namespace_name));
+        let authorized = zone_policy::authorize(&data.zone_policies.state(), namespace_name,
+                                                namespace.as_ref(), &record.spec.fqdn);
+        if let Err(e) = authorized {
+            events::publish(&data.client, &record, EventType::Warning,
+                            events::RECORD_REJECTED, e.to_string()).await;
+            return Ok(ReconcilerAction {
+                requeue_after: None,
+            })
+        }
+    }
+
+    // A rolled back Record is synced with the values of a previous generation until the
+    // annotation is removed, and the history of the Record is left as-is.
+    let rollback = rollback_values(&record)?;
+    let record = match &rollback {
+        Some(values) => {
+            info!("Rolling back records");
+            let mut rolled_back = record.clone();
+            rolled_back.spec.value = Some(values.clone());
+            rolled_back.spec.value_from = None;
+            rolled_back
+        },
+        None => record,
+    };
+
+    // The first time a Record is reconciled after a restart, the Record is only synced if its
+    // values differ from those in the state cache; otherwise, it is synced on the next resync.
+    let uid = record.metadata.uid.clone().unwrap_or_default();
+    let first_reconcile = data.cache_checked.lock().unwrap().insert(uid);
+    let last_synced = record.status.as_ref().map(|status| {
+        status.fqdn.as_ref() == Some(&record.spec.fqdn)
+            && status.type_.as_ref() == Some(&record.spec.type_)
+            && status.failures.is_none()
+            && status.plan.is_none()
+    });
+    if first_reconcile && last_synced == Some(true) && matches_state_cache(&record, data).await? {
+        debug!("Record matches state cache");
+        return Ok(ReconcilerAction {
+            requeue_after: data.resync_after(&record),
+        })
+    }
+
+    if let Some(plan) = pending_plan(&record, data).await? {
+        let approved = record
+            .metadata
+            .annotations
+            .as_ref()
+            .and_then(|annotations| annotations.get(APPROVE_ANNOTATION))
+            == Some(&plan.hash);
+        if !approved {
+            let pending = record.status.as_ref().and_then(|status| status.plan.as_ref());
+            if pending != Some(&plan) {
+                info!(plan = %plan.hash, "Waiting for approval");
+                events::publish(&data.client, &record, EventType::Normal,
+                                events::APPROVAL_REQUIRED,
+                                format!("Changes to {} require approval; set the {} \
+                                         annotation to {}",
+                                        record.spec.fqdn, APPROVE_ANNOTATION, plan.hash)).await;
+                patch_plan_status(&data.client, &record, &plan).await?;
+            }
+            return Ok(ReconcilerAction {
+                requeue_after: Some(resync_delay(data.resync_interval)),
+            })
+        }
+    }
+
+    if let Some(previous) = previous_record(&record) {
+        // The FQDN or type of the Record was changed; the records created for the previous
+        // spec would otherwise be orphaned. They are only deleted once the new spec was
+        // authorized and the deletions approved, so that a rejected Record keeps its records.
+        info!(previous_record = %previous.spec.fqdn, "Migrating records");
+        if let Err(e) = delete_record(&previous, data).await {
+            events::publish(&data.client, &record, EventType::Warning,
+                            events::RECORD_SYNC_FAILED,
+                            format!("Unable to delete previous records: {}", e)).await;
+            return Err(e)
+        }
+    }
+
+    info!("Syncing");
+    // Every change made by the sync is also sent here, to tell whether it corrected drift.
+    let (changes, mut changed) = unbounded_channel();
+    let (providers, values, result) = sync_record(&record, data, &[changes]).await;
+    if let Err(e) = result {
+        let reason = if e.downcast_ref::<TooManyChanges>().is_some() {
+            error!("Aborted sync: {}", e);
+            events::TOO_MANY_CHANGES
+        } else {
+            events::RECORD_SYNC_FAILED
+        };
+        events::publish(&data.client, &record, EventType::Warning, reason,
+                        format!("Unable to sync records: {}", e)).await;
+        if let Err(status_error) = patch_providers_status(&data.client, &record, &providers).await {
+            warn!("Unable to update Record status: {}", status_error);
+        }
+        return Err(e)
+    }
+    // Nothing was changed by a dry run, so neither the Events nor the status of the Record
+    // claim that it was synced.
+    if dry_run::enabled() {
+        info!("Finished dry run");
+        return Ok(ReconcilerAction {
+            requeue_after: data.resync_after(&record),
+        })
+    }
+    events::publish(&data.client, &record, EventType::Normal,
+                    events::RECORD_SYNCED,
+                    format!("Synced records for {}", record.spec.fqdn)).await;
+    // Changes made while the values of the Record are the same as at its last sync correct
+    // changes made to the provider outside of ARES.
+    if rollback.is_none() && last_synced == Some(true) && last_values(&record) == Some(&values) {
+        let mut drift = vec![];
+        while let Ok(change) = changed.try_recv() {
+            drift.push(change);
+        }
+        data.register_drift(&record, &drift);
+    }
+    let history = match rollback {
+        Some(_) => record.status.as_ref().and_then(|status| status.history.clone()),
+        None => updated_history(&record, values, data.history_size),
+    };
+    let synced = record.status.as_ref().map(|status| {
+        status.fqdn.as_ref() == Some(&record.spec.fqdn)
+            && status.type_.as_ref() == Some(&record.spec.type_)
+            && status.failures.is_none()
+            && status.providers.as_ref() == Some(&providers)
+            && status.plan.is_none()
+            && status.history == history
+            && status.ready == Some(true)
+    });
+    if synced != Some(true) {
+        patch_synced_status(&data.client, &record, providers, history).await?;
+    }
+    info!("Finished syncing");
+    Ok(ReconcilerAction {
+        requeue_after: data.resync_after(&record),
+    })
+}
+
+async fn reconcile(record: Record, ctx: Context<Data>) ->
+        Result<ReconcilerAction, ReconcileError> {
+    let data = ctx.get_ref();
+    let _claim = match data.claim(&record) {
+        Some(claim) => claim,
+        None => return Ok(ReconcilerAction {
+            requeue_after: Some(CLAIM_RETRY_AFTER),
+        }),
+    };
+    // Reconciliations beyond the concurrency limit wait here until a permit is released.
+    let _permit = data.permits.acquire().await;
+    if data.shutting_down.load(Ordering::SeqCst) {
+        return Ok(ReconcilerAction {
+            requeue_after: None,
+        })
+    }
+    let _in_flight = InFlight::new(&data.in_flight);
+    if let Some(remaining) = data.retry_remaining(&record) {
+        return Ok(ReconcilerAction {
+            requeue_after: Some(remaining),
+        })
+    }
+    let correlation_id = correlation::generate();
+    let span = info_span!("reconcile", record = %record.spec.fqdn,
+                          correlation_id = %correlation_id);
+    let reconciled = correlation::scope(correlation_id, reconcile_record(&record, data));
+    let started = Instant::now();
+    let reconciled = reconciled.instrument(span).await;
+    let result = if reconciled.is_ok() { "success" } else { "error" };
+    metrics::RECONCILE_DURATION
+        .with_label_values(&[result])
+        .observe(started.elapsed().as_secs_f64());
+    match reconciled {
+        Ok(action) => {
+            data.register_success(&record);
+            Ok(action)
+        },
+        Err(e) => {
+            let e = AresError::from(e);
+            let (failures, backoff) = data.register_failure(&record);
+            // A provider asking not to be called before a time is not called again before then.
+            let retry_after = e.retry_after().map_or(backoff, |after| after.max(backoff));
+            reporting::record_failure(&record, failures, data.failure_threshold, &e);
+            let patched = patch_failures_status(&data.client, &record, failures, &e).await;
+            if let Err(status_error) = patched {
+                warn!("Unable to update Record status: {}", status_error);
+            }
+            // Shut down once the Record is marked failed, so that Kubernetes restarts ARES,
+            // rather than retrying the Record. Errors that retrying may fix, such as rate
+            // limits, are still retried.
+            if data.fail_fast && !e.is_retryable() {
+                error!(record = %record.spec.fqdn, "Unable to sync Record, terminating: {}", e);
+                data.failure.notify();
+            }
+            Err(ReconcileError {
+                error: e,
+                retry_after: retry_after,
+            })
+        },
+    }
+}
+
+/// Reconcile a ClusterRecord as a Record without a namespace.
+async fn reconcile_cluster_record(record: ClusterRecord, ctx: Context<Data>) ->
+        Result<ReconcilerAction, ReconcileError> {
+    reconcile(record.to_record(), ctx).await
+}
+
+fn error_policy(error: &ReconcileError, ctx: Context<Data>) -> ReconcilerAction {
+    if let Some(suppressed) = ctx.get_ref().log_limiter.check(&error.to_string()) {
+        error!(retry_after = ?error.retry_after, retryable = error.error.is_retryable(),
+               suppressed = suppressed, "Error! {}", error);
+    }
+    ReconcilerAction {
+        requeue_after: Some(error.retry_after),
+    }
+}
+
+/// Delay a watcher after every error, such as the resourceVersion of the watch expiring (410
+/// Gone). The watcher relists and resumes watching on the next poll, which would otherwise be
+/// retried immediately and repeatedly for persistent errors.
+pub fn delay_on_error<S, T, E>(stream: S) -> impl Stream<Item = Result<T, E>>
+        where S: Stream<Item = Result<T, E>> {
+    stream.then(|item| async move {
+        if item.is_err() {
+            tokio::time::delay_for(WATCH_ERROR_DELAY).await;
+        }
+        item
+    })
+}
+
+/// Get the ListParams used for every watch. Bookmarks let the API server advance the
+/// resourceVersion of a quiet watch, so that a watch restarted after timing out can resume
+/// rather than relisting every object after the resourceVersion expires.
+pub fn watch_params() -> ListParams {
+    ListParams::default().allow_bookmarks()
+}
+
+/// Add a field selector skipping the namespaces excluded with `--exclude-namespaces` to
+/// ListParams.
+fn exclude_namespaces(opts: &Opts, params: ListParams) -> ListParams {
+    let excluded = opts
+        .exclude_namespaces
+        .iter()
+        .map(|namespace| format!("metadata.namespace!={}", namespace))
+        .collect::<Vec<_>>()
+        .join(",");
+    if excluded.is_empty() { params } else { params.fields(&excluded) }
+}
+
+/// Watch objects of kind `K` in the namespaces that Records are watched in, skipping excluded
+/// namespaces. When watching more than one namespace, a relist of one namespace is merged with
+/// the objects of every other namespace in `store`, so that the relist does not remove them.
+fn watch_namespaces<K>(client: &Client, opts: &Opts, params: ListParams, store: Option<Store<K>>)
+        -> BoxStream<'static, Result<watcher::Event<K>, watcher::Error>>
+        where K: Resource + Meta + Clone + DeserializeOwned + Send + Sync + 'static {
+    let params = exclude_namespaces(opts, params);
+    if opts.watch_namespaces.is_empty() {
+        return watcher(Api::<K>::all(client.clone()), params).boxed()
+    }
+    let watchers = opts
+        .watch_namespaces
+        .iter()
+        .map(|namespace| {
+            let namespace = namespace.clone();
+            watcher(Api::<K>::namespaced(client.clone(), &namespace), params.clone())
+                .map_ok(move |event| (namespace.clone(), event))
+                .boxed()
+        })
+        .collect::<Vec<_>>();
+    stream::select_all(watchers)
+        .map_ok(move |(namespace, event)| match (event, &store) {
+            (watcher::Event::Restarted(objects), Some(store)) => {
+                let mut merged = store
+                    .state()
+                    .into_iter()
+                    .filter(|object| Meta::namespace(object).as_ref() != Some(&namespace))
+                    .collect::<Vec<_>>();
+                merged.extend(objects);
+                watcher::Event::Restarted(merged)
+            },
+            (event, _) => event,
+        })
+        .boxed()
+}
+
+/// Find every Record with values collected from a changed object of kind `K`.
+fn records_watching<K: Resource>(store: &Store<Record>, object: &ObjectMeta) ->
+        Vec<ObjectRef<Record>> {
+    store
+        .state()
+        .into_iter()
+        .filter(|record| record
+            .spec
+            .value_from
+            .as_ref()
+            .map(|collector| collector.watches_object(&record.metadata, K::KIND, object))
+            .unwrap_or(false))
+        .map(|record| ObjectRef::from_obj(&record))
+        .collect()
+}
+
+/// Find every Record in a Namespace.
+fn records_in_namespace(store: &Store<Record>, namespace: &Namespace) ->
+        Vec<ObjectRef<Record>> {
+    store
+        .state()
+        .into_iter()
+        .filter(|record| record.metadata.namespace == namespace.metadata.name)
+        .map(|record| ObjectRef::from_obj(&record))
+        .collect()
+}
+
+/// Find every Record in a Zone.
+fn records_in_zone(store: &Store<Record>, zone: &Zone) -> Vec<ObjectRef<Record>> {
+    store
+        .state()
+        .into_iter()
+        .filter_map(|record| record.normalized().ok())
+        .filter(|record| zone.contains(&record.spec.fqdn))
+        .map(|record| ObjectRef::from_obj(&record))
+        .collect()
+}
+
+/// List every ClusterRecord as a Record. ClusterRecords are listed directly when looking for
+/// orphans, as they are only watched by their own controller.
+async fn list_cluster_records(client: &Client) -> Result<Vec<Record>> {
+    match Api::<ClusterRecord>::all(client.clone()).list(&ListParams::default()).await {
+        Ok(list) => Ok(list.items.iter().map(ClusterRecord::to_record).collect()),
+        Err(kube::Error::Api(e)) if e.code == 404 => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Remove the records of every domain name tracked by ARES in the zones of an AresConfig that no
+/// Record or ClusterRecord in `records` exists for, such as Records that were deleted while ARES
+/// was not running. Returns every orphaned domain name; with `dry_run`, nothing is removed.
+async fn collect_orphans(ares: &AresConfig, data: &Data, records: &[Record], dry_run: bool) ->
+        Result<Vec<FullDomainName>> {
+    if ares.policy != Policy::Sync {
+        return Ok(vec![])
+    }
+    let provider: &dyn ProviderBackend = ares.provider.deref();
+    let mut zones = HashSet::new();
+    for selector in ares.selector.iter() {
+        let domain = selector.trim_start_matches("*.").trim_start_matches('.').to_string();
+        zones.insert(data.zone_cache.get_zone(&ares.provider, &domain).await?);
+    }
+    // Records that are migrating to a new FQDN still own the records of the previous FQDN.
+    let wanted: HashSet<FullDomainName> = records
+        .iter()
+        .filter_map(|record| record.clone().normalized().ok())
+        .flat_map(|record| {
+            let previous = record.status.and_then(|status| status.fqdn);
+            vec![Some(record.spec.fqdn), previous]
+        })
+        .flatten()
+        .collect();
+    let owner = Heritage {
+        owner: Some(data.owner_id.clone()),
+        ..Heritage::default()
+    };
+    let mut orphans = vec![];
+    for zone in zones {
+        if zone_hash(&zone) % data.shard_count != data.shard_index {
+            continue
+        }
+        // Only the tracking records owned by this instance are kept, along with the type of a
+        // record of every other domain name, so that large zones are never held in memory.
+        let mut tracking_records = vec![];
+        let mut record_types: HashMap<FullDomainName, RecordType> = HashMap::new();
+        let mut zone_records = provider.get_all_records(&zone);
+        while let Some(record) = zone_records.try_next().await? {
+            let record = record.normalized();
+            let owned = record.record_type == RecordType::TXT
+                && Heritage::parse(&record.value).map(|h| owner.owns(&h)).unwrap_or(false);
+            if owned {
+                tracking_records.push(record);
+            } else {
+                record_types.entry(record.fqdn).or_insert(record.record_type);
+            }
+        }
+        for tracking_record in tracking_records {
+            let fqdn = match tracked_domain(&tracking_record.fqdn) {
+                Some(fqdn) => fqdn,
+                None => continue,
+            };
+            if !ares.matches_selector(&fqdn)
+                    || !data.domain_filter.allows(&fqdn)
+                    || wanted.contains(&fqdn) {
+                continue
+            }
+            orphans.push(fqdn.clone());
+            if dry_run {
+                info!(record = %fqdn, "Found orphaned records");
+                continue
+            }
+            info!(record = %fqdn, "Removing orphaned records");
+            match record_types.get(&fqdn) {
+                Some(record_type) => {
+                    let builder = RecordObject::builder(fqdn, zone.clone(), record_type.clone())
+                        .max_changes(data.max_changes)
+                        .heritage(owner.clone())
+                        .changes(data.changes.clone());
+                    provider.delete_records(&builder).await?;
+                },
+                // Only the tracking record was left behind.
+                None => provider._delete_record(&zone, &tracking_record).await?,
+            }
+        }
+    }
+    Ok(orphans)
+}
+
+/// Periodically persist the state cache.
+async fn flush_state_cache_periodically(ctx: Context<Data>, interval: Duration) {
+    loop {
+        tokio::time::delay_for(interval).await;
+        let data = ctx.get_ref();
+        if data.shutting_down.load(Ordering::SeqCst) {
+            return
+        }
+        if let Err(e) = data.flush_state_cache().await {
+            warn!("Unable to write state cache: {}", e);
+        }
+    }
+}
+
+/// Periodically remove orphaned records from the provider of every AresConfig. Sweeps start only
+/// once every Record has been listed, as every tracked domain name would otherwise look orphaned.
+async fn collect_orphans_periodically(ctx: Context<Data>, records: Store<Record>,
+                                      interval: Duration) {
+    loop {
+        tokio::time::delay_for(interval).await;
+        let data = ctx.get_ref();
+        if data.shutting_down.load(Ordering::SeqCst) {
+            return
+        }
+        if !data.records_listed.load(Ordering::SeqCst) {
+            continue
+        }
+        let _in_flight = InFlight::new(&data.in_flight);
+        let mut all_records = records.state();
+        match list_cluster_records(&data.client).await {
+            Ok(cluster_records) => all_records.extend(cluster_records),
+            Err(e) => {
+                error!("Unable to list ClusterRecords: {}", e);
+                continue
+            },
+        }
+        for ares in data.config.iter() {
+            match collect_orphans(ares, data, &all_records, false).await {
+                Ok(orphans) if orphans.is_empty() => {},
+                Ok(orphans) => {
+                    info!("Removed {} orphaned domain names", orphans.len())
+                },
+                Err(e) => error!("Unable to remove orphaned records: {}", e),
+            }
+        }
+    }
+}
+
+/// A Record planned against the records of its zone by `sync_zone()`.
+struct ZonePlan<'a> {
+    record: &'a Record,
+    builder: RecordBuilder,
+    values: Vec<String>,
+    plan: SyncPlan,
+    changed: UnboundedReceiver<Change>,
+}
+
+/// Plan a Record against the records of its zone, by the domain name of the records. `None` is
+/// returned if the Record has to be reconciled on its own, such as when its values changed since
+/// its last sync.
+async fn plan_zone_record<'a>(record: &'a Record, data: &Data, ares: &AresConfig,
+                              provider: &dyn ProviderBackend, zone: &ZoneDomainName,
+                              remote: &HashMap<FullDomainName, Vec<RecordObject>>) ->
+        Result<Option<ZonePlan<'a>>> {
+    provider.check_record(&record.spec.type_)?;
+    let values = record_values(record, data, ares).await?;
+    // New values are recorded in the history of the Record by its reconciliation.
+    if last_values(record) != Some(&values) {
+        return Ok(None)
+    }
+    let mut builder = record_builder(record, data, ares, provider, zone.clone());
+    // Every change made for the Record is also sent here, as it corrected drift.
+    let (changes, changed) = unbounded_channel();
+    builder.changes.push(changes);
+    let no_records = vec![];
+    let remote_records = remote.get(&builder.fqdn).unwrap_or(&no_records);
+    let tracking_records = remote.get(&tracking_domain(&builder.fqdn)).unwrap_or(&no_records);
+    // Records owned by external-dns are imported by the reconciliation of the Record.
+    if remote_records.iter().any(|x| external_dns_owner(&x.value).is_some()) {
+        return Ok(None)
+    }
+    if normalize_values(&builder.record_type, &values).len() > 1
+            && !provider.capabilities().multi_value {
+        return Ok(None)
+    }
+    // Like with `sync_records()`, only records of the type of the Record are synced.
+    let typed_records: Vec<RecordObject> = remote_records
+        .iter()
+        .filter(|x| x.record_type == builder.record_type)
+        .cloned()
+        .collect();
+    let mut plan = SyncPlan::new(&builder, &typed_records, &values)?;
+    plan.track(&builder, remote_records, tracking_records)?;
+    Ok(Some(ZonePlan {
+        record: record,
+        builder: builder,
+        values: values,
+        plan: plan,
+        changed: changed,
+    }))
+}
+
+/// Resync Records with the provider of an AresConfig in a zone at once: the records of the zone
+/// are listed once, every Record is planned against them, and the changes of every Record are
+/// applied in one call. Returns the Records to reconcile on their own instead.
+async fn sync_zone(data: &Data, ares: &AresConfig, name: &str, zone: &ZoneDomainName,
+                   records: &[Record]) -> Result<Vec<Record>> {
+    let provider: &dyn ProviderBackend = ares.provider.deref();
+    let wanted: HashSet<FullDomainName> = records
+        .iter()
+        .flat_map(|record| vec![record.spec.fqdn.clone(), tracking_domain(&record.spec.fqdn)])
+        .collect();
+    // Only the records of the domain names of the Records are kept, so that large zones are
+    // never held in memory.
+    let mut remote: HashMap<FullDomainName, Vec<RecordObject>> = HashMap::new();
+    let mut zone_records = provider.get_all_records(zone);
+    while let Some(remote_record) = zone_records.try_next().await? {
+        let fqdn = remote_record.fqdn.trim_end_matches('.').to_lowercase();
+        if wanted.contains(&fqdn) {
+            remote.entry(fqdn).or_insert_with(Vec::new).push(remote_record);
+        }
+    }
+    let mut unsynced = vec![];
+    let mut plans = vec![];
+    for record in records {
+        match plan_zone_record(record, data, ares, provider, zone, &remote).await {
+            Ok(Some(plan)) => plans.push(plan),
+            Ok(None) => unsynced.push(record.clone()),
+            Err(e) => {
+                debug!(record = %record.spec.fqdn, "Unable to plan Record with its zone: {}", e);
+                unsynced.push(record.clone());
+            },
+        }
+    }
+    let changes: Vec<Change> = plans
+        .iter()
+        .flat_map(|planned| planned.plan.changes.iter().cloned())
+        .collect();
+    if !changes.is_empty() {
+        info!(zone = %zone, records = plans.len(), changes = changes.len(), "Applying changes");
+        provider.apply_changes(zone, changes).await?;
+    }
+    for mut planned in plans {
+        provider.finish_sync(&planned.builder, &planned.plan).await?;
+        if let Some(state_cache) = &data.state_cache {
+            state_cache.update(name, &planned.builder, &planned.values);
+        }
+        let mut drift = vec![];
+        while let Ok(change) = planned.changed.try_recv() {
+            drift.push(change);
+        }
+        data.register_drift(planned.record, &drift);
+    }
+    Ok(unsynced)
+}
+
+/// Resync every Record that can be resynced with its zone, a zone at a time with `sync_zone()`.
+/// Returns the Records to reconcile on their own instead, such as Records whose values changed
+/// or whose zone could not be synced.
+async fn sync_zones(data: &Data, records: &[Record]) -> Vec<Record> {
+    let zones = data.zones.state();
+    let zone_policies = data.zone_policies.state();
+    let mut unsynced = vec![];
+    let mut claims = vec![];
+    let mut groups: BTreeMap<(usize, ZoneDomainName), Vec<Record>> = BTreeMap::new();
+    for record in records {
+        let record = match record.clone().normalized() {
+            Ok(record) => record,
+            Err(_) => continue,
+        };
+        if !zone_syncable(&record, data) || !data.domain_filter.allows(&record.spec.fqdn) {
+            continue
+        }
+        if let Some(namespace_name) = &record.metadata.namespace {
+            let namespace = data.namespaces.get(&ObjectRef::new(namespace_name));
+            let authorized = zone_policy::authorize(&zone_policies, namespace_name,
+                                                    namespace.as_ref(), &record.spec.fqdn);
+            if authorized.is_err() {
+                continue
+            }
+        }
+        let record = match find_zone(&zones, &record.spec.fqdn).map(|zone| zone.apply(&record)) {
+            Some(Ok(defaulted)) => defaulted,
+            Some(Err(_)) => continue,
+            None => record,
+        };
+        match data.owns_record(&record).await {
+            Ok(true) => {},
+            Ok(false) => continue,
+            Err(_) => {
+                unsynced.push(record);
+                continue
+            },
+        }
+        // Records being reconciled are resynced by their reconciliation.
+        match data.claim(&record) {
+            Some(claim) => claims.push(claim),
+            None => continue,
+        }
+        for (index, ares) in data.matching_configs(&record) {
+            match data.zone_cache.get_zone(&ares.provider, &zone_domain(&record)).await {
+                Ok(zone) => groups.entry((index, zone)).or_insert_with(Vec::new)
+                    .push(record.clone()),
+                Err(_) => unsynced.push(record.clone()),
+            }
+        }
+    }
+    let _permit = data.permits.acquire().await;
+    for ((index, zone), records) in groups {
+        if data.shutting_down.load(Ordering::SeqCst) {
+            break
+        }
+        let ares = &data.config[index];
+        let name = ares.display_name(index);
+        let correlation_id = correlation::generate();
+        let span = info_span!("zone_sync", provider = %name, zone = %zone,
+                              correlation_id = %correlation_id);
+        let synced = correlation::scope(correlation_id, sync_zone(data, ares, &name, &zone,
+                                                                  &records));
+        match synced.instrument(span).await {
+            Ok(records) => unsynced.extend(records),
+            Err(e) => {
+                warn!(zone = %zone, provider = %name,
+                      "Unable to resync zone, reconciling its Records on their own: {}", e);
+                unsynced.extend(records);
+            },
+        }
+    }
+    let mut seen = HashSet::new();
+    unsynced.retain(|record| seen.insert(record.metadata.uid.clone()));
+    unsynced
+}
+
+/// Periodically resync the Records that can be resynced with their zone with `sync_zones()`,
+/// queueing the Records that could not be for reconciliation on their own. Resyncs start only
+/// once every Record has been listed.
+async fn sync_zones_periodically(ctx: Context<Data>, records: Store<Record>,
+                                 record_queue: UnboundedSender<ObjectRef<Record>>,
+                                 cluster_record_queue: UnboundedSender<ObjectRef<ClusterRecord>>) {
+    loop {
+        tokio::time::delay_for(resync_delay(ctx.get_ref().resync_interval)).await;
+        let data = ctx.get_ref();
+        if data.shutting_down.load(Ordering::SeqCst) {
+            return
+        }
+        if !data.records_listed.load(Ordering::SeqCst) {
+            continue
+        }
+        let _in_flight = InFlight::new(&data.in_flight);
+        let mut all_records = records.state();
+        match list_cluster_records(&data.client).await {
+            Ok(cluster_records) => all_records.extend(cluster_records),
+            Err(e) => {
+                error!("Unable to list ClusterRecords: {}", e);
+                continue
+            },
+        }
+        for record in sync_zones(data, &all_records).await {
+            let name = record.metadata.name.clone().unwrap_or_default();
+            // The receiving end only closes when shutting down.
+            let _ = match &record.metadata.namespace {
+                Some(namespace) => record_queue.send(ObjectRef::new(&name).within(namespace)),
+                None => cluster_record_queue.send(ObjectRef::new(&name)),
+            };
+        }
+    }
+}
+
+/// Build the controller. The Context shared by every reconciliation is returned alongside the
+/// future running the controller, so that the caller can drain in-flight reconciliations when
+/// shutting down.
+///
+/// Records, Pods and Nodes are each watched through a single reflector, which both triggers
+/// reconciliations and serves as a local cache for the RecordValueCollectors.
+pub fn build(client: Client, config: Vec<Arc<AresConfig>>,
+             clusters: HashMap<String, Client>, changes: Vec<UnboundedSender<Change>>,
+             state_cache: Option<StateCache>, opts: &Opts) ->
+        Result<(Context<Data>, BoxFuture<'static, ()>)> {
+    let record_writer = Writer::<Record>::default();
+    let cluster_record_writer = Writer::<ClusterRecord>::default();
+    let pod_writer = Writer::<Pod>::default();
+    let node_writer = Writer::<Node>::default();
+    let zone_writer = Writer::<Zone>::default();
+    let zone_policy_writer = Writer::<ZonePolicy>::default();
+    let namespace_writer = Writer::<Namespace>::default();
+    let record_store = record_writer.as_reader();
+    let pod_cache = pod_writer.as_reader();
+    let collector_context = CollectorContext {
+        client: client.clone(),
+        pods: pod_cache.clone(),
+        nodes: node_writer.as_reader(),
+        records: record_store.clone(),
+        config: config.clone(),
+        clusters: vec![],
+    };
+    let context = Context::new(Data::new(client.clone(),
+                                         config,
+                                         clusters,
+                                         changes,
+                                         state_cache,
+                                         collector_context,
+                                         zone_writer.as_reader(),
+                                         zone_policy_writer.as_reader(),
+                                         namespace_writer.as_reader(),
+                                         opts)?);
+
+    let record_params = match &opts.record_label_selector {
+        Some(selector) => watch_params().labels(selector),
+        None => watch_params(),
+    };
+    let cluster_record_store = cluster_record_writer.as_reader();
+    let cluster_records = reflector(cluster_record_writer, delay_on_error(
+        watcher(Api::<ClusterRecord>::all(client.clone()), record_params.clone())));
+    // Values are only ever collected from Pods and ConfigMaps in the namespace of the Record.
+    let listed_context = context.clone();
+    let records = reflector(record_writer, delay_on_error(
+        watch_namespaces(&client, opts, record_params, Some(record_store.clone()))))
+        .inspect_ok(move |event| {
+            if let watcher::Event::Restarted(_) = event {
+                listed_context.get_ref().records_listed.store(true, Ordering::SeqCst);
+            }
+        });
+    let pods = reflector(pod_writer, delay_on_error(
+        watch_namespaces(&client, opts, watch_params(), Some(pod_cache))));
+    let nodes = reflector(node_writer, delay_on_error(
+        watcher(Api::<Node>::all(client.clone()), watch_params())));
+    let config_maps = delay_on_error(
+        watch_namespaces::<ConfigMap>(&client, opts, watch_params(), None));
+    let zones = reflector(zone_writer, delay_on_error(
+        watcher(Api::<Zone>::all(client.clone()), watch_params())));
+    let zone_policies = reflector(zone_policy_writer, delay_on_error(
+        watcher(Api::<ZonePolicy>::all(client.clone()), watch_params())));
+    let namespaces = reflector(namespace_writer, delay_on_error(
+        watcher(Api::<Namespace>::all(client.clone()), watch_params())));
+
+    let (pod_store, node_store, config_map_store, zone_store) =
+        (record_store.clone(), record_store.clone(), record_store.clone(), record_store.clone());
+    let (zone_policy_store, namespace_store) = (record_store.clone(), record_store.clone());
+    let referenced_store = record_store.clone();
+    // Records that could not be resynced with their zone are queued for reconciliation.
+    let (record_queue, record_receiver) = unbounded_channel();
+    let (cluster_record_queue, cluster_record_receiver) = unbounded_channel();
+    let queue = stream::select_all(vec![
+        // A changed Record is synced along with every Record referencing its values.
+        trigger_with(try_flatten_applied(records), move |record: Record| {
+            let mut records = records_watching::<Record>(&referenced_store, &record.metadata);
+            records.push(ObjectRef::from_obj(&record));
+            records
+        }).boxed(),
+        trigger_with(try_flatten_touched(pods),
+                     move |pod: Pod| records_watching::<Pod>(&pod_store, &pod.metadata)).boxed(),
+        trigger_with(try_flatten_touched(nodes),
+                     move |node: Node| records_watching::<Node>(&node_store, &node.metadata))
+            .boxed(),
+        trigger_with(try_flatten_touched(config_maps),
+                     move |config_map: ConfigMap| {
+                         records_watching::<ConfigMap>(&config_map_store, &config_map.metadata)
+                     }).boxed(),
+        trigger_with(try_flatten_touched(zones),
+                     move |zone: Zone| records_in_zone(&zone_store, &zone)).boxed(),
+        // Any Record may be authorized or rejected by a changed ZonePolicy.
+        trigger_with(try_flatten_touched(zone_policies), move |_: ZonePolicy| {
+            zone_policy_store
+                .state()
+                .iter()
+                .map(ObjectRef::from_obj)
+                .collect::<Vec<_>>()
+        }).boxed(),
+        trigger_with(try_flatten_touched(namespaces),
+                     move |namespace: Namespace| {
+                         records_in_namespace(&namespace_store, &namespace)
+                     }).boxed(),
+        record_receiver.map(Ok).boxed(),
+    ]);
+
+    let gc_interval = opts.orphan_gc_interval;
+    let scoped = opts.record_label_selector.is_some()
+        || !opts.watch_namespaces.is_empty()
+        || !opts.exclude_namespaces.is_empty();
+    let gc = if gc_interval == 0 || scoped {
+        info!("Orphaned record collection is disabled");
+        future::ready(()).boxed()
+    } else {
+        collect_orphans_periodically(context.clone(),
+                                     record_store.clone(),
+                                     Duration::from_secs(gc_interval)).boxed()
+    };
+
+    let flush = if context.get_ref().state_cache.is_some() {
+        flush_state_cache_periodically(context.clone(),
+                                       Duration::from_secs(opts.state_cache_interval)).boxed()
+    } else {
+        future::ready(()).boxed()
+    };
+
+    let zone_sync = if context.get_ref().zone_sync {
+        info!("Resyncing Records a zone at a time");
+        sync_zones_periodically(context.clone(),
+                                record_store.clone(),
+                                record_queue,
+                                cluster_record_queue).boxed()
+    } else {
+        future::ready(()).boxed()
+    };
+
+    let (run_context, cluster_context) = (context.clone(), context.clone());
+    let (log_context, cluster_log_context) = (context.clone(), context.clone());
+    let controller = async move {
+        info!("Watching over Records");
+        let applier = applier(reconcile, error_policy, run_context, record_store, queue)
+            .for_each(|result| {
+                match result {
+                    Ok((record, _)) => debug!("Reconciled {}", record),
+                    Err(e) => {
+                        let message = e.to_string();
+                        let limiter = &log_context.get_ref().log_limiter;
+                        if let Some(suppressed) = limiter.check(&message) {
+                            error!(suppressed = suppressed, "Controller error: {}", message);
+                        }
+                    },
+                }
+                future::ready(())
+            });
+        // ClusterRecords are synced when they change and periodically, as their values are not
+        // collected from namespaced objects.
+        let cluster_applier = applier(reconcile_cluster_record, error_policy, cluster_context,
+                                      cluster_record_store,
+                                      stream::select(
+                                          trigger_self(try_flatten_applied(cluster_records)),
+                                          cluster_record_receiver.map(Ok)))
+            .for_each(|result| {
+                match result {
+                    Ok((record, _)) => debug!("Reconciled {}", record),
+                    Err(e) => {
+                        let message = e.to_string();
+                        let limiter = &cluster_log_context.get_ref().log_limiter;
+                        if let Some(suppressed) = limiter.check(&message) {
+                            error!(suppressed = suppressed, "ClusterRecord controller error: {}",
+                                   message);
+                        }
+                    },
+                }
+                future::ready(())
+            });
+        future::join5(applier, cluster_applier, gc, flush, zone_sync).await;
+    };
+    Ok((context, controller.boxed()))
+}
+
+/// List every object of kind `K` into a Store, without watching for further changes. Kinds
+/// whose CustomResourceDefinition is not installed are listed as having no objects.
+async fn list_store<K>(api: Api<K>, params: &ListParams) -> Result<Store<K>>
+        where K: Resource + Meta + Clone + DeserializeOwned + Send + Sync + 'static {
+    let objects = list_objects(api, params).await?;
+    let mut writer = Writer::default();
+    writer.apply_watcher_event(&watcher::Event::Restarted(objects));
+    Ok(writer.as_reader())
+}
+
+/// List objects of kind `K`, of which there are none if the resource is not installed.
+async fn list_objects<K>(api: Api<K>, params: &ListParams) -> Result<Vec<K>>
+        where K: Resource + Meta + Clone + DeserializeOwned + Send + Sync + 'static {
+    match api.list(params).await {
+        Ok(list) => Ok(list.items),
+        Err(kube::Error::Api(e)) if e.code == 404 => Ok(vec![]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// List objects of kind `K` in the namespaces that Records are watched in, skipping excluded
+/// namespaces, like `watch_namespaces()` does for watches.
+async fn list_namespaces<K>(client: &Client, opts: &Opts, params: ListParams) -> Result<Store<K>>
+        where K: Resource + Meta + Clone + DeserializeOwned + Send + Sync + 'static {
+    let params = exclude_namespaces(opts, params);
+    if opts.watch_namespaces.is_empty() {
+        return list_store(Api::<K>::all(client.clone()), &params).await
+    }
+    let mut objects = vec![];
+    for namespace in opts.watch_namespaces.iter() {
+        objects.extend(list_objects(Api::<K>::namespaced(client.clone(), namespace),
+                                    &params).await?);
+    }
+    let mut writer = Writer::default();
+    writer.apply_watcher_event(&watcher::Event::Restarted(objects));
+    Ok(writer.as_reader())
+}
+
+/// List every Record and ClusterRecord managed by this instance, along with the Data to sync them
+/// with. Objects are listed once instead of being watched, and the state cache is not consulted,
+/// so that every Record is compared with its providers. Like with watches, Records and Pods are
+/// only listed in the namespaces that Records are watched in.
+async fn list_records(client: Client, config: Vec<Arc<AresConfig>>,
+                      clusters: HashMap<String, Client>, changes: Vec<UnboundedSender<Change>>,
+                      opts: &Opts) -> Result<(Context<Data>, Vec<Record>)> {
+    let record_params = match &opts.record_label_selector {
+        Some(selector) => ListParams::default().labels(selector),
+        None => ListParams::default(),
+    };
+    let records: Store<Record> = list_namespaces(&client, opts, record_params.clone()).await?;
+    let cluster_records = list_store(Api::<ClusterRecord>::all(client.clone()),
+                                     &record_params).await?;
+    let collector_context = CollectorContext {
+        client: client.clone(),
+        pods: list_namespaces(&client, opts, ListParams::default()).await?,
+        nodes: list_store(Api::<Node>::all(client.clone()), &ListParams::default()).await?,
+        records: records.clone(),
+        config: config.clone(),
+        clusters: vec![],
+    };
+    let data = Data::new(client.clone(),
+                         config,
+                         clusters,
+                         changes,
+                         None,
+                         collector_context,
+                         list_store(Api::<Zone>::all(client.clone()),
+                                    &ListParams::default()).await?,
+                         list_store(Api::<ZonePolicy>::all(client.clone()),
+                                    &ListParams::default()).await?,
+                         list_store(Api::<Namespace>::all(client.clone()),
+                                    &ListParams::default()).await?,
+                         opts)?;
+
+    let watched = |record: &Record| match &record.metadata.namespace {
+        Some(namespace) => {
+            (opts.watch_namespaces.is_empty() || opts.watch_namespaces.contains(namespace))
+                && !opts.exclude_namespaces.contains(namespace)
+        },
+        None => true,
+    };
+    let records = records
+        .state()
+        .into_iter()
+        .chain(cluster_records.state().iter().map(ClusterRecord::to_record))
+        .filter(watched)
+        .collect();
+    Ok((Context::new(data), records))
+}
+
+/// Sync every Record and ClusterRecord once, then return. An error is returned if any Record
+/// failed to sync.
+pub async fn sync_all(client: Client, config: Vec<Arc<AresConfig>>,
+                      clusters: HashMap<String, Client>, changes: Vec<UnboundedSender<Change>>,
+                      opts: &Opts) -> Result<()> {
+    let (context, records) = list_records(client, config, clusters, changes,
+                                          opts).await?;
+    let total = records.len();
+    info!("Syncing {} Records", total);
+    let failed = stream::iter(records)
+        .map(|record| reconcile(record, context.clone()))
+        .buffer_unordered(opts.max_concurrent_reconciles)
+        .filter(|result| {
+            if let Err(e) = result {
+                error!("Unable to sync Record: {}", e);
+            }
+            future::ready(result.is_err())
+        })
+        .count()
+        .await;
+    if failed > 0 {
+        return Err(anyhow!("Unable to sync {} of {} Records", failed, total))
+    }
+    info!("Synced {} Records", total);
+    Ok(())
+}
+
+/// Compute the changes that syncing a Record would make with every matching AresConfig, as
+/// pairs of the zone and provider the change is made in and a description of the change. The
+/// Record is synced with dry runs enabled, so that the changes are those reported for the calls
+/// skipped by `DryRun`, including to PTR records. Records being deleted are planned as deleted.
+async fn plan_changes(record: &Record, data: &Data) -> Result<Vec<(String, String)>> {
+    let record = record.clone().normalized()?;
+    let mut changes = vec![];
+    let deleted = record.metadata.deletion_timestamp.is_some();
+    if !deleted && record.spec.value_from.is_none() && record.spec.value.is_none() {
+        return Ok(changes)
+    }
+    if !data.domain_filter.allows(&record.spec.fqdn) {
+        return Ok(changes)
+    }
+    for (index, ares) in data.matching_configs(&record) {
+        let provider: &dyn ProviderBackend = ares.provider.deref();
+        provider.check_record(&record.spec.type_)?;
+        let zone = data.zone_cache.get_zone(&ares.provider, &zone_domain(&record)).await?;
+        let mut builder = record_builder(&record, data, ares, provider, zone);
+        let (planned, mut skipped) = unbounded_channel();
+        builder.changes.push(planned);
+        if !deleted {
+            let values = record_values(&record, data, ares).await?;
+            provider.sync_records(&builder, &values).await?;
+        } else if builder.policy == Policy::Sync {
+            provider.delete_records(&builder).await?;
+        }
+        let name = ares.display_name(index);
+        while let Ok(change) = skipped.try_recv() {
+            let sign = match change.kind {
+                ChangeKind::Create => '+',
+                ChangeKind::Delete => '-',
+            };
+            let x = &change.record;
+            changes.push((format!("{} ({})", x.zone, name),
+                          format!("{} {:?} {} {}", sign, x.record_type, x.fqdn, x.value)));
+        }
+    }
+    Ok(changes)
+}
+
+/// Print the changes that syncing every Record and ClusterRecord would make, grouped by zone,
+/// without making any of them. Ignored Records are left out. An error is returned if the changes
+/// of any Record could not be computed.
+pub async fn plan_all(client: Client, config: Vec<Arc<AresConfig>>,
+                      clusters: HashMap<String, Client>, opts: &Opts) -> Result<()> {
+    // Plans are made by syncing every Record while skipping every change, like `--dry-run`.
+    dry_run::enable();
+    let (context, records) = list_records(client, config, clusters, vec![],
+                                          opts).await?;
+    let data = context.get_ref();
+    let mut zones: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut failed = 0;
+    for record in records.iter().filter(|record| !is_ignored(record)) {
+        match plan_changes(record, data).await {
+            Ok(changes) => {
+                for (zone, change) in changes {
+                    zones.entry(zone).or_default().push(change);
+                }
+            },
+            Err(e) => {
+                error!(record = %record.spec.fqdn, "Unable to plan Record: {}", e);
+                failed += 1;
+            },
+        }
+    }
+    let (mut added, mut removed) = (0, 0);
+    for (zone, mut changes) in zones {
+        changes.sort();
+        changes.dedup();
+        println!("{}:", zone);
+        for change in changes {
+            if change.starts_with('+') {
+                added += 1;
+            } else {
+                removed += 1;
+            }
+            println!("  {}", change);
+        }
+    }
+    println!("Plan: {} to add, {} to remove.", added, removed);
+    if failed > 0 {
+        return Err(anyhow!("Unable to plan {} of {} Records", failed, records.len()))
+    }
+    Ok(())
+}
+
+/// The state of a Record exported by `ares export`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedRecord {
+    kind: String,
+    namespace: Option<String>,
+    name: String,
+    spec: RecordSpec,
+    providers: Vec<ExportedProvider>,
+}
+
+/// The state of a Record at a single provider, exported by `ares export`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedProvider {
+    name: String,
+    zone: Option<ZoneDomainName>,
+    /// The values collected for the Record.
+    values: Vec<String>,
+    /// The values of the records of the type of the Record at the provider.
+    records: Vec<String>,
+    /// The owner of the domain name, according to its tracking record.
+    owner: Option<Heritage>,
+    error: Option<String>,
+}
+
+/// Get the state of a Record at the provider of an AresConfig.
+async fn export_provider(record: &Record, data: &Data, ares: &AresConfig, name: String) ->
+        Result<ExportedProvider> {
+    let provider: &dyn ProviderBackend = ares.provider.deref();
+    let zone = data.zone_cache.get_zone(&ares.provider, &zone_domain(record)).await?;
+    let records = provider
+        .get_records(&zone, &record.spec.fqdn)
+        .await?
+        .into_iter()
+        .filter(|x| x.record_type == record.spec.type_)
+        .map(|x| x.value)
+        .collect();
+    let owner = provider
+        .get_records(&zone, &tracking_domain(&record.spec.fqdn))
+        .await?
+        .iter()
+        .find_map(|x| Heritage::parse(&x.value));
+    Ok(ExportedProvider {
+        name: name,
+        zone: Some(zone),
+        values: record_values(record, data, ares).await?,
+        records: records,
+        owner: owner,
+        error: None,
+    })
+}
+
+/// Print the spec of every Record and ClusterRecord along with the values collected for it, the
+/// records at each of its providers and the owner of its domain name, as YAML or JSON. Providers
+/// that can't be reached are exported with the error instead.
+pub async fn export_all(client: Client, config: Vec<Arc<AresConfig>>,
+                        clusters: HashMap<String, Client>, opts: &Opts, format: &str) ->
+        Result<()> {
+    let (context, records) = list_records(client, config, clusters, vec![],
+                                          opts).await?;
+    let data = context.get_ref();
+    let mut exported = vec![];
+    for record in records {
+        let record = record.normalized()?;
+        let mut providers = vec![];
+        for (index, ares) in data.matching_configs(&record) {
+            let name = ares.display_name(index);
+            providers.push(match export_provider(&record, data, ares, name.clone()).await {
+                Ok(provider) => provider,
+                Err(e) => ExportedProvider {
+                    name: name,
+                    zone: None,
+                    values: vec![],
+                    records: vec![],
+                    owner: None,
+                    error: Some(e.to_string()),
+                },
+            });
+        }
+        exported.push(ExportedRecord {
+            kind: record.kind.clone(),
+            namespace: record.metadata.namespace.clone(),
+            name: record.metadata.name.clone().unwrap_or_default(),
+            spec: record.spec,
+            providers: providers,
+        });
+    }
+    match format {
+        "json" => println!("{}", serde_json::to_string_pretty(&exported)?),
+        _ => println!("{}", serde_yaml::to_string(&exported)?),
+    }
+    Ok(())
+}
+
+/// Look up the records of a type of a domain name with the resolvers of the system, returning
+/// every value answered.
+pub async fn lookup_dns(fqdn: &str, type_: &RecordType) -> Result<Vec<String>> {
+    // ALIAS records are flattened by the provider, and are answered as A records.
+    let type_ = match type_ {
+        RecordType::ALIAS => DnsRecordType::A,
+        type_ => DnsRecordType::from_str(&format!("{:?}", type_))?,
+    };
+    let resolver = TokioAsyncResolver::tokio_from_system_conf().await?;
+    let lookup = resolver.lookup(fqdn, type_, DnsRequestOptions::default()).await?;
+    Ok(lookup.iter().map(|rdata| rdata.to_string()).collect())
+}
+
+/// Print every Record and ClusterRecord of a domain name along with the state of the Record at
+/// each of its providers, followed by the answer of the resolvers of the system for the domain
+/// name, to find out why a domain name does not resolve as expected.
+pub async fn query(client: Client, config: Vec<Arc<AresConfig>>,
+                   clusters: HashMap<String, Client>, opts: &Opts, fqdn: &str) -> Result<()> {
+    let fqdn = normalize_domain(fqdn)?;
+    let (context, records) = list_records(client, config, clusters, vec![],
+                                          opts).await?;
+    let data = context.get_ref();
+    let mut types = vec![];
+    for record in records {
+        let record = record.normalized()?;
+        if record.spec.fqdn != fqdn {
+            continue
+        }
+        println!("{} {}/{}: {:?}", record.kind, record.metadata.namespace.as_deref().unwrap_or(""),
+                 record.metadata.name.as_deref().unwrap_or(""), record.spec.type_);
+        if is_ignored(&record) {
+            println!("  Ignored with the {} annotation", IGNORE_ANNOTATION);
+        }
+        if !data.domain_filter.allows(&record.spec.fqdn) {
+            println!("  Excluded by the domain filter");
+        }
+        let configs = data.matching_configs(&record);
+        if configs.is_empty() {
+            println!("  No AresConfig matches the Record");
+        }
+        for (index, ares) in configs {
+            let name = ares.display_name(index);
+            match export_provider(&record, data, ares, name.clone()).await {
+                Ok(provider) => {
+                    println!("  Provider {} (zone {}):", name,
+                             provider.zone.unwrap_or_default());
+                    println!("    values: {}", provider.values.join(", "));
+                    println!("    records: {}", provider.records.join(", "));
+                    match provider.owner {
+                        Some(owner) => println!("    owner: {}", owner),
+                        None => println!("    owner: none"),
+                    }
+                },
+                Err(e) => println!("  Provider {}: {}", name, e),
+            }
+        }
+        if !types.contains(&record.spec.type_) {
+            types.push(record.spec.type_);
+        }
+    }
+    if types.is_empty() {
+        println!("No Record found for {}", fqdn);
+        types.push(RecordType::A);
+    }
+    for type_ in types {
+        match lookup_dns(&fqdn, &type_).await {
+            Ok(answer) => println!("DNS {} {:?}: {}", fqdn, type_, answer.join(", ")),
+            Err(e) => println!("DNS {} {:?}: {}", fqdn, type_, e),
+        }
+    }
+    Ok(())
+}
+
+/// Remove the records of every domain name tracked by ARES that no Record or ClusterRecord exists
+/// for, printing each orphaned domain name; with `dry_run`, nothing is removed. Orphans can't be
+/// told apart from Records that are not managed by this instance, so an error is returned when
+/// Records are filtered by label or namespace.
+pub async fn cleanup_orphans(client: Client, config: Vec<Arc<AresConfig>>,
+                             changes: Vec<UnboundedSender<Change>>, opts: &Opts,
+                             dry_run: bool) -> Result<()> {
+    if opts.record_label_selector.is_some()
+            || !opts.watch_namespaces.is_empty()
+            || !opts.exclude_namespaces.is_empty() {
+        return Err(anyhow!("Unable to find orphans when Records are filtered"))
+    }
+    let (context, records) = list_records(client, config, HashMap::new(), changes,
+                                          opts).await?;
+    let data = context.get_ref();
+    let mut total = 0;
+    for (index, ares) in data.config.iter().enumerate() {
+        for orphan in collect_orphans(ares, data, &records, dry_run).await? {
+            println!("{}: {}", ares.display_name(index), orphan);
+            total += 1;
+        }
+    }
+    if dry_run {
+        println!("Found {} orphaned domain names.", total);
+    } else {
+        println!("Removed {} orphaned domain names.", total);
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Correlation IDs of reconciliations. Each reconciliation of a Record is given a random ID,
+//! which is logged with every event of the reconciliation and sent with every request made to a
+//! provider during the reconciliation, so that the logs of ARES can be matched with the logs of
+//! the provider.
+
+// {{{ imports
+use std::future::Future;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+// }}}
+
+/// Header sent with every request made to a provider during a reconciliation.
+static HEADER: &str = "x-correlation-id";
+
+tokio::task_local! {
+    static CORRELATION_ID: String;
+}
+
+/// Generate a new correlation ID.
+pub fn generate() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Run a future with a correlation ID, which is sent with every request made to a provider by
+/// the future.
+pub async fn scope<F: Future>(id: String, f: F) -> F::Output {
+    CORRELATION_ID.scope(id, f).await
+}
+
+/// Get the headers to send the current correlation ID with, if any.
+pub fn headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let value = CORRELATION_ID
+        .try_with(|id| HeaderValue::from_str(id))
+        .ok()
+        .and_then(Result::ok);
+    if let Some(value) = value {
+        headers.insert(HeaderName::from_static(HEADER), value);
+    }
+    headers
+}
@@ -0,0 +1,66 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! CustomResourceDefinitions of every resource of ARES, generated from the code so that the
+//! definitions installed in a cluster match the version of ARES that is running.
+
+// {{{ imports
+use anyhow::{anyhow, Result};
+use k8s_openapi::apiextensions_apiserver::pkg::apis::apiextensions::v1::CustomResourceDefinition;
+use kube::{
+    api::{Api, PatchParams, PostParams},
+    Client,
+};
+use tracing::info;
+
+use crate::cluster_record::ClusterRecord;
+use crate::provider_spec::Provider;
+use crate::record_spec::{self, Record};
+use crate::record_template::RecordTemplate;
+use crate::zone_policy::ZonePolicy;
+use crate::zone_spec::Zone;
+use crate::zone_sync::ZoneSync;
+// }}}
+
+/// Get the CustomResourceDefinition of every resource of ARES.
+pub fn definitions() -> Result<Vec<CustomResourceDefinition>> {
+    Ok(vec![
+        record_spec::with_schema(Record::crd())?,
+        record_spec::with_schema(ClusterRecord::crd())?,
+        Provider::crd(),
+        Zone::crd(),
+        ZonePolicy::crd(),
+        RecordTemplate::crd(),
+        ZoneSync::crd(),
+    ])
+}
+
+/// Render every CustomResourceDefinition as a multi-document YAML file, ready to be applied with
+/// `kubectl apply -f`.
+pub fn to_yaml() -> Result<String> {
+    let documents = definitions()?
+        .iter()
+        .map(serde_yaml::to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+    // serde_yaml starts every document with a separator.
+    Ok(documents.join("\n"))
+}
+
+/// Create every CustomResourceDefinition, or update those that already exist.
+pub async fn install(client: &Client) -> Result<()> {
+    let api: Api<CustomResourceDefinition> = Api::all(client.clone());
+    for crd in definitions()? {
+        let name = crd.metadata.name.clone().ok_or(anyhow!("Missing crd.meta.name"))?;
+        let patch = serde_json::json!({
+            "spec": crd.spec,
+        });
+        match api.patch(&name, &PatchParams::default(), serde_json::to_vec(&patch)?).await {
+            Ok(_) => info!(crd = %name, "Updated CustomResourceDefinition"),
+            Err(kube::Error::Api(e)) if e.code == 404 => {
+                api.create(&PostParams::default(), &crd).await?;
+                info!(crd = %name, "Created CustomResourceDefinition");
+            },
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
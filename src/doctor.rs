@@ -0,0 +1,234 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Diagnostics of the environment of ARES for `ares doctor`, checking that ARES has the
+//! permissions it needs in the cluster and that every provider and zone can be reached, before
+//! the controller is deployed.
+
+// {{{ imports
+use std::ops::Deref;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use k8s_openapi::api::authorization::v1::{
+    ResourceAttributes, SelfSubjectAccessReview, SelfSubjectAccessReviewSpec,
+};
+use kube::{
+    api::{Api, PostParams},
+    Client,
+};
+
+use crate::cli::Opts;
+use crate::controller;
+use crate::events;
+use crate::program_config::AresConfig;
+use crate::providers::util::{ProviderBackend, RecordType};
+use crate::validate::{self, Finding, Report};
+// }}}
+
+/// A permission that ARES needs: a verb on a resource of an API group, in a namespace or in
+/// every namespace.
+#[derive(PartialEq)]
+struct Permission {
+    verb: &'static str,
+    group: &'static str,
+    resource: &'static str,
+    subresource: Option<&'static str>,
+    namespace: Option<String>,
+}
+
+/// Add a permission for each of the verbs on a resource, skipping permissions already added.
+fn require(permissions: &mut Vec<Permission>, verbs: &[&'static str], group: &'static str,
+           resource: &'static str, subresource: Option<&'static str>,
+           namespace: Option<&str>) {
+    for &verb in verbs {
+        let permission = Permission {
+            verb: verb,
+            group: group,
+            resource: resource,
+            subresource: subresource,
+            namespace: namespace.map(str::to_string),
+        };
+        if !permissions.contains(&permission) {
+            permissions.push(permission);
+        }
+    }
+}
+
+/// Get every permission that ARES needs with the given flags. Records, and the Pods and
+/// ConfigMaps that their values are collected from, are watched in every namespace unless
+/// `--watch-namespaces` is given.
+fn permissions(opts: &Opts) -> Vec<Permission> {
+    let namespaces: Vec<Option<&str>> = if opts.watch_namespaces.is_empty() {
+        vec![None]
+    } else {
+        opts.watch_namespaces.iter().map(|namespace| Some(namespace.as_str())).collect()
+    };
+    let mut permissions = vec![];
+    for namespace in namespaces {
+        require(&mut permissions, &["list", "watch"], "", "pods", None, namespace);
+        require(&mut permissions, &["get", "list", "watch"], "", "configmaps", None, namespace);
+        // Records are patched to add and remove the finalizer of ARES.
+        require(&mut permissions, &["list", "watch", "patch"], "syntixi.io", "records", None,
+                namespace);
+        require(&mut permissions, &["patch"], "syntixi.io", "records", Some("status"), namespace);
+        require(&mut permissions, &["create"], "", "events", None, namespace);
+    }
+    for &(group, resource) in &[("", "nodes"), ("", "namespaces"), ("syntixi.io", "zones"),
+                                ("syntixi.io", "zonepolicies"), ("syntixi.io", "providers")] {
+        require(&mut permissions, &["list", "watch"], group, resource, None, None);
+    }
+    require(&mut permissions, &["list", "watch", "patch"], "syntixi.io", "clusterrecords", None,
+            None);
+    require(&mut permissions, &["patch"], "syntixi.io", "clusterrecords", Some("status"), None);
+    require(&mut permissions, &["patch"], "syntixi.io", "providers", Some("status"), None);
+    require(&mut permissions, &["create"], "", "events", None,
+            Some(events::CLUSTER_EVENT_NAMESPACE));
+
+    // The configuration is watched to restart ARES once it changes.
+    let secret_namespace = Some(opts.secret_namespace.as_str());
+    require(&mut permissions, &["get", "list", "watch"], "", "secrets", None, secret_namespace);
+    if opts.config_configmap.is_some() {
+        require(&mut permissions, &["get", "list", "watch"], "", "configmaps", None,
+                secret_namespace);
+    }
+    if opts.audit_configmap.is_some() || opts.state_cache_configmap.is_some() {
+        require(&mut permissions, &["get", "create", "patch"], "", "configmaps", None,
+                secret_namespace);
+    }
+    if opts.install_crds {
+        require(&mut permissions, &["create", "patch"], "apiextensions.k8s.io",
+                "customresourcedefinitions", None, None);
+    }
+    // Records generated from RecordTemplates and imported by ZoneSyncs are managed by the first
+    // shard only.
+    if opts.shard_index == 0 {
+        require(&mut permissions, &["list", "watch"], "syntixi.io", "recordtemplates", None,
+                None);
+        require(&mut permissions, &["list"], "syntixi.io", "zonesyncs", None, None);
+        require(&mut permissions, &["patch"], "syntixi.io", "zonesyncs", Some("status"), None);
+        require(&mut permissions, &["create", "patch", "delete"], "syntixi.io", "records", None,
+                None);
+    }
+    permissions
+}
+
+/// Check whether the user of the client is granted a permission, using a
+/// SelfSubjectAccessReview.
+async fn check_permission(client: &Client, permission: &Permission) -> Finding {
+    let resource = match permission.subresource {
+        Some(subresource) => format!("{}/{}", permission.resource, subresource),
+        None => permission.resource.to_string(),
+    };
+    let description = match &permission.namespace {
+        Some(namespace) => format!("{} {} in namespace {}", permission.verb, resource, namespace),
+        None => format!("{} {} in every namespace", permission.verb, resource),
+    };
+    let review = SelfSubjectAccessReview {
+        spec: SelfSubjectAccessReviewSpec {
+            resource_attributes: Some(ResourceAttributes {
+                verb: Some(permission.verb.to_string()),
+                group: Some(permission.group.to_string()),
+                resource: Some(permission.resource.to_string()),
+                subresource: permission.subresource.map(str::to_string),
+                namespace: permission.namespace.clone(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let reviews: Api<SelfSubjectAccessReview> = Api::all(client.clone());
+    match reviews.create(&PostParams::default(), &review).await {
+        Ok(SelfSubjectAccessReview { status: Some(status), .. }) if status.allowed => {
+            Finding::Ok(format!("Allowed to {}", description))
+        },
+        Ok(review) => {
+            let reason = review.status.and_then(|status| status.reason);
+            Finding::Error(format!("Not allowed to {}{}; grant it in the ClusterRole of ARES",
+                                   description,
+                                   reason.map(|x| format!(" ({})", x)).unwrap_or_default()))
+        },
+        Err(e) => Finding::Error(format!("Unable to check whether allowed to {}: {}",
+                                         description, e)),
+    }
+}
+
+/// Check that the zone of every Selector of an AresConfig can be read at each provider, and is
+/// delegated: its NS records are answered by the resolvers of the system.
+async fn check_zones(ares: &AresConfig) -> Vec<Finding> {
+    let mut findings = vec![];
+    for (position, provider) in ares.providers().enumerate() {
+        let provider_name = AresConfig::provider_name(position);
+        let backend: &dyn ProviderBackend = provider.deref();
+        if !backend.capabilities().updates {
+            findings.push(Finding::Warning(format!(
+                "Provider {} replaces records to change them, leaving a short time in which \
+                 they do not resolve", provider_name)));
+        }
+        for selector in ares.selector.iter() {
+            let domain = selector.trim_start_matches("*.").trim_start_matches('.').to_string();
+            let zone = match backend.get_zone(&domain).await {
+                Ok(zone) => zone,
+                // Reported by the check of the credentials.
+                Err(_) => continue,
+            };
+            match backend.get_records(&zone, &zone).await {
+                Ok(_) => findings.push(Finding::Ok(format!(
+                    "Provider {} is able to read the records of zone {}", provider_name, zone))),
+                Err(e) => findings.push(Finding::Error(format!(
+                    "Provider {} is unable to read the records of zone {}: {}",
+                    provider_name, zone, e))),
+            }
+            match controller::lookup_dns(&zone, &RecordType::NS).await {
+                Ok(answer) if !answer.is_empty() => findings.push(Finding::Ok(format!(
+                    "Zone {} is delegated to {}", zone, answer.join(", ")))),
+                Ok(_) => findings.push(Finding::Warning(format!(
+                    "Zone {} has no NS records; check its delegation", zone))),
+                Err(e) => findings.push(Finding::Warning(format!(
+                    "Unable to resolve the NS records of zone {}; check its delegation: {}",
+                    zone, e))),
+            }
+        }
+    }
+    findings
+}
+
+/// Check the permissions of ARES in the cluster, followed by the credentials of every provider
+/// and the zones of every Selector of the configuration, or the error of loading it, printing a
+/// report to stdout. An error is returned if any problem was found, while warnings are only
+/// reported.
+pub async fn run(client: &Client, opts: &Opts, config: Result<Vec<Arc<AresConfig>>>) ->
+        Result<()> {
+    let mut report = Report::default();
+    let mut findings = vec![];
+    for permission in permissions(opts) {
+        findings.push(check_permission(client, &permission).await);
+    }
+    report.print("Permissions", findings);
+
+    match config {
+        Ok(config) => {
+            if config.is_empty() {
+                report.print("Configuration",
+                             vec![Finding::Error("No AresConfigs found".to_string())]);
+            }
+            for (index, ares) in config.iter().enumerate() {
+                let mut findings = validate::check_credentials(ares).await;
+                findings.extend(check_zones(ares).await);
+                report.print(&format!("{} ({})", ares.display_name(index),
+                                      validate::provider_kind(&ares.provider)),
+                             findings);
+            }
+        },
+        Err(e) => {
+            report.print("Configuration",
+                         vec![Finding::Error(format!("Unable to load configuration: {}", e))]);
+        },
+    }
+
+    println!("{} errors, {} warnings", report.errors, report.warnings);
+    if report.errors > 0 {
+        return Err(anyhow!("Environment checks failed"))
+    }
+    Ok(())
+}
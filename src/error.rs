@@ -0,0 +1,149 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Errors that ARES makes decisions on, such as whether to retry a call to a provider or to
+//! report a failing Record right away. Errors are passed around as `anyhow::Error`, and are told
+//! apart by looking for a ProviderError in the chain of causes of an error.
+
+// {{{ imports
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::providers::util::TooManyChanges;
+// }}}
+
+/// Error returned by a provider, by the kind of failure reported by the provider.
+#[derive(Error, Debug)]
+pub enum ProviderError {
+    /// The zone or record that a call was made for does not exist at the provider.
+    #[error("Not found: {0}")]
+    NotFound(String),
+
+    /// The call was refused by the provider to limit the rate of calls, and was not made. The
+    /// time to wait before calling the provider again is given when known.
+    #[error("Rate limited: {message}")]
+    RateLimited {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+
+    /// The credentials given to the provider are invalid, or are not allowed to make the call.
+    #[error("Authentication failed: {0}")]
+    AuthFailed(String),
+
+    /// The call conflicts with the state of the provider, such as when creating a record that
+    /// already exists.
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    /// The call failed at the provider, such as with a server error, and may succeed if made
+    /// again.
+    #[error("Provider unavailable ({status}): {message}")]
+    Transient {
+        status: u16,
+        message: String,
+    },
+}
+
+impl ProviderError {
+    /// Check whether the call may succeed if made again without any change to ARES or to the
+    /// provider.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ProviderError::RateLimited { .. } | ProviderError::Transient { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Get the name of the kind of the error, as used to label metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProviderError::NotFound(_) => "not_found",
+            ProviderError::RateLimited { .. } => "rate_limited",
+            ProviderError::AuthFailed(_) => "auth_failed",
+            ProviderError::Conflict(_) => "conflict",
+            ProviderError::Transient { .. } => "transient",
+        }
+    }
+
+    /// Find the first ProviderError in the chain of causes of an error.
+    pub fn find(error: &anyhow::Error) -> Option<&ProviderError> {
+        error.chain().find_map(|cause| cause.downcast_ref::<ProviderError>())
+    }
+
+    /// Check whether an error shows that the provider is unavailable, such as when it fails
+    /// transiently, rate limits calls or can't be connected to, rather than that it refused the
+    /// call.
+    pub fn unavailable(error: &anyhow::Error) -> bool {
+        error.chain().any(|cause| {
+            if let Some(e) = cause.downcast_ref::<ProviderError>() {
+                return e.is_retryable()
+            }
+            match cause.downcast_ref::<reqwest::Error>() {
+                Some(e) => e.is_connect() || e.is_timeout() || e.status().map_or(false, |status| {
+                    status.is_server_error() || status.as_u16() == 429
+                }),
+                None => false,
+            }
+        })
+    }
+}
+
+/// Error returned while reconciling a Record.
+#[derive(Error, Debug)]
+pub enum AresError {
+    /// The sync was refused by a provider.
+    #[error(transparent)]
+    Provider(#[from] ProviderError),
+
+    /// The sync was aborted before making any changes, as it would have made too many.
+    #[error(transparent)]
+    TooManyChanges(#[from] TooManyChanges),
+
+    /// Any other error, such as from the Kubernetes API.
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for AresError {
+    /// Classify an error returned by a ProviderBackend or while syncing a Record.
+    fn from(error: anyhow::Error) -> AresError {
+        let error = match error.downcast::<ProviderError>() {
+            Ok(e) => return AresError::Provider(e),
+            Err(error) => error,
+        };
+        match error.downcast::<TooManyChanges>() {
+            Ok(e) => AresError::TooManyChanges(e),
+            Err(error) => AresError::Other(error),
+        }
+    }
+}
+
+impl AresError {
+    /// Get the ProviderError that caused the error, if any.
+    pub fn provider_error(&self) -> Option<&ProviderError> {
+        match self {
+            AresError::Provider(e) => Some(e),
+            AresError::TooManyChanges(_) => None,
+            AresError::Other(e) => ProviderError::find(e),
+        }
+    }
+
+    /// Check whether reconciling the Record again may succeed without any change to the Record,
+    /// to ARES or to the provider. Errors that are not known to be caused by the Record or the
+    /// provider, such as from the Kubernetes API, are assumed to be retryable.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AresError::TooManyChanges(_) => false,
+            _ => self.provider_error().map(ProviderError::is_retryable).unwrap_or(true),
+        }
+    }
+
+    /// Get the time asked by the provider to wait before calling it again, if any.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self.provider_error() {
+            Some(ProviderError::RateLimited { retry_after, .. }) => *retry_after,
+            _ => None,
+        }
+    }
+}
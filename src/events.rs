@@ -0,0 +1,112 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Kubernetes Events attached to Records and ClusterRecords, so that `kubectl describe record`
+//! shows what ARES did to a Record and why.
+
+// {{{ imports
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use k8s_openapi::{
+    api::core::v1::{Event, EventSource, ObjectReference},
+    apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time},
+    Resource,
+};
+use kube::{
+    api::{Api, PostParams},
+    Client,
+};
+use tracing::warn;
+
+use crate::record_spec::Record;
+// }}}
+
+/// Component reported as the source of every Event.
+static COMPONENT: &str = "ares";
+
+/// Namespace that the Events of ClusterRecords are published in.
+pub static CLUSTER_EVENT_NAMESPACE: &str = "default";
+
+/// Reason of the Event published after the records of a Record were synced.
+pub static RECORD_SYNCED: &str = "RecordSynced";
+
+/// Reason of the Event published after the records of a Record failed to sync.
+pub static RECORD_SYNC_FAILED: &str = "RecordSyncFailed";
+
+/// Reason of the Event published when a sync of a Record was aborted for making too many
+/// changes.
+pub static TOO_MANY_CHANGES: &str = "TooManyChanges";
+
+/// Reason of the Event published when changes to a Record are waiting for approval.
+pub static APPROVAL_REQUIRED: &str = "ApprovalRequired";
+
+/// Reason of the Event published when a Record is not allowed by its Zone.
+pub static RECORD_REJECTED: &str = "RecordRejected";
+
+/// Reason of the Event published after the records of a Record were deleted.
+pub static RECORD_DELETED: &str = "RecordDeleted";
+
+pub enum EventType {
+    Normal,
+    Warning,
+}
+
+impl EventType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventType::Normal => "Normal",
+            EventType::Warning => "Warning",
+        }
+    }
+}
+
+async fn create_event(client: &Client, record: &Record, event_type: EventType, reason: &str,
+                      message: String) -> Result<()> {
+    let name = record.metadata.name.as_ref().ok_or(anyhow!("Missing record.meta.name"))?;
+    // Events of ClusterRecords, which have no namespace, are published in the default
+    // namespace, like the Events of other cluster-scoped objects.
+    let namespace = record
+        .metadata
+        .namespace
+        .clone()
+        .unwrap_or_else(|| CLUSTER_EVENT_NAMESPACE.to_string());
+    let now = Time(Utc::now());
+    let event = Event {
+        metadata: ObjectMeta {
+            generate_name: Some(format!("{}.", name)),
+            namespace: Some(namespace.clone()),
+            ..ObjectMeta::default()
+        },
+        involved_object: ObjectReference {
+            api_version: Some(Record::API_VERSION.to_string()),
+            kind: Some(record.kind.clone()),
+            name: Some(name.clone()),
+            namespace: record.metadata.namespace.clone(),
+            resource_version: record.metadata.resource_version.clone(),
+            uid: record.metadata.uid.clone(),
+            ..ObjectReference::default()
+        },
+        type_: Some(event_type.as_str().to_string()),
+        reason: Some(reason.to_string()),
+        message: Some(message),
+        source: Some(EventSource {
+            component: Some(COMPONENT.to_string()),
+            ..EventSource::default()
+        }),
+        first_timestamp: Some(now.clone()),
+        last_timestamp: Some(now),
+        count: Some(1),
+        ..Event::default()
+    };
+    let events: Api<Event> = Api::namespaced(client.clone(), &namespace);
+    events.create(&PostParams::default(), &event).await?;
+    Ok(())
+}
+
+/// Publish an Event attached to a Record. Failing to publish an Event should not interrupt
+/// the management of the Record, so errors are logged rather than returned.
+pub async fn publish(client: &Client, record: &Record, event_type: EventType,
+                     reason: &str, message: String) {
+    if let Err(e) = create_event(client, record, event_type, reason, message).await {
+        warn!("Unable to publish Event {}: {}", reason, e);
+    }
+}
@@ -0,0 +1,159 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Health and readiness endpoints for the probes of a Deployment. `/healthz` answers as long as
+//! ARES is running, while `/readyz` only answers once the configuration is loaded, the
+//! CustomResourceDefinitions of ARES are installed and the credentials of every provider were
+//! checked successfully. The metrics of ARES are served at `/metrics` on the same address.
+
+// {{{ imports
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::Duration;
+
+use anyhow::Result;
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use kube::{
+    api::{Api, ListParams},
+    Client,
+};
+use tracing::{error, info, warn};
+use tracing_futures::Instrument;
+
+use crate::cli::Opts;
+use crate::metrics;
+use crate::program_config::AresConfig;
+use crate::record_spec::Record;
+use crate::validate::{self, Finding};
+// }}}
+
+/// Time between checks of the conditions of readiness, until every condition is met.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The conditions of the readiness of ARES.
+#[derive(Default)]
+pub struct Health {
+    config_loaded: AtomicBool,
+    crds_installed: AtomicBool,
+    credentials_checked: AtomicBool,
+    /// Errors of the last check of the credentials of every provider.
+    provider_errors: Mutex<Vec<String>>,
+}
+
+impl Health {
+    /// Mark the configuration as loaded.
+    pub fn set_config_loaded(&self) {
+        self.config_loaded.store(true, Ordering::SeqCst);
+    }
+
+    /// Get a description of every condition of readiness that is not met.
+    fn unmet(&self) -> Vec<String> {
+        let conditions = [
+            (&self.config_loaded, "configuration not loaded"),
+            (&self.crds_installed, "CustomResourceDefinitions not installed"),
+        ];
+        let mut unmet: Vec<String> = conditions
+            .iter()
+            .filter(|(condition, _)| !condition.load(Ordering::SeqCst))
+            .map(|(_, description)| description.to_string())
+            .collect();
+        if !self.credentials_checked.load(Ordering::SeqCst) {
+            let provider_errors = self.provider_errors.lock().unwrap();
+            if provider_errors.is_empty() {
+                unmet.push("provider credentials not checked".to_string());
+            }
+            unmet.extend(provider_errors
+                .iter()
+                .map(|e| format!("provider check failed: {}", e)));
+        }
+        unmet
+    }
+}
+
+/// Respond to a probe, or to a scrape of the metrics of ARES.
+async fn handle(health: Arc<Health>, request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let (status, body) = match request.uri().path() {
+        "/healthz" => (StatusCode::OK, Body::from("ok")),
+        "/readyz" => {
+            let unmet = health.unmet();
+            if unmet.is_empty() {
+                (StatusCode::OK, Body::from("ok"))
+            } else {
+                (StatusCode::SERVICE_UNAVAILABLE, Body::from(unmet.join("\n")))
+            }
+        },
+        "/metrics" => match metrics::encode() {
+            Ok(metrics) => (StatusCode::OK, Body::from(metrics)),
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Body::from(e.to_string())),
+        },
+        _ => (StatusCode::NOT_FOUND, Body::from("not found")),
+    };
+    let mut response = Response::new(body);
+    *response.status_mut() = status;
+    Ok(response)
+}
+
+/// Start serving the health endpoints and metrics on the address given on the command line.
+/// Nothing is served if no address is given.
+pub async fn spawn(health: Arc<Health>, opts: &Opts) -> Result<()> {
+    let address: SocketAddr = match &opts.health_address {
+        Some(address) => address.parse()?,
+        None => return Ok(()),
+    };
+    let server = Server::try_bind(&address)?.serve(make_service_fn(move |_| {
+        let health = health.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| handle(health.clone(), request)))
+        }
+    }));
+    info!(address = %address, "Serving health endpoints");
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            warn!("Error serving health endpoints: {}", e);
+        }
+    }.in_current_span());
+    Ok(())
+}
+
+/// Check the conditions of readiness that depend on the cluster and the providers until they are
+/// met: that Records can be listed, and that the credentials of every provider are valid. Each
+/// provider that fails the check is logged along with its error.
+pub async fn check(client: Client, health: Arc<Health>, config: Vec<Arc<AresConfig>>) {
+    let records: Api<Record> = Api::all(client);
+    loop {
+        if !health.crds_installed.load(Ordering::SeqCst) {
+            match records.list(&ListParams::default().limit(1)).await {
+                Ok(_) => health.crds_installed.store(true, Ordering::SeqCst),
+                Err(e) => warn!("Unable to list Records, not ready: {}", e),
+            }
+        }
+        if !health.credentials_checked.load(Ordering::SeqCst) {
+            let mut provider_errors = vec![];
+            for (index, ares) in config.iter().enumerate() {
+                let name = ares.display_name(index);
+                for finding in validate::check_credentials(ares).await {
+                    if let Finding::Error(message) = finding {
+                        error!(config = %name, "Provider check failed: {}", message);
+                        provider_errors.push(format!("{}: {}", name, message));
+                    }
+                }
+            }
+            if provider_errors.is_empty() {
+                info!("Checked the credentials of every provider");
+            }
+            health.credentials_checked.store(provider_errors.is_empty(), Ordering::SeqCst);
+            *health.provider_errors.lock().unwrap() = provider_errors;
+        }
+        if health.unmet().is_empty() {
+            info!("Ready");
+            break
+        }
+        tokio::time::delay_for(CHECK_INTERVAL).await;
+    }
+}
@@ -0,0 +1,68 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Rate limiting of repeated log messages, so that an error repeated by every retry, such as a
+//! provider rejecting the credentials of ARES, does not flood the logs. Each distinct message is
+//! logged at most once per interval, along with the number of times it was suppressed since.
+
+// {{{ imports
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+// }}}
+
+/// When a message was last logged, and how many times it was suppressed since.
+struct Seen {
+    logged_at: Instant,
+    suppressed: u64,
+}
+
+/// Limiter of identical log messages to one per interval.
+pub struct LogLimiter {
+    interval: Duration,
+    seen: Mutex<HashMap<String, Seen>>,
+}
+
+impl LogLimiter {
+    /// Build a limiter logging each message at most once per interval. Every message is logged
+    /// if the interval is zero.
+    pub fn new(interval: Duration) -> LogLimiter {
+        LogLimiter {
+            interval: interval,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check whether a message should be logged, returning the number of times the message was
+    /// suppressed since it was last logged. `None` is returned if the message should be
+    /// suppressed.
+    pub fn check(&self, message: &str) -> Option<u64> {
+        if self.interval == Duration::from_secs(0) {
+            return Some(0)
+        }
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+        // Messages that were not logged within the last two intervals are forgotten, along with
+        // the number of times they were suppressed, so that the limiter does not grow forever.
+        let interval = self.interval;
+        seen.retain(|_, entry| now.duration_since(entry.logged_at) < interval * 2);
+        match seen.get_mut(message) {
+            Some(entry) if now.duration_since(entry.logged_at) < interval => {
+                entry.suppressed += 1;
+                None
+            },
+            Some(entry) => {
+                let suppressed = entry.suppressed;
+                entry.logged_at = now;
+                entry.suppressed = 0;
+                Some(suppressed)
+            },
+            None => {
+                seen.insert(message.to_string(), Seen {
+                    logged_at: now,
+                    suppressed: 0,
+                });
+                Some(0)
+            },
+        }
+    }
+}
@@ -16,6 +16,76 @@
 //! default namespace. This may change in the future to default to the
 //! namespace that ARES is deployed in.
 //!
+//! The CustomResourceDefinitions of ARES can be printed with `ares crd`, ready to
+//! be applied with `kubectl apply -f -`, or created and updated by ARES itself on
+//! startup with `--install-crds`, which requires permission to create and patch
+//! CustomResourceDefinitions. Either way, the definitions are generated from the
+//! code of the running version of ARES, and invalid Records are rejected when they
+//! are applied. Records can be listed with `kubectl get rec`, which shows the FQDN,
+//! type and TTL of each Record, and whether it is ready: synced to every provider.
+//!
+//! ARES runs the controller when no subcommand is given, or with `ares run`. Other
+//! subcommands share the flags of the controller, given before the subcommand:
+//! `ares version` prints the version of ARES, and `ares sync` syncs every Record
+//! once and exits, failing if any Record could not be synced, such as to repair
+//! records from a CronJob without running the controller.
+//!
+//! The configuration can be checked before it is rolled out with `ares validate`,
+//! which prints a report of every problem found in the configuration of the Secret
+//! and Providers, or in a local file given with `--file`, and fails if any error
+//! is found, such as in a CI pipeline. With `--check-credentials`, the zone of
+//! every selector is also looked up at each provider, checking its credentials.
+//!
+//! Changes can be previewed with `ares plan`, which compares every Record with the
+//! records of its providers and prints the records that syncing would add (`+`)
+//! or remove (`-`), grouped by zone, without making any changes. Records are
+//! synced like with `--dry-run`, so that the plan lists the changes that would be
+//! made, including to PTR records. Records being deleted are planned as removing
+//! their records.
+//!
+//! ```
+//! syntixi.io (cloudflare):
+//!   + A www.syntixi.io 10.0.0.2
+//!   - A www.syntixi.io 10.0.0.1
+//! Plan: 1 to add, 1 to remove.
+//! ```
+//!
+//! Records left behind at a provider, such as by Records deleted while ARES was not
+//! running, can be removed with `ares cleanup-orphans`, which finds every domain
+//! name owned by ARES in the zones of each selector that no Record or
+//! ClusterRecord exists for, and removes its records. With `--dry-run`, the
+//! orphaned domain names are only printed. As Records that are not managed by this
+//! instance would look orphaned, the command refuses to run when Records are
+//! filtered by label or namespace.
+//!
+//! Zones that were managed by hand can be brought under ARES with `ares import
+//! --provider <name> --zone <zone>`, which prints a Record for each type of each
+//! domain name in the zone at the provider of the named element of the
+//! configuration, ready to be reviewed and applied with `kubectl apply -f -`. With
+//! `--apply`, the Records are created in the namespace given with `--namespace`
+//! and ARES takes ownership of their records, so that they are managed like any
+//! other Record. Domain names that are already owned by ARES are skipped.
+//!
+//! The state of every Record can be dumped for a backup or an audit with `ares
+//! export`, which prints the spec of each Record and ClusterRecord along with, for
+//! every provider it is synced with, the values collected for the Record, the
+//! records at the provider and the owner of the domain name according to its
+//! tracking record. Records are printed as YAML, or as JSON with `--format json`.
+//!
+//! When a domain name does not resolve as expected, `ares query <fqdn>` prints
+//! each Record and ClusterRecord of the domain name, whether it is ignored or
+//! filtered out, and for every provider it is synced with, the values collected
+//! for the Record, the records at the provider and their owner, followed by the
+//! answer of the resolvers of the system for the domain name.
+//!
+//! Before deploying the controller, `ares doctor` checks its environment: that
+//! ARES is allowed to use every API that the controller needs with the given
+//! flags, such as watching Records, Pods and ConfigMaps, patching Records and
+//! their status, publishing Events and reading the ARES Secret, that the
+//! credentials of every provider are valid, and that the zone of every selector
+//! can be read at the provider and is delegated. Every failed check is reported
+//! along with what to fix, and the command fails if any check failed.
+//!
 //! ## Configuration
 //!
 //! A configuration file should look like this:
@@ -49,6 +119,148 @@
 //! configure another provider there. You can configure multiple domain zones
 //! through a single provider.
 //!
+//! Credentials can be kept out of the configuration, in Secrets of their own
+//! with tighter RBAC, by giving any option of `providerOptions` as a reference to
+//! a key of a Secret, named after the option with a `SecretRef` suffix. Secrets
+//! are read from the namespace of the ARES Secret unless given a `namespace`, and
+//! ARES restarts when any of them changes.
+//!
+//! ```yaml
+//! - selector:
+//!   - syntixi.io
+//!   provider: cloudflare
+//!   providerOptions:
+//!     apiTokenSecretRef:
+//!       name: cloudflare-token
+//!       key: token
+//! ```
+//!
+//! Placeholders such as `${CLOUDFLARE_TOKEN}` in the values of the configuration
+//! are replaced by the environment variables of ARES when the configuration is
+//! loaded, so that the same configuration can be used in every environment, with
+//! credentials given by the env of the Deployment. Variables that are not set are
+//! an error, and a literal `${` is written as `$${`.
+//!
+//! Requests to a provider are sent through the proxies given by the `HTTP_PROXY`
+//! and `HTTPS_PROXY` environment variables, if any. The `providerOptions` of a
+//! provider can also set a `proxy` of their own, a `timeoutSeconds` for every
+//! request, and a `caBundle` (or `caFile`) of PEM certificates to trust for
+//! providers serving certificates signed by a private CA.
+//! `insecureSkipVerify: true` accepts any certificate, and is only meant for
+//! testing.
+//!
+//! Providers can also be configured as syntixi.io/v1alpha1/Provider resources,
+//! in which case the Secret may be left out entirely. The spec of a Provider takes
+//! the same fields as an element of the configuration file, and `secretOptions`
+//! reads options of the provider from keys of Secrets in the namespace of the
+//! Provider. Providers are named after their namespace and name unless given a
+//! `name`, and the `status` of each Provider reports whether it was loaded.
+//!
+//! ```yaml
+//! apiVersion: syntixi.io/v1alpha1
+//! kind: Provider
+//! metadata:
+//!   name: cloudflare
+//!   namespace: ares
+//! spec:
+//!   selector:
+//!   - syntixi.io
+//!   provider: cloudflare
+//!   secretOptions:
+//!     apiToken:
+//!       name: cloudflare-token
+//!       key: token
+//! ```
+//!
+//! Configuration can also be loaded from a file with `--config-file`, such as a
+//! Secret or ConfigMap mounted into the Pod, or from a ConfigMap in the namespace
+//! of the ARES Secret with `--config-configmap`, under the same key as in the
+//! Secret. The elements of every source are combined, and ARES restarts when any
+//! of them changes.
+//!
+//! A Record matching the selectors of more than one element is published to
+//! every matching provider, such as a public and an internal provider for
+//! split-horizon DNS. Each element can be given a `name`, which is used to report
+//! the result of the last sync with each provider in the status of the Record.
+//!
+//! A TTL that a provider does not accept, such as a TTL below 60 seconds at
+//! CloudFlare, is clamped to the closest TTL that the provider accepts rather
+//! than failing the sync, and reported as a `warning` of the provider in the
+//! status of the Record.
+//!
+//! Values are compared with the records of a provider once normalized, so that
+//! values differing only in form from the records of the provider, such as an IPv6
+//! address written out in full or a CNAME target in uppercase or with a trailing
+//! dot, are not replaced at every sync.
+//!
+//! An element can also list `standby` providers, each with its own `provider`
+//! and `providerOptions`. While the provider of the element is unreachable,
+//! records are written to the first standby provider that succeeds, and writes
+//! to the provider are retried until it recovers. The `servedBy` field of the
+//! status of the Record reports which provider the records were last written to.
+//! Only a provider that is unreachable, fails with a server error or rate limits
+//! calls is failed over from; any other error, such as invalid credentials or a
+//! domain name owned by someone else, fails the sync.
+//!
+//! By default, ARES adds and removes records to match every Record. Setting
+//! `policy: upsert-only` on an element prevents ARES from ever removing records
+//! through that provider, and `policy: create-only` additionally prevents ARES
+//! from adding values to a domain name that already has records.
+//!
+//! The `policy` can also be set on a single Record, overriding the policy of the
+//! element, such as to protect a high-risk record from removal. When a Record
+//! with a policy other than `sync` is deleted, its records are left in place and
+//! no longer managed by ARES.
+//!
+//! Setting `createZones: true` on an element creates the zone of the matching
+//! selector when the provider does not host a zone for a Record, if the provider
+//! is able to create zones.
+//!
+//! Setting a `rateLimit` on an element limits the calls made to its provider and
+//! standby providers, shared by every Record, to `requestsPerSecond` on average.
+//! Up to `burst` calls (by default, the number of calls per second) are made at
+//! once after the provider was left idle, and further calls wait their turn, so
+//! that a large cluster syncing at once does not get shared credentials banned.
+//!
+//! Setting `maxConcurrentRequests` on an element limits the number of calls made
+//! at once to its provider and to each standby provider, shared by every Record,
+//! for providers that throttle parallel writes. Further calls wait until an
+//! earlier call finishes.
+//!
+//! Values collected with the podSelector can be merged across clusters by listing
+//! additional `clusters` on an element, each with a `name` and the `secretName`
+//! of a Secret holding a kubeconfig for the cluster (under the `kubeconfig` key by
+//! default, in the namespace of the ARES Secret unless `secretNamespace` is given).
+//! Pods in the namespace of the Record are matched in every listed cluster.
+//!
+//! ```yaml
+//! - selector:
+//!   - syntixi.io
+//!   provider: cloudflare
+//!   providerOptions:
+//!     apiToken: ***
+//!   clusters:
+//!   - name: europe
+//!     secretName: europe-kubeconfig
+//! ```
+//!
+//! By default, every Record is synced when ARES starts, which queries the provider
+//! for every Record at once. Passing `--state-cache-configmap` (or
+//! `--state-cache-file`, such as on a persistent volume) persists the records last
+//! synced to each provider, and Records whose values have not changed since are
+//! instead synced at the next resync after a restart.
+//!
+//! The zone of each domain name at each provider is cached for 5 minutes (set with
+//! `--zone-cache-ttl`, or 0 to disable the cache), shared by every Record, so that
+//! Records in the same zone don't each look up the zone at every sync.
+//!
+//! By default, every Record is resynced on its own, which lists its records at the
+//! provider at every resync. Passing `--reconcile-strategy zone` instead resyncs
+//! the Records of each zone at once: the records of the zone are listed once, and
+//! the changes needed by every Record are applied together. Records whose values
+//! changed, that are failing, or that have PTR records, are still reconciled on
+//! their own, as are the Records of a zone that could not be resynced.
+//!
 //! ## Custom Resource Definitions
 //!
 //! ARES watches over the syntixi.io/v1alpha1/Record CustomResourceDefinition
@@ -104,185 +316,833 @@
 //!         app: nginx
 //! ```
 //!
+//! SSHFP records for a machine can be published from its SSH host keys using
+//! the hostKeys selector. Keys are read from every value of a ConfigMap in the
+//! namespace of the Record, or from an annotation on a Node (by default,
+//! `ares.syntixi.io/ssh-host-keys`), with one OpenSSH public key per line.
+//!
+//! ```yaml
+//! apiVersion: syntixi.io/v1alpha1
+//! kind: Record
+//! metadata:
+//!   name: node-1-sshfp
+//! spec:
+//!   fqdn: node-1.syntixi.io
+//!   ttl: 3600
+//!   type: SSHFP
+//!   valueFrom:
+//!     hostKeys:
+//!       nodeName: node-1
+//! ```
+//!
+//! Setting `reverse: true` on a Record with A or AAAA values will also
+//! manage a PTR record for each address in the matching `in-addr.arpa` or
+//! `ip6.arpa` zone, so long as the configured provider hosts that zone.
+//!
+//! Setting `proxied: true` on a Record proxies traffic to its records through
+//! CloudFlare (the "orange cloud"), and `proxied: false` makes them DNS-only. When
+//! `proxied` is not given, records keep the proxied state they have at CloudFlare,
+//! and new records are created with the default of CloudFlare.
+//!
+//! Zones can be declared with the cluster-scoped syntixi.io/v1alpha1/Zone resource.
+//! Records in a Zone without a `ttl` use the `defaultTtl` of the Zone, Records of a
+//! type not listed in the `allowedTypes` of the Zone are rejected, and a
+//! `providerRef` restricts the Records in the Zone to the provider of that name.
+//!
+//! ```yaml
+//! apiVersion: syntixi.io/v1alpha1
+//! kind: Zone
+//! metadata:
+//!   name: syntixi-io
+//! spec:
+//!   zone: syntixi.io
+//!   providerRef: ares/cloudflare
+//!   defaultTtl: 300
+//!   allowedTypes: [A, AAAA, CNAME, TXT]
+//! ```
+//!
+//! A single Record can also choose its provider with `providerRef`, naming the
+//! element of the configuration (or Provider) it is synced with. The Record is
+//! then synced with only that provider, whether or not its selector matches the
+//! Record, and the `providerRef` of its Zone is ignored.
+//!
+//! Which namespaces may create Records under a domain name can be restricted with
+//! the cluster-scoped syntixi.io/v1alpha1/ZonePolicy resource. Once a domain name
+//! is under one of the `domains` of any ZonePolicy, Records for it are only synced
+//! in the `namespaces` (or namespaces matching the `namespaceSelector`) of the
+//! ZonePolicies covering it; other Records are rejected. Domain names that no
+//! ZonePolicy covers may be used from any namespace.
+//!
+//! ```yaml
+//! apiVersion: syntixi.io/v1alpha1
+//! kind: ZonePolicy
+//! metadata:
+//!   name: team-a
+//! spec:
+//!   namespaceSelector:
+//!     matchLabels:
+//!       team: a
+//!   domains:
+//!   - .team-a.syntixi.io
+//! ```
+//!
+//! Records that don't belong to any namespace, such as the apex or mail records
+//! of a zone, can be declared with the cluster-scoped syntixi.io/v1alpha1/ClusterRecord
+//! resource, which has the same spec and status as a Record. ZonePolicies do not
+//! apply to ClusterRecords, and their values can't be collected with the
+//! podSelector or from a ConfigMap, as both are read from the namespace of the
+//! Record. Events of ClusterRecords are published in the default namespace.
+//!
+//! ```yaml
+//! apiVersion: syntixi.io/v1alpha1
+//! kind: ClusterRecord
+//! metadata:
+//!   name: syntixi-io-mx
+//! spec:
+//!   fqdn: syntixi.io
+//!   type: MX
+//!   value:
+//!   - 10 mail.syntixi.io
+//! ```
+//!
+//! Records can be generated with the cluster-scoped syntixi.io/v1alpha1/RecordTemplate
+//! resource, which creates a Record from its `template` for every Namespace (or
+//! every Node with `forEach: Node`) matching its `selector`. `{name}` in the
+//! template is replaced with the name of the Namespace or Node, and `{namespace}`
+//! with the namespace of the generated Record. Records for Namespaces are named
+//! after the RecordTemplate and created in the Namespace, while Records for Nodes
+//! are named `<template>-<node>` and created in the `recordNamespace`. Generated
+//! Records are updated along with the RecordTemplate, and removed once their
+//! Namespace or Node no longer matches or the RecordTemplate is deleted.
+//!
+//! ```yaml
+//! apiVersion: syntixi.io/v1alpha1
+//! kind: RecordTemplate
+//! metadata:
+//!   name: apps
+//! spec:
+//!   selector:
+//!     matchLabels:
+//!       ares.syntixi.io/apps: "true"
+//!   template:
+//!     fqdn: "{namespace}.apps.syntixi.io"
+//!     type: CNAME
+//!     value:
+//!     - ingress.syntixi.io
+//! ```
+//!
+//! The records already hosted by a provider can be imported as Records with the
+//! syntixi.io/v1alpha1/ZoneSync resource, which lists every record of its `zone`
+//! at the provider named by `providerRef` every `interval` seconds (300 by
+//! default). With the default `mode: readOnly`, each type of each domain name is
+//! mirrored as a Record named `<type>.<fqdn>` in the namespace of the ZoneSync,
+//! which ARES ignores and keeps up to date with the provider; mirrored Records are
+//! removed along with their records or the ZoneSync. With `mode: adopt`, ARES
+//! takes ownership of the records instead, creating a tracking record for each
+//! domain name and a Record that is synced like any other, so that deleting it
+//! deletes the records. Domain names that are already owned by ARES are skipped.
+//!
+//! ```yaml
+//! apiVersion: syntixi.io/v1alpha1
+//! kind: ZoneSync
+//! metadata:
+//!   name: syntixi-io
+//! spec:
+//!   zone: syntixi.io
+//!   providerRef: cloudflare
+//!   mode: readOnly
+//! ```
+//!
+//! Subdomains can be delegated to a zone hosted by another configured provider
+//! using the delegation selector, which publishes NS records in the parent zone
+//! for the nameservers of the child zone. The Record must have the NS type.
+//!
+//! ```yaml
+//! apiVersion: syntixi.io/v1alpha1
+//! kind: Record
+//! metadata:
+//!   name: team-delegation
+//! spec:
+//!   fqdn: team.syntixi.io
+//!   ttl: 3600
+//!   type: NS
+//!   valueFrom:
+//!     delegation:
+//!       zone: team.syntixi.io
+//! ```
+//!
+//! The values of a Record can also be copied from another Record of the same type
+//! with the recordRef selector, such as to publish the addresses of a service
+//! under a second domain name without repeating its selector. The referenced
+//! Record is in the namespace of the Record unless a `namespace` is given, and
+//! can't itself use a recordRef. The Record is synced whenever the referenced
+//! Record changes.
+//!
+//! ```yaml
+//! apiVersion: syntixi.io/v1alpha1
+//! kind: Record
+//! metadata:
+//!   name: www-alias
+//! spec:
+//!   fqdn: www.syntixi.net
+//!   type: A
+//!   valueFrom:
+//!     recordRef:
+//!       name: www
+//! ```
+//!
 //! When a syntixi.io/v1alpha1/Record resource is created, an additional record
-//! is made for ARES to track ownership over the DNS record. So long as that
-//! tracking record exists, when the Kubernetes resource is deleted, the
-//! corresponding record and tracking record will be deleted.
+//! is made for ARES to track ownership over the DNS record, and the
+//! `ares.syntixi.io/finalizer` finalizer is added to the resource. So long as
+//! that tracking record exists, when the Kubernetes resource is deleted, the
+//! corresponding record and tracking record will be deleted before the
+//! finalizer is removed. Tracked records left behind by Records deleted while ARES
+//! was not running are periodically removed as well.
+//!
+//! The tracking record is a TXT record at `_owner.<fqdn>` naming the owner ID of
+//! the ARES instance (`--owner-id`, by default `default`) along with the
+//! namespace, name and UID of the Record that created it, and the ID of the
+//! cluster given by `--cluster-id`. Domain names tracked by another owner are
+//! never modified, so that multiple instances of ARES can share a zone.
+//!
+//! Records owned by external-dns, marked by its `heritage=external-dns` TXT
+//! records, are likewise never modified. To migrate records from external-dns to
+//! ARES, pass the owner ID used by external-dns to `--import-external-dns-owner`;
+//! the heritage records of external-dns are then replaced with tracking records
+//! the next time a matching Record is synced.
+//!
+//! Management of a Record can be paused by annotating it with
+//! `ares.syntixi.io/ignore: "true"`, such as during provider maintenance or while
+//! fixing records by hand. While paused, ARES neither syncs nor deletes the
+//! records of the Record; a paused Record that is deleted is removed right
+//! away, and its records are left in place.
+//!
+//! The values synced for each of the last generations of a Record (5 by default,
+//! set with `--history-size`) are kept in the `history` field of its status. To
+//! quickly recover from a bad change, such as a selector matching the wrong Pods,
+//! annotate the Record with `ares.syntixi.io/rollback: "<generation>"`; the values
+//! of that generation are then synced instead of those of the Record until the
+//! annotation is removed.
+//!
+//! Setting `requireApproval: true` on an element holds back changes to the records
+//! of that provider until they are approved. The changes are listed in the `plan`
+//! field of the status of the Record along with a hash, and are made once the
+//! Record is annotated with `ares.syntixi.io/approve: <hash>`. If the changes
+//! differ from the approved plan by the next sync, a new plan must be approved.
+//! Changing the FQDN or type of a Record plans the removal of the records of its
+//! previous FQDN or type along with the new records.
+//!
+//! Logs are written to stderr at the level given with `--log-level` (`info` by
+//! default; one of `trace`, `debug`, `info`, `warn` and `error`), as
+//! human-readable lines or, with `--log-format json`, as a JSON object per line for
+//! log collectors.
+//!
+//! In the JSON format, the fields of each message are written as keys of its
+//! object, along with the spans it was written in, so that logs can be queried by
+//! the `record` being synced, the `provider` and `zone` it is synced with, and,
+//! for every record created or deleted, the `action`, `fqdn` and `value`.
+//!
+//! Each reconciliation of a Record is given a random `correlation_id`, which is
+//! logged with every message of the reconciliation and sent in the
+//! `X-Correlation-ID` header of every request made to a provider during the
+//! reconciliation, to match the logs of ARES with the logs of the provider.
+//!
+//! Errors repeated by every retry, such as a provider rejecting the credentials of
+//! ARES, are logged once per minute, with the number of times the error was
+//! suppressed since it was last logged in the `suppressed` field. The interval is
+//! set in seconds with `--log-rate-limit`, and every error is logged when it is 0.
+//!
+//! The sync of each Record is traced as a span, along with every request made to
+//! its providers. With `--otlp-endpoint`, such as `http://localhost:4317`, spans are
+//! exported to an OpenTelemetry collector over OTLP, so that a sync can be followed
+//! from the change of a Record to the requests made to its providers.
+//!
+//! ARES connects to the cluster it runs in, or with the default kubeconfig when
+//! run outside a cluster. During development or an incident, ARES can be run
+//! locally against a remote cluster with `--kubeconfig`, choosing a context other
+//! than the current context of the kubeconfig with `--context`.
+//!
+//! As a last line of defense when trying out a configuration against production
+//! credentials, the domain names that ARES manages can be restricted with
+//! `--domain-filter` and `--exclude-domains`, each a list of domains separated by
+//! commas. A domain matches itself and all of its subdomains, and Records outside
+//! of the filter are left alone regardless of the selectors of the configuration;
+//! such a Record that is deleted is removed right away, and its records are left
+//! in place.
+//!
+//! A Record that fails to sync is marked failed in its status and retried with an
+//! exponential backoff while every other Record keeps syncing. With `--fail-fast`,
+//! ARES instead shuts down once a Record is marked failed with an error that
+//! retrying won't fix, waiting for in-flight syncs like on a shutdown signal, and
+//! leaves it to Kubernetes to restart ARES, which surfaces failures as restarts of
+//! the Pod. Errors that retrying may fix, such as rate limits, are still retried.
+//!
+//! The error of the most recent failed sync is kept in `status.lastError` of the
+//! Record, with its `message` and the `time` of the failure, even once the Record
+//! is synced again. The time taken by every reconciliation is measured in the
+//! `ares_reconcile_duration_seconds` metric, labeled by `result`.
+//!
+//! With `--health-address`, such as `0.0.0.0:8080`, ARES serves `/healthz` for a
+//! liveness probe, answering as long as ARES runs, and `/readyz` for a readiness
+//! probe, answering once the configuration is loaded, Records can be listed and
+//! the credentials of every provider were checked successfully by finding the
+//! zones of its Selectors. Until then, `/readyz` responds with 503 and lists the
+//! conditions that are not met, including the error of each provider that failed
+//! the check. As ARES restarts when its configuration changes, providers are
+//! checked again whenever the configuration is reloaded.
+//!
+//! Prometheus metrics are served at `/metrics` on the same address. Every call
+//! made to a provider is measured in `ares_provider_request_duration_seconds`,
+//! and calls that failed, such as by being rate limited, are counted in
+//! `ares_provider_request_errors_total`, both labeled by the kind of `provider`
+//! and the `operation`, such as `add_record`, `delete_record`, `update_record` or
+//! `apply_batch` for a batch of changes. Failed calls are also labeled by the
+//! kind of `error` reported by the provider: `not_found`, `rate_limited`,
+//! `auth_failed`, `conflict`, `transient`, or `other` for any other error.
+//!
+//! Calls to providers that fail transiently, such as with a server error, a
+//! timeout or by being rate limited, are retried up to 3 times with an exponential
+//! backoff and jitter, waiting at least as long as asked by a provider that rate
+//! limited a call. Calls that create records are only retried when they are
+//! known not to have been made, so that records are never created twice, and
+//! retries are limited to about one in ten calls during an outage. Every retry is
+//! counted in `ares_provider_request_retries_total`.
+//!
+//! Records are resynced periodically, and changes made by a resync while the
+//! values of a Record are the same as at its last sync correct changes made to the
+//! provider outside of ARES. Such drift is counted in
+//! `ares_drift_corrections_total`, labeled by `zone` and by `kind`: `created` for
+//! missing records, `deleted` for unexpected records and `corrected` for both. The
+//! gauge `ares_zone_drifted_records` holds the number of Records of each zone that
+//! had drifted at their last resync, to alert on out-of-band DNS changes.
+//!
+//! Errors can be reported to Sentry by passing a DSN with `--sentry-dsn`. Panics
+//! are reported, as are Records that failed to sync a number of times in a row (3
+//! by default, set with `--sentry-failure-threshold`), tagged with the FQDN of the
+//! Record and naming the Record in the report. Each streak of failures of a Record
+//! is reported once. Failures that retrying won't fix, such as credentials refused
+//! by the provider, are reported at the first failure.
+//!
+//! With `--api-address`, ARES serves a read-only JSON API for dashboards and quick
+//! inspection. `/api/records` lists every Record with its desired values, the
+//! values observed at each provider according to the state cache (when enabled)
+//! and the result of its last sync, and `/api/zones` lists every Zone with the
+//! number of Records in the zone and how many of them are ready.
+//!
+//! With `--dry-run`, ARES reads from providers as usual but logs every change it
+//! would make to their records instead of making it, counting each skipped change
+//! in `ares_dry_run_operations_total`, labeled by the kind of `provider` and the
+//! `operation`. Combined with `cleanup-orphans`, it only prints the orphaned domain
+//! names, like `cleanup-orphans --dry-run`. Skipped changes are neither audited nor
+//! notified, the state cache is left as is, and Records are not marked synced; a
+//! Record being deleted keeps its finalizer until ARES runs without `--dry-run`.
+//!
+//! The `noop` provider hosts no records and skips every change like `--dry-run`,
+//! to try ARES without credentials for a real provider. It hosts the zones listed
+//! in the `zones` of its `providerOptions`.
+//!
+//! Every record created or deleted at a provider can be written to an audit log,
+//! naming the Record the change was made for along with the old and new value.
+//! Passing `--audit-log` writes each change to stdout as a JSON object, and
+//! `--audit-configmap` keeps the latest changes (500 by default, set with
+//! `--audit-configmap-size`) in a ConfigMap in the namespace of the ARES Secret.
+//!
+//! Changes can also be posted to webhooks given with `--notify-webhooks`, such as
+//! to alert on-call engineers. Each change is posted as a JSON object by default,
+//! or as a message for Slack-compatible incoming webhooks with
+//! `--notify-format slack`. Posting a notification fails after 10 seconds, set
+//! with `--notify-timeout`.
+//!
+//! Records can be kept short with the defaulting webhook, served with
+//! `--webhook-address` using the PEM certificate and PKCS #8 key given with
+//! `--webhook-cert` and `--webhook-key`. When registered as a mutating webhook for
+//! Records, it normalizes the `fqdn` to lowercase ASCII, fills in the `ttl` from
+//! the `defaultTtl` of the Zone of the Record (or, failing that, the `defaultTtl`
+//! of the matching element of the configuration), and infers a missing `type`:
+//! A or AAAA for addresses, CNAME for a single domain name, A for Pod selectors,
+//! SSHFP for host keys and NS for delegations.
+//!
+//! ```yaml
+//! apiVersion: admissionregistration.k8s.io/v1
+//! kind: MutatingWebhookConfiguration
+//! metadata:
+//!   name: ares
+//! webhooks:
+//! - name: records.ares.syntixi.io
+//!   admissionReviewVersions: [v1, v1beta1]
+//!   sideEffects: None
+//!   clientConfig:
+//!     caBundle: <base64 CA certificate>
+//!     service:
+//!       name: ares
+//!       namespace: ares
+//!       port: 8443
+//!   rules:
+//!   - apiGroups: [syntixi.io]
+//!     apiVersions: [v1alpha1]
+//!     operations: [CREATE, UPDATE]
+//!     resources: [records, clusterrecords]
+//! ```
 // }}}
 
 // imports {{{
 use clap::Clap;
 
-use std::ops::Deref;
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 
-use slog::{
-    crit, debug, error, info, log, o,
-    Drain, Logger,
+use tracing::{debug, info, info_span, warn};
+use tracing_futures::Instrument;
+use tracing_subscriber::{
+    filter::LevelFilter,
+    fmt,
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
 };
 
 use anyhow::{anyhow, Result};
 
 use futures::{
-    StreamExt, TryStreamExt, select,
-    future::{Future, Fuse, join_all},
-};
-use k8s_openapi::api::core::v1::{Event, Secret};
-use kube::{
-    api::{Api, ListParams, Meta, WatchEvent},
-    Client,
+    StreamExt, TryStreamExt,
+    future::join_all,
 };
-use kube_runtime::{utils::try_flatten_applied, watcher};
-use kube_derive::{CustomResource};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::{api::Api, Client};
+use kube_runtime::watcher;
+use tokio::signal::unix::{signal, SignalKind};
 
+mod api;
+mod audit;
 mod cli;
-
-mod xpathable;
+mod cluster_record;
+mod clusters;
+mod controller;
+mod correlation;
+mod crd;
+mod doctor;
+mod error;
+mod events;
+mod health;
+mod log_limit;
+mod metrics;
+mod notify;
 
 mod providers;
 mod program_config;
+mod provider_spec;
 mod record_spec;
+mod record_template;
+mod reporting;
+mod secret_ref;
+mod state_cache;
+mod validate;
+mod webhook;
+mod zone_cache;
+mod zone_policy;
+mod zone_spec;
+mod zone_sync;
 
 use program_config::AresConfig;
-use providers::{ProviderConfig, util::{ProviderBackend, ZoneDomainName,
-                                       RecordType, Record as RecordObject}};
-use record_spec::{Record, RecordValueCollector};
 // }}}
 
+/// Time between reads of the configuration file given with `--config-file` to detect changes.
+const CONFIG_FILE_INTERVAL: Duration = Duration::from_secs(30);
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts: cli::Opts = cli::Opts::parse();
-    let decorator = slog_term::TermDecorator::new().build();
-    let drain = slog_term::FullFormat::new(decorator).build().fuse();
-    let drain = slog_async::Async::new(drain).build().fuse();
-    let root_logger = slog::Logger::root(
-        drain,
-        o!("secret" => opts.secret.clone(),
-           "secret_key" => opts.secret_key.clone(),
-           "secret_namespace" => opts.secret_namespace.clone()),
-    );
-    let client = Client::try_default().await?;
-
-    info!(root_logger, "Loading configuration from Secret");
-    let secrets: Api<Secret> = Api::namespaced(client, opts.secret_namespace.as_str());
-    let secret = secrets.get(opts.secret.as_str()).await?;
-    let config_data = secret
-        .data
-        .as_ref()
-        .ok_or(anyhow!("Unable to get data from Secret"))?;
-    let config_content = config_data
-        .get(opts.secret_key.as_str())
-        .ok_or(anyhow!("Unable to get key from Secret"))?
-        .clone().0;
-
-    debug!(root_logger, "Configuration loaded from Secret");
-    let config: Vec<Arc<AresConfig>> =
-        serde_yaml::from_str::<Vec<_>>(std::str::from_utf8(&config_content[..])?)?
-        .into_iter()
-        .map(Arc::new)
-        .collect();
-
-    let records: Api<Record> = Api::all(Client::try_default().await?);
-    let record_list: Vec<Arc<Record>> = records.list(&ListParams::default()).await?
-        .items
-        .into_iter()
-        .map(Arc::new)
-        .collect();
+    match opts.command {
+        Some(cli::Command::Version) => {
+            println!("ares {}", env!("CARGO_PKG_VERSION"));
+            return Ok(())
+        },
+        Some(cli::Command::Crd) => {
+            println!("{}", crd::to_yaml()?);
+            return Ok(())
+        },
+        _ => {},
+    }
+    // The exporter flushes the remaining spans once the guard is dropped, as ARES terminates.
+    let _exporter = init_tracing(&opts)?;
+    let _reporting = reporting::init(&opts);
+    let span = info_span!("ares",
+                          secret = %opts.secret,
+                          secret_key = %opts.secret_key,
+                          secret_namespace = %opts.secret_namespace,
+                          cluster_id = ?opts.cluster_id);
+    run(opts).instrument(span).await
+}
 
-    let mut handles = vec![];
+/// Run the subcommand given on the command line, or the controller if none is given.
+async fn run(opts: cli::Opts) -> Result<()> {
+    if opts.dry_run {
+        providers::dry_run::enable();
+    }
+    if let Some(cli::Command::Validate(validate)) = &opts.command {
+        let config: Vec<Arc<AresConfig>> = match &validate.file {
+            Some(path) => {
+                // Files are validated without a cluster unless they reference Secrets.
+                let client = clusters::local(&opts).await.ok();
+                let content = std::fs::read_to_string(path)?;
+                secret_ref::parse(client.as_ref(), &content, opts.secret_namespace.as_str())
+                    .await?
+                    .0
+                    .into_iter()
+                    .map(Arc::new)
+                    .collect()
+            },
+            None => {
+                let client = clusters::local(&opts).await?;
+                let secrets = Api::namespaced(client.clone(), opts.secret_namespace.as_str());
+                load_config(&client, &secrets, &opts).await?.config
+            },
+        };
+        return validate::run(&config, validate.check_credentials).await
+    }
 
-    // TODO watch over config and reload when changes are made
-    for ares in config.into_iter() {
-        // Find all matching Records and put a ref of them into a Vec
-        let allowed_records: Vec<Arc<Record>> = record_list
-            .iter()
-            .filter(|record| ares.matches_selector(record.spec.fqdn.as_str()))
-            .map(|x| x.clone()) // clone() of Arc<> is intentional
-            .collect();
-
-        // TODO put a watcher over records instead of just getting them at program start
-        for mut record in allowed_records {
-            // Generate a proxy logger to be cloned so we can build upon it every loop
-            let proxy_logger = root_logger.new(o!());
-            let sub_ac = ares.clone(); // clone of Arc<> is intentional
-            handles.push(tokio::spawn(async move {
-                loop {
-                    let sub_logger = proxy_logger.new(o!("record" => record.spec.fqdn.clone()));
-                    if let Some(collector_obj) = &record.spec.value_from {
-                        let collector = collector_obj.deref();
-                        info!(sub_logger, "Getting zone domain name");
-                        let zone = match sub_ac.provider.get_zone(&record.spec.fqdn).await {
-                            Ok(z) => z,
-                            Err(e) => {
-                                crit!(sub_logger, "Error! {}", e);
-                                break
-                            }
-                        };
-                        let mut builder = RecordObject::builder(record.spec.fqdn.clone(),
-                                                                zone, RecordType::A);
-                        // Syncing should happen regardless of using a watcher to ensure that any
-                        // extra records are deleted.
-                        info!(sub_logger, "Syncing");
-                        let sync_state = collector.sync(&record.metadata, &sub_ac.provider,
-                                                        &mut builder).await;
-                        if let Err(e) = sync_state {
-                            crit!(sub_logger, "Error! {}", e);
-                            break
-                        }
-                        info!(sub_logger, "Finished syncing");
-
-                        info!(sub_logger, "Spawning watcher");
-                        let res = collector.watch_values(&record.metadata, &sub_ac.provider,
-                                                         &mut builder).await;
-                        info!(sub_logger, "Stopped watching");
-
-                        // Set a new record if the watcher stops; this could be the result of a
-                        // timeout or a change in the Record value, which may need a refresh.
-                        record = match res {
-                            Ok(r) => Arc::new(r),
-                            Err(e) => {
-                                crit!(sub_logger, "Error! {}", e);
-                                break
-                            }
-                        }
-                    }
-                }
-            }));
-        }
+    let client = clusters::local(&opts).await?;
+
+    if let Some(cli::Command::Doctor) = &opts.command {
+        let secrets = Api::namespaced(client.clone(), opts.secret_namespace.as_str());
+        let config = load_config(&client, &secrets, &opts)
+            .await
+            .map(|loaded| loaded.config);
+        return doctor::run(&client, &opts, config).await
     }
 
-    let secret_logger = root_logger.new(o!());
-    handles.push(tokio::spawn(async move {
-        loop {
-            info!(secret_logger, "Watching over Secrets to detect configuration changes");
-            let mut secret_watcher = secrets
-                .watch(&ListParams::default(), "0")
+    // The health endpoints are served before the configuration is loaded, so that ARES is live
+    // but not ready while loading it.
+    let health = Arc::new(health::Health::default());
+    if let None | Some(cli::Command::Run) = &opts.command {
+        health::spawn(health.clone(), &opts).await?;
+    }
+
+    if opts.install_crds {
+        info!("Installing CustomResourceDefinitions");
+        crd::install(&client).await?;
+    }
+
+    let secrets: Api<Secret> = Api::namespaced(client.clone(), opts.secret_namespace.as_str());
+    let loaded = load_config(&client, &secrets, &opts).await?;
+    let config = loaded.config;
+    health.set_config_loaded();
+
+    // TODO watch over config and reload when changes are made
+    let clusters = clusters::connect_all(&client, &config, &opts.secret_namespace).await?;
+    match &opts.command {
+        Some(cli::Command::Plan) => {
+            return controller::plan_all(client, config, clusters, &opts).await
+        },
+        Some(cli::Command::Query(query)) => {
+            return controller::query(client, config, clusters, &opts,
+                                     &query.fqdn).await
+        },
+        Some(cli::Command::Export(export)) => {
+            return controller::export_all(client, config, clusters, &opts,
+                                          &export.format).await
+        },
+        _ => {},
+    }
+    let domain_filter = program_config::DomainFilter::new(&opts.domain_filter,
+                                                          &opts.exclude_domains);
+    // Domain names adopted outside of the controller are owned like those of any Record.
+    let owner = providers::util::Heritage {
+        owner: Some(opts.owner_id.clone()),
+        cluster: opts.cluster_id.clone(),
+        ..providers::util::Heritage::default()
+    };
+    if let Some(cli::Command::Import(import)) = &opts.command {
+        return zone_sync::import(&client, &config, &domain_filter, &owner, &import.provider,
+                                 &import.zone, &import.namespace, import.apply).await
+    }
+    // Changes skipped by dry runs were never made, so they are neither audited nor notified.
+    let changes = if opts.dry_run {
+        vec![]
+    } else {
+        vec![
+            audit::spawn(client.clone(), &opts),
+            notify::spawn(&opts)?,
+        ].into_iter().flatten().collect()
+    };
+    match &opts.command {
+        Some(cli::Command::Sync) => {
+            return controller::sync_all(client, config, clusters, changes, &opts)
                 .await
-                .unwrap()
-                .boxed();
-            while let Ok(Some(secret_status)) = secret_watcher.try_next().await {
-                // If the configuration changes, trigger a panic which will cause a restart.
-                match secret_status {
-                    WatchEvent::Modified(modified) => {
-                        if modified.metadata.uid == secret.metadata.uid {
-                            info!(secret_logger, "Found config change, terminating");
-                            std::process::exit(0);
-                        }
-                    },
-                    WatchEvent::Deleted(deleted) => {
-                        if deleted.metadata.uid == secret.metadata.uid {
-                            info!(secret_logger, "Found config change, terminating");
-                            std::process::exit(0);
-                        }
-                    },
-                    _ => {},
-                }
-            }
-            info!(secret_logger, "Restarting Secret watcher");
+        },
+        Some(cli::Command::CleanupOrphans(cleanup)) => {
+            return controller::cleanup_orphans(client, config, changes, &opts,
+                                               cleanup.dry_run || opts.dry_run).await
+        },
+        _ => {},
+    }
+
+    // Every task is spawned within the current span, so that its events carry the fields of the
+    // span like those of this task.
+    let mut handles = vec![];
+    handles.push(tokio::spawn(health::check(client.clone(), health, config.clone())
+        .in_current_span()));
+    let state_cache = state_cache::StateCache::load(&client, &opts).await?;
+    webhook::spawn(client.clone(), config.clone(), &opts).await?;
+    let zone_sync_config = config.clone();
+    let (context, controller) = controller::build(client.clone(),
+                                                  config,
+                                                  clusters,
+                                                  changes,
+                                                  state_cache,
+                                                  &opts)?;
+    api::spawn(context.clone(), &opts).await?;
+    handles.push(tokio::spawn(controller.in_current_span()));
+
+    if let Some(generations) = loaded.provider_generations {
+        handles.push(tokio::spawn(provider_spec::watch_changes(client.clone(), generations)
+            .in_current_span()));
+    }
+
+    // Records generated from RecordTemplates and imported by ZoneSyncs are shared by every shard,
+    // so only the first shard generates them.
+    if opts.shard_index == 0 {
+        handles.push(tokio::spawn(record_template::run(
+            client.clone(),
+            std::time::Duration::from_secs(opts.resync_interval)).in_current_span()));
+        handles.push(tokio::spawn(zone_sync::run(
+            client.clone(),
+            zone_sync_config,
+            domain_filter,
+            owner).in_current_span()));
+    }
+
+    handles.push(tokio::spawn(exit_on_change(secrets, opts.secret.clone(), loaded.secret)
+        .in_current_span()));
+    if let Some(name) = &opts.config_configmap {
+        let config_maps = Api::namespaced(client.clone(), opts.secret_namespace.as_str());
+        handles.push(tokio::spawn(exit_on_change::<ConfigMap>(config_maps, name.clone(),
+                                                              loaded.config_map)
+            .in_current_span()));
+    }
+    if let (Some(path), Some(file)) = (&opts.config_file, loaded.file) {
+        handles.push(tokio::spawn(exit_on_file_change(path.clone(), file).in_current_span()));
+    }
+    // Credentials referenced by the configuration are reloaded when rotated.
+    for ((namespace, name), secret) in loaded.referenced_secrets {
+        let secrets = Api::namespaced(client.clone(), &namespace);
+        handles.push(tokio::spawn(exit_on_change::<Secret>(secrets, name, Some(secret))
+            .in_current_span()));
+    }
+
+    // A Record failing with `--fail-fast` shuts down ARES the same way as a shutdown signal.
+    let failed = tokio::select! {
+        _ = join_all(handles) => return Ok(()),
+        signal = shutdown_signal() => {
+            signal?;
+            info!("Received shutdown signal, waiting for in-flight syncs");
+            false
+        },
+        _ = context.get_ref().failed() => {
+            info!("A Record failed to sync, waiting for in-flight syncs");
+            true
+        },
+    };
+    if context.get_ref().drain(Duration::from_secs(opts.drain_timeout)).await {
+        info!("Finished in-flight syncs, terminating");
+    } else {
+        warn!("Timed out waiting for in-flight syncs, terminating");
+    }
+    if let Err(e) = context.get_ref().flush_state_cache().await {
+        warn!("Unable to write state cache: {}", e);
+    }
+    if failed {
+        return Err(anyhow!("A Record failed to sync with --fail-fast"))
+    }
+
+    Ok(())
+}
+
+/// Install the subscriber writing events to stderr at the level and in the format given on the
+/// command line, and exporting spans to the OpenTelemetry collector given with `--otlp-endpoint`,
+/// if any. Spans are exported until the returned guard is dropped.
+fn init_tracing(opts: &cli::Opts) -> Result<Option<opentelemetry_otlp::Uninstall>> {
+    let level: LevelFilter = opts
+        .log_level
+        .parse()
+        .map_err(|_| anyhow!("Invalid log level: {}", opts.log_level))?;
+    let (otlp, exporter) = match &opts.otlp_endpoint {
+        Some(endpoint) => {
+            let (tracer, exporter) = opentelemetry_otlp::new_pipeline()
+                .with_endpoint(endpoint)
+                .install()?;
+            (Some(tracing_opentelemetry::layer().with_tracer(tracer)), Some(exporter))
+        },
+        None => (None, None),
+    };
+    let registry = tracing_subscriber::registry().with(level).with(otlp);
+    match opts.log_format.as_str() {
+        // Fields of events are flattened into the object of each line, next to the fields of
+        // every span the event was recorded in, such as the Record and provider being synced.
+        "json" => {
+            registry.with(fmt::layer().json().flatten_event(true).with_writer(std::io::stderr))
+                .init()
+        },
+        _ => registry.with(fmt::layer().with_writer(std::io::stderr)).init(),
+    }
+    Ok(exporter)
+}
+
+/// The configuration of ARES, along with the sources that it was loaded from, so that changes to
+/// them can be detected.
+struct LoadedConfig {
+    config: Vec<Arc<AresConfig>>,
+    secret: Option<Secret>,
+    config_map: Option<ConfigMap>,
+    file: Option<String>,
+    referenced_secrets: secret_ref::ReferencedSecrets,
+    provider_generations: Option<HashMap<String, Option<i64>>>,
+}
+
+/// Load the configuration from the ARES Secret, followed by the configuration of the ConfigMap
+/// and file given with `--config-configmap` and `--config-file`, if any, and of every Provider.
+async fn load_config(client: &Client, secrets: &Api<Secret>, opts: &cli::Opts) ->
+        Result<LoadedConfig> {
+    info!("Loading configuration from Secret");
+    // The Secret may be left out when the configuration is loaded from other sources.
+    let secret = match secrets.get(opts.secret.as_str()).await {
+        Ok(secret) => Some(secret),
+        Err(kube::Error::Api(e)) if e.code == 404 => {
+            info!("Secret not found, loading configuration from other sources only");
+            None
+        },
+        Err(e) => return Err(e.into()),
+    };
+    let namespace = opts.secret_namespace.as_str();
+    let mut referenced_secrets = secret_ref::ReferencedSecrets::new();
+    let mut config: Vec<AresConfig> = match &secret {
+        Some(secret) => {
+            let config_data = secret
+                .data
+                .as_ref()
+                .ok_or(anyhow!("Unable to get data from Secret"))?;
+            let config_content = config_data
+                .get(opts.secret_key.as_str())
+                .ok_or(anyhow!("Unable to get key from Secret"))?
+                .clone().0;
+            let content = std::str::from_utf8(&config_content[..])?;
+            let (config, secrets) = secret_ref::parse(Some(client), content, namespace).await?;
+            referenced_secrets.extend(secrets);
+            debug!("Configuration loaded from Secret");
+            config
+        },
+        None => vec![],
+    };
+
+    let config_map = match &opts.config_configmap {
+        Some(name) => {
+            let config_maps: Api<ConfigMap> = Api::namespaced(client.clone(),
+                                                              opts.secret_namespace.as_str());
+            let config_map = config_maps.get(name).await?;
+            let config_content = config_map
+                .data
+                .as_ref()
+                .and_then(|data| data.get(opts.secret_key.as_str()))
+                .ok_or(anyhow!("Unable to get key from ConfigMap"))?;
+            let (parsed, secrets) = secret_ref::parse(Some(client), config_content, namespace)
+                .await?;
+            config.extend(parsed);
+            referenced_secrets.extend(secrets);
+            debug!(configmap = %name, "Configuration loaded from ConfigMap");
+            Some(config_map)
+        },
+        None => None,
+    };
+
+    let file = match &opts.config_file {
+        Some(path) => {
+            let config_content = tokio::fs::read_to_string(path).await?;
+            let (parsed, secrets) = secret_ref::parse(Some(client), &config_content, namespace)
+                .await?;
+            config.extend(parsed);
+            referenced_secrets.extend(secrets);
+            debug!(file = %path, "Configuration loaded from file");
+            Some(config_content)
+        },
+        None => None,
+    };
+
+    let (provider_config, provider_generations) = provider_spec::load_all(client).await?;
+    config.extend(provider_config);
+    Ok(LoadedConfig {
+        config: config.into_iter().map(Arc::new).collect(),
+        secret: secret,
+        config_map: config_map,
+        file: file,
+        referenced_secrets: referenced_secrets,
+        provider_generations: provider_generations,
+    })
+}
+
+/// Watch over the object of kind `K` named `name` that the configuration was loaded from, and
+/// terminate once it changes, which will cause a restart. `loaded` is the object as it was
+/// loaded, if it existed.
+async fn exit_on_change<K>(api: Api<K>, name: String, loaded: Option<K>)
+        where K: k8s_openapi::Resource + kube::api::Meta + Clone +
+                 serde::de::DeserializeOwned + Send + Sync + 'static {
+    info!("Watching over {}s to detect configuration changes", K::KIND);
+    let list_params = controller::watch_params().fields(&format!("metadata.name={}", name));
+    // The watcher relists the object whenever the watch expires, so the object can be watched
+    // for the lifetime of the process.
+    let mut config_watcher = controller::delay_on_error(watcher(api, list_params)).boxed();
+    let version = |object: &K| {
+        let metadata = kube::api::Meta::meta(object);
+        (metadata.uid.clone(), metadata.resource_version.clone())
+    };
+    let loaded = loaded.as_ref().map(version);
+    loop {
+        let changed = match config_watcher.try_next().await {
+            Ok(Some(watcher::Event::Applied(applied))) => {
+                Some(version(&applied)) != loaded
+            },
+            Ok(Some(watcher::Event::Deleted(_))) => loaded.is_some(),
+            Ok(Some(watcher::Event::Restarted(restarted))) => {
+                // The object may have been created, changed or deleted while relisting.
+                restarted.first().map(version) != loaded
+            },
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Error watching {}, relisting: {}", K::KIND, e);
+                false
+            },
+        };
+        if changed {
+            info!("Found config change, terminating");
+            std::process::exit(0);
         }
-    }));
+    }
+}
 
-    join_all(handles).await;
+/// Periodically read the configuration file, and terminate once it changes, which will cause a
+/// restart. Files mounted from Secrets and ConfigMaps are updated in place by the kubelet.
+async fn exit_on_file_change(path: String, loaded: String) {
+    info!(file = %path, "Watching over configuration file to detect changes");
+    loop {
+        tokio::time::delay_for(CONFIG_FILE_INTERVAL).await;
+        match tokio::fs::read_to_string(&path).await {
+            Ok(content) if content == loaded => {},
+            Ok(_) => {
+                info!("Found config change, terminating");
+                std::process::exit(0);
+            },
+            Err(e) => warn!("Unable to read configuration file: {}", e),
+        }
+    }
+}
 
+/// Wait for either SIGTERM, sent by Kubernetes when terminating a Pod, or SIGINT.
+async fn shutdown_signal() -> Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sigint = signal(SignalKind::interrupt())?;
+    tokio::select! {
+        _ = sigterm.recv() => {},
+        _ = sigint.recv() => {},
+    }
     Ok(())
 }
+
@@ -0,0 +1,76 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Prometheus metrics of ARES, served at `/metrics` alongside the health endpoints.
+
+// {{{ imports
+use anyhow::Result;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, Encoder,
+    HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder,
+};
+// }}}
+
+lazy_static! {
+    /// Time taken by each reconciliation of a Record, by whether it succeeded.
+    pub static ref RECONCILE_DURATION: HistogramVec = register_histogram_vec!(
+        "ares_reconcile_duration_seconds",
+        "Time taken by reconciliations of Records, by result (success or error).",
+        &["result"]
+    ).unwrap();
+
+    /// Time taken by each call to a provider, by kind of provider and operation.
+    pub static ref PROVIDER_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "ares_provider_request_duration_seconds",
+        "Time taken by calls to providers, by kind of provider and operation.",
+        &["provider", "operation"]
+    ).unwrap();
+
+    /// Number of calls to a provider that failed, by kind of provider, operation and kind of
+    /// error.
+    pub static ref PROVIDER_REQUEST_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "ares_provider_request_errors_total",
+        "Number of calls to providers that failed, by kind of provider, operation and kind of \
+         error.",
+        &["provider", "operation", "error"]
+    ).unwrap();
+
+    /// Number of calls to a provider retried after failing transiently, by kind of provider and
+    /// operation.
+    pub static ref PROVIDER_REQUEST_RETRIES: IntCounterVec = register_int_counter_vec!(
+        "ares_provider_request_retries_total",
+        "Number of calls to providers retried after failing transiently, by kind of provider \
+         and operation.",
+        &["provider", "operation"]
+    ).unwrap();
+
+    /// Number of calls to a provider skipped by dry runs, by kind of provider and operation.
+    pub static ref DRY_RUN_OPERATIONS: IntCounterVec = register_int_counter_vec!(
+        "ares_dry_run_operations_total",
+        "Number of changes to providers skipped by dry runs, by kind of provider and operation.",
+        &["provider", "operation"]
+    ).unwrap();
+
+    /// Number of domain names found to differ from their Record by a resync, by zone and by
+    /// whether records were created, deleted, or both to correct them.
+    pub static ref DRIFT_CORRECTIONS: IntCounterVec = register_int_counter_vec!(
+        "ares_drift_corrections_total",
+        "Number of domain names corrected by resyncs after changes made outside of ARES, by \
+         zone and kind of correction (created, deleted or corrected).",
+        &["zone", "kind"]
+    ).unwrap();
+
+    /// Number of Records of each zone that differed from the provider at their last resync.
+    pub static ref DRIFTED_RECORDS: IntGaugeVec = register_int_gauge_vec!(
+        "ares_zone_drifted_records",
+        "Number of Records that differed from the provider at their last resync, by zone.",
+        &["zone"]
+    ).unwrap();
+}
+
+/// Render every registered metric in the Prometheus text format.
+pub fn encode() -> Result<Vec<u8>> {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(buffer)
+}
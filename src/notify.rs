@@ -0,0 +1,88 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Notifications posted to webhooks whenever records are created or deleted at a provider, so
+//! that changes to DNS can be followed as they happen.
+
+// {{{ imports
+use std::time::Duration;
+
+use anyhow::Result;
+use serde_json::Value;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tracing::warn;
+use tracing_futures::Instrument;
+
+use crate::cli::Opts;
+use crate::providers::util::{Change, ChangeKind};
+use crate::reqwest_client_builder;
+// }}}
+
+/// Build the message describing a change, such as "Created A record www.syntixi.io with value
+/// 192.0.2.1 for Record default/www".
+fn message(change: &Change) -> String {
+    let action = match change.kind {
+        ChangeKind::Create => "Created",
+        ChangeKind::Delete => "Deleted",
+    };
+    let mut message = format!("{} {:?} record {} with value {}", action,
+                              change.record.record_type, change.record.fqdn,
+                              change.record.value);
+    if let Some(resource) = &change.heritage.resource {
+        message.push_str(&format!(" for Record {}", resource));
+    }
+    if let Some(cluster) = &change.heritage.cluster {
+        message.push_str(&format!(" in cluster {}", cluster));
+    }
+    message
+}
+
+/// Build the payload posted to webhooks for a change.
+fn payload(change: &Change, format: &str) -> Result<Value> {
+    match format {
+        "slack" => Ok(serde_json::json!({
+            "text": message(change),
+        })),
+        _ => Ok(serde_json::to_value(change)?),
+    }
+}
+
+/// Start posting notifications to the webhooks given on the command line, returning the channel
+/// that changes should be reported to. `None` is returned if no webhooks are given.
+pub fn spawn(opts: &Opts) -> Result<Option<UnboundedSender<Change>>> {
+    if opts.notify_webhooks.is_empty() {
+        return Ok(None)
+    }
+    let client = reqwest_client_builder!()
+        .timeout(Duration::from_secs(opts.notify_timeout))
+        .build()?;
+    let webhooks = opts.notify_webhooks.clone();
+    let format = opts.notify_format.clone();
+    let (sender, mut receiver) = mpsc::unbounded_channel::<Change>();
+    tokio::spawn(async move {
+        while let Some(change) = receiver.recv().await {
+            let payload = match payload(&change, &format) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!("Unable to build notification: {}", e);
+                    continue
+                },
+            };
+            for (index, webhook) in webhooks.iter().enumerate() {
+                let sent = client
+                    .post(webhook.as_str())
+                    .json(&payload)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                // Webhook URLs often embed a secret token, so only the position of the webhook
+                // is logged rather than the error, which includes the URL.
+                if let Err(e) = sent {
+                    warn!(status = ?e.status().map(|status| status.as_u16()),
+                          timeout = e.is_timeout(),
+                          "Unable to post notification to webhook {}", index);
+                }
+            }
+        }
+    }.in_current_span());
+    Ok(Some(sender))
+}
@@ -1,28 +1,260 @@
 // vim:set et sw=4 ts=4 foldmethod=marker:
 
 // imports {{{
+use anyhow::{anyhow, Result};
 use serde::{Serialize, Deserialize};
 
-use super::providers::ProviderConfig;
+use super::providers::{
+    ProviderConfig,
+    rate_limit::RateLimit,
+    util::{normalize_domain, Policy, ProviderBackend},
+};
 // }}}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all(serialize="camelCase", deserialize="camelCase"))]
 pub struct AresConfig {
+    /// Name of the AresConfig, used to report the status of each provider
+    /// that a Record is synced to.
+    #[serde(default)]
+    pub name: Option<String>,
+
     pub selector: Vec<String>,
 
+    /// Changes that ARES may make to records of the provider; defaults to
+    /// adding and removing records.
+    #[serde(default)]
+    pub policy: Policy,
+
     #[serde(flatten)]
     pub provider: ProviderConfig,
+
+    /// Providers to fail over to, in order, when the provider is
+    /// unreachable. Records are written to the first standby provider that
+    /// succeeds, while writes to the provider are retried.
+    #[serde(default)]
+    pub standby: Vec<ProviderConfig>,
+
+    /// Create the zone of a matching Selector when no zone hosts a Record,
+    /// if the provider is able to create zones.
+    #[serde(default)]
+    pub create_zones: bool,
+
+    /// Only make changes to records of the provider once they are approved
+    /// with the `ares.syntixi.io/approve` annotation of the Record.
+    #[serde(default)]
+    pub require_approval: bool,
+
+    /// TTL of matching Records that set no TTL and are not in a Zone with a
+    /// default TTL.
+    #[serde(default)]
+    pub default_ttl: Option<u32>,
+
+    /// Additional clusters that the values of matching Records are collected
+    /// from, merged with the values collected from the local cluster.
+    #[serde(default)]
+    pub clusters: Vec<ClusterRef>,
+
+    /// Limit of the rate of calls made to the provider and to each standby
+    /// provider, shared by every Record. Calls are not limited if not given.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+
+    /// Number of calls made at once to the provider and to each standby
+    /// provider, shared by every Record. Calls are not limited if not given.
+    #[serde(default)]
+    pub max_concurrent_requests: Option<usize>,
+}
+
+/// An additional cluster, connected to with a kubeconfig stored in a Secret.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all(serialize="camelCase", deserialize="camelCase"))]
+pub struct ClusterRef {
+    pub name: String,
+
+    pub secret_name: String,
+
+    /// Namespace of the Secret; defaults to the namespace of the Secret that
+    /// the configuration is loaded from.
+    #[serde(default)]
+    pub secret_namespace: Option<String>,
+
+    #[serde(default="default_kubeconfig_key")]
+    pub secret_key: String,
+}
+
+fn default_kubeconfig_key() -> String {
+    "kubeconfig".to_string()
 }
 
 impl AresConfig {
+    /// Normalize every Selector with `normalize_domain()`, so that Selectors
+    /// written in unicode match the normalized FQDNs of Records.
+    pub fn normalized(mut self) -> Result<AresConfig> {
+        self.selector = self
+            .selector
+            .iter()
+            .map(|x| normalize_domain(x))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.validate()?;
+            for provider in self.providers() {
+                provider.set_rate_limit(rate_limit);
+            }
+        }
+        if let Some(max_concurrent_requests) = self.max_concurrent_requests {
+            if max_concurrent_requests == 0 {
+                return Err(anyhow!("Invalid maxConcurrentRequests: 0"))
+            }
+            for provider in self.providers() {
+                provider.set_max_concurrent_requests(max_concurrent_requests);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Get the name of the AresConfig, falling back to the position of the
+    /// AresConfig in the configuration file.
+    pub fn display_name(&self, index: usize) -> String {
+        self.name.clone().unwrap_or_else(|| index.to_string())
+    }
+
+    /// Iterate over the provider followed by every standby provider, in
+    /// failover order.
+    pub fn providers(&self) -> impl Iterator<Item = &ProviderConfig> {
+        std::iter::once(&self.provider).chain(self.standby.iter())
+    }
+
+    /// Get the name of a provider by position in the failover chain.
+    pub fn provider_name(position: usize) -> String {
+        match position {
+            0 => "primary".to_string(),
+            _ => format!("standby-{}", position - 1),
+        }
+    }
+
+    /// Get the domain name of the first Selector matching an item, without a
+    /// leading wildcard label or dot.
+    pub fn selector_domain(&self, item: &str) -> Option<String> {
+        self.selector
+            .iter()
+            .find(|x| selector_matches(x, item))
+            .map(|x| x.trim_start_matches("*.").trim_start_matches('.').to_string())
+    }
+
     /// Iterate over Selectors and ensure that a given item matches at least
     /// one of the Selectors. The Selector syntax must be a raw string, not
     /// something like a regex pattern. To match subdomains under example.com
     /// but not example.com itself, use the selector ".example.com", then have
     /// a Selector for another AresConfig (further down the chain) that matches
     /// "example.com".
+    ///
+    /// A Selector starting with "*." is a wildcard Selector, and will match
+    /// any subdomain of the rest of the Selector, including wildcard records;
+    /// "*.example.com" matches both "www.example.com" and "*.example.com".
     pub fn matches_selector(&self, item: &str) -> bool {
-        self.selector.iter().filter(|x| item.ends_with(x.as_str())).next().is_some()
+        self.selector.iter().filter(|x| selector_matches(x, item)).next().is_some()
+    }
+}
+
+/// Domain names that ARES may manage records of, given with `--domain-filter` and
+/// `--exclude-domains`. The filter applies on top of the Selectors of every AresConfig, as a last
+/// line of defense when trying out a configuration against production credentials.
+#[derive(Clone, Debug, Default)]
+pub struct DomainFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl DomainFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> DomainFilter {
+        let normalize = |domains: &[String]| {
+            domains
+                .iter()
+                .map(|x| x.trim().trim_matches('.').to_lowercase())
+                .filter(|x| !x.is_empty())
+                .collect()
+        };
+        DomainFilter {
+            include: normalize(include),
+            exclude: normalize(exclude),
+        }
+    }
+
+    /// Check whether records of a domain name may be managed. A domain in the filter matches
+    /// itself and every subdomain; every domain name is allowed when no domains are included.
+    pub fn allows(&self, fqdn: &str) -> bool {
+        let matches = |domain: &String| {
+            fqdn == domain || fqdn.ends_with(&format!(".{}", domain))
+        };
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Parse the elements of a configuration file into a list of AresConfigs, each normalized with
+/// `AresConfig::normalized()`. Configuration files are parsed with `secret_ref::parse()`, which
+/// first replaces references to Secrets.
+pub fn from_elements(elements: Vec<serde_json::Value>) -> Result<Vec<AresConfig>> {
+    serde_json::from_value::<Vec<AresConfig>>(serde_json::Value::Array(elements))?
+        .into_iter()
+        .map(|ares| ares.normalized())
+        .collect()
+}
+
+/// Replace every `${VAR}` in the strings of an element of a configuration file by the value of
+/// the environment variable `VAR`, so that the same configuration can be used in every
+/// environment. `$${` is replaced by a literal `${`. Variables that are not set are an error.
+pub fn expand_env(value: &mut serde_json::Value) -> Result<()> {
+    match value {
+        serde_json::Value::String(string) => *string = expand_env_str(string)?,
+        serde_json::Value::Array(values) => {
+            for value in values.iter_mut() {
+                expand_env(value)?;
+            }
+        },
+        serde_json::Value::Object(fields) => {
+            for value in fields.values_mut() {
+                expand_env(value)?;
+            }
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+/// Replace every `${VAR}` in a string; see `expand_env()`. The string is left out of errors, as
+/// it may hold credentials.
+fn expand_env_str(string: &str) -> Result<String> {
+    let mut expanded = String::with_capacity(string.len());
+    let mut rest = string;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with("$${") {
+            expanded.push_str("${");
+            rest = &rest[3..];
+        } else if rest.starts_with("${") {
+            let end = rest.find('}').ok_or(anyhow!("Unterminated ${{ in configuration"))?;
+            let name = &rest[2..end];
+            let value = std::env::var(name)
+                .map_err(|_| anyhow!("Environment variable {} is not set", name))?;
+            expanded.push_str(&value);
+            rest = &rest[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Check whether a single Selector matches an item; see `AresConfig::matches_selector()`.
+pub fn selector_matches(selector: &str, item: &str) -> bool {
+    if selector.starts_with("*.") {
+        item.ends_with(&selector[1..])
+    } else {
+        item.ends_with(selector)
     }
 }
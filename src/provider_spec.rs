@@ -0,0 +1,187 @@
+//! CRD and Code for providers.syntixi.io/v1alpha1
+
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+// {{{ imports
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{anyhow, Result};
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{Api, ListParams, PatchParams},
+    Client,
+};
+use kube_derive::CustomResource;
+use kube_runtime::watcher;
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+use tracing::{debug, info, warn};
+
+use crate::controller;
+use crate::program_config::AresConfig;
+// }}}
+
+/// A provider configured as a Kubernetes object, as an alternative to an element of the
+/// configuration in the ARES Secret. Credentials are read from Secrets in the namespace of the
+/// Provider rather than written into the Provider.
+#[derive(CustomResource, Clone, Deserialize, Serialize, Debug)]
+#[kube(group="syntixi.io", version="v1alpha1", namespaced, status="ProviderResourceStatus")]
+pub struct ProviderSpec {
+    /// Fields of an AresConfig, such as `selector`, `provider` and `providerOptions`.
+    #[serde(flatten)]
+    pub config: Map<String, Value>,
+    /// Options of the provider to read from Secrets, by the name of the option.
+    #[serde(rename = "secretOptions", default)]
+    pub secret_options: BTreeMap<String, SecretOption>,
+}
+
+/// A key of a Secret in the namespace of the Provider.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct SecretOption {
+    pub name: String,
+    pub key: String,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, Default)]
+pub struct ProviderResourceStatus {
+    /// Whether the Provider was loaded into the configuration of ARES.
+    pub loaded: bool,
+    /// The error raised while loading the Provider, if it could not be loaded.
+    pub error: Option<String>,
+    /// The generation of the Provider that was last loaded.
+    #[serde(rename = "observedGeneration")]
+    pub observed_generation: Option<i64>,
+}
+
+/// Build the AresConfig of a Provider, reading the options of the provider from Secrets. The
+/// AresConfig is named after the namespace and name of the Provider unless given a name.
+async fn resolve(client: &Client, provider: &Provider) -> Result<AresConfig> {
+    let name = provider.metadata.name.clone().ok_or(anyhow!("Missing provider.meta.name"))?;
+    let namespace = provider
+        .metadata
+        .namespace
+        .clone()
+        .ok_or(anyhow!("Missing provider.meta.namespace"))?;
+    let mut config = provider.spec.config.clone();
+    let mut options = match config.remove("providerOptions") {
+        Some(Value::Object(options)) => options,
+        Some(_) => return Err(anyhow!("providerOptions must be an object")),
+        None => Map::new(),
+    };
+    let secrets: Api<Secret> = Api::namespaced(client.clone(), &namespace);
+    for (option, secret_option) in provider.spec.secret_options.iter() {
+        let secret = secrets.get(&secret_option.name).await?;
+        let value = secret
+            .data
+            .as_ref()
+            .and_then(|data| data.get(&secret_option.key))
+            .ok_or(anyhow!("Unable to get key {} from Secret {}",
+                           secret_option.key, secret_option.name))?;
+        options.insert(option.clone(), Value::String(String::from_utf8(value.0.clone())?));
+    }
+    config.insert("providerOptions".to_string(), Value::Object(options));
+    config
+        .entry("name")
+        .or_insert_with(|| Value::String(format!("{}/{}", namespace, name)));
+    let mut ares: AresConfig = serde_json::from_value(Value::Object(config))?;
+    // Kubeconfigs of additional clusters are read from the namespace of the Provider.
+    for cluster in ares.clusters.iter_mut() {
+        if cluster.secret_namespace.is_none() {
+            cluster.secret_namespace = Some(namespace.clone());
+        }
+    }
+    ares.normalized()
+}
+
+/// Record whether a Provider was loaded in the status of the Provider.
+async fn patch_status(client: &Client, provider: &Provider, result: &Result<AresConfig>) ->
+        Result<()> {
+    let name = provider.metadata.name.clone().ok_or(anyhow!("Missing provider.meta.name"))?;
+    let namespace = provider
+        .metadata
+        .namespace
+        .as_ref()
+        .ok_or(anyhow!("Missing provider.meta.namespace"))?;
+    let providers: Api<Provider> = Api::namespaced(client.clone(), namespace);
+    let patch = serde_json::json!({
+        "status": ProviderResourceStatus {
+            loaded: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            observed_generation: provider.metadata.generation,
+        },
+    });
+    providers.patch_status(&name, &PatchParams::default(), serde_json::to_vec(&patch)?).await?;
+    Ok(())
+}
+
+/// Load the AresConfig of every Provider, in order of namespace and name. Providers that can't
+/// be loaded are skipped, and the result of loading each Provider is recorded in its status.
+/// The generation of every Provider is returned by UID, so that changes can be detected with
+/// `watch_changes()`; `None` is returned instead if the Provider CRD is not installed.
+pub async fn load_all(client: &Client) ->
+        Result<(Vec<AresConfig>, Option<HashMap<String, Option<i64>>>)> {
+    let providers: Api<Provider> = Api::all(client.clone());
+    let mut list = match providers.list(&ListParams::default()).await {
+        Ok(list) => list.items,
+        Err(kube::Error::Api(e)) if e.code == 404 => {
+            debug!("Provider CRD is not installed");
+            return Ok((vec![], None))
+        },
+        Err(e) => return Err(e.into()),
+    };
+    list.sort_by_key(|provider| {
+        (provider.metadata.namespace.clone(), provider.metadata.name.clone())
+    });
+    let mut config = vec![];
+    let mut generations = HashMap::new();
+    for provider in list {
+        let result = resolve(client, &provider).await;
+        if let Err(e) = &result {
+            warn!(provider = ?provider.metadata.name, "Unable to load Provider: {}", e);
+        }
+        if let Err(e) = patch_status(client, &provider, &result).await {
+            warn!("Unable to update Provider status: {}", e);
+        }
+        if let Ok(ares) = result {
+            config.push(ares);
+        }
+        generations.insert(provider.metadata.uid.clone().unwrap_or_default(),
+                           provider.metadata.generation);
+    }
+    Ok((config, Some(generations)))
+}
+
+/// Watch over Providers, terminating when a Provider is added, changed or removed, which will
+/// cause a restart. Changes to the status of a Provider do not change its generation, and are
+/// ignored.
+pub async fn watch_changes(client: Client, generations: HashMap<String, Option<i64>>) {
+    let providers: Api<Provider> = Api::all(client);
+    let changed = |provider: &Provider| {
+        let uid = provider.metadata.uid.clone().unwrap_or_default();
+        generations.get(&uid) != Some(&provider.metadata.generation)
+    };
+    info!("Watching over Providers to detect configuration changes");
+    let mut provider_watcher = controller::delay_on_error(
+        watcher(providers, controller::watch_params())).boxed();
+    loop {
+        let found_change = match provider_watcher.try_next().await {
+            Ok(Some(watcher::Event::Applied(applied))) => changed(&applied),
+            Ok(Some(watcher::Event::Deleted(deleted))) => {
+                generations.contains_key(&deleted.metadata.uid.clone().unwrap_or_default())
+            },
+            Ok(Some(watcher::Event::Restarted(restarted))) => {
+                restarted.len() != generations.len() || restarted.iter().any(changed)
+            },
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Error watching Providers, relisting: {}", e);
+                false
+            },
+        };
+        if found_change {
+            info!("Found Provider change, terminating");
+            std::process::exit(0);
+        }
+    }
+}
@@ -31,23 +31,61 @@
 //!         email: ryan@***
 //!         apiKey: ***
 //! ```
+//!
+//! When the credentials have access to more than one account, `accountId` may be given to only
+//! look up zones in one account, so that zones with the same name in other accounts are never
+//! used.
+//!
+//! Requests to CloudFlare can be given a `timeoutSeconds`, sent through a `proxy`, and trust a
+//! `caBundle` or `caFile`, such as when behind a corporate proxy; see `HttpOptions`.
+//!
+//! Records created by ARES are given a comment naming the owner of ARES and the Record they were
+//! created for. With `recordTags: true`, they are also tagged with the same information, such as
+//! `ares-owner:default`; tags are only available for zones on paid plans.
 // }}}
 
 // {{{ imports
 use anyhow::{anyhow, Result};
-use serde::{Serialize, Deserialize};
-use serde_json::value::{Value, Index, from_value};
-use reqwest::header;
+use lazy_static::lazy_static;
+use serde::{de::{DeserializeOwned, Deserializer, IgnoredAny}, Serialize, Deserialize};
+use reqwest::{header, StatusCode};
+use sha2::{Digest, Sha256};
+use tracing::instrument;
 
-use super::util::{ProviderBackend, SubDomainName, FullDomainName, ZoneDomainName, Record};
+use super::util::{ProviderBackend, SubDomainName, FullDomainName, ZoneDomainName, Record,
+                  RecordPage, RecordType, Heritage, Change, Capabilities, pair_updates};
+use super::http::HttpOptions;
+use crate::error::ProviderError;
 use crate::reqwest_client_builder;
-use crate::xpathable::XPathable;
 
-use std::convert::{TryFrom, TryInto};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 // }}}
 
 static BASE_URL: &str = "https://api.cloudflare.com/client/v4";
 
+/// Number of DNS records requested per page; the largest page size CloudFlare allows.
+static PER_PAGE: &str = "100";
+
+/// Largest number of changes sent in one batch; the limit of CloudFlare for zones on the free
+/// plan.
+const BATCH_SIZE: usize = 200;
+
+/// Longest comment allowed on a DNS record; the limit of CloudFlare for zones on the free plan.
+const COMMENT_LENGTH: usize = 100;
+
+/// Time for which the ID of a zone is cached.
+const ZONE_ID_TTL: Duration = Duration::from_secs(3600);
+
+lazy_static! {
+    /// IDs of zones, by the credentials they were looked up with and the name of the zone, along
+    /// with when they were looked up.
+    static ref ZONE_IDS: Mutex<HashMap<(String, ZoneDomainName), (String, Instant)>> =
+        Mutex::new(HashMap::new());
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(untagged)]
 pub enum CloudFlareConfig {
@@ -63,6 +101,9 @@ pub enum CloudFlareConfig {
     /// - Zone / Zone / Read
     /// - Zone / DNS / Edit
     ///
+    /// Creating zones with `createZones` additionally requires the
+    /// Zone / Zone / Edit permission for the account.
+    ///
     /// To limit your CloudFlare token to a specific zone, choose a zone from
     /// the Zone Resources option, which is already set up using the template.
     ///
@@ -72,6 +113,16 @@ pub enum CloudFlareConfig {
     Token {
         #[serde(rename="apiToken")]
         api_token: String,
+        /// The ID of the account to look up zones in, for tokens with access to more than one
+        /// account. Zones from every account available to the token are used if not given.
+        #[serde(rename="accountId", default, skip_serializing_if="Option::is_none")]
+        account_id: Option<String>,
+        /// Whether to tag records with the owner of ARES and the Record they were created for.
+        /// Tags are only available for zones on paid plans.
+        #[serde(rename="recordTags", default)]
+        record_tags: bool,
+        #[serde(flatten)]
+        http: HttpOptions,
     },
     /// A CloudFlare API Key. Unlike an API Token, this key - when combined
     /// with the email address of the account - is given the full permissions
@@ -89,73 +140,549 @@ pub enum CloudFlareConfig {
         email: String,
         #[serde(rename="apiKey")]
         api_key: String,
+        /// The ID of the account to look up zones in, for users that are members of more than
+        /// one account. Zones from every account of the user are used if not given.
+        #[serde(rename="accountId", default, skip_serializing_if="Option::is_none")]
+        account_id: Option<String>,
+        /// Whether to tag records with the owner of ARES and the Record they were created for.
+        /// Tags are only available for zones on paid plans.
+        #[serde(rename="recordTags", default)]
+        record_tags: bool,
+        #[serde(flatten)]
+        http: HttpOptions,
     },
 }
 
 macro_rules! client_builder {
-    (auth::bearer(auth_token => $token:expr)) => ({
+    (auth::bearer(auth_token => $token:expr), $http:expr) => ({
         let mut headers = header::HeaderMap::new();
         headers.insert(header::AUTHORIZATION,
                        header::HeaderValue::from_str(format!("Bearer {}", $token).as_str())?);
-        reqwest_client_builder!().default_headers(headers)
+        reqwest_client_builder!($http).default_headers(headers)
     });
-    (auth::key(auth_email => $email:expr, auth_key => $key:expr)) => ({
+    (auth::key(auth_email => $email:expr, auth_key => $key:expr), $http:expr) => ({
         let mut headers = header::HeaderMap::new();
         let x_auth_email = header::HeaderName::from_static("x-auth-email");
         let x_auth_key = header::HeaderName::from_static("x-auth-key");
         headers.insert(x_auth_email, header::HeaderValue::from_str($email.as_str())?);
         headers.insert(x_auth_key, header::HeaderValue::from_str($key.as_str())?);
-        reqwest_client_builder!().default_headers(headers)
+        reqwest_client_builder!($http).default_headers(headers)
     });
 }
 
+// {{{ API models
+/// The envelope of every response of the CloudFlare API.
+#[derive(Deserialize, Debug)]
+struct ApiResponse<T> {
+    success: bool,
+    #[serde(default)]
+    errors: Vec<ApiError>,
+    result: Option<T>,
+    result_info: Option<ResultInfo>,
+}
+
+/// An error reported by the CloudFlare API, along with the errors that caused it.
+#[derive(Deserialize, Debug)]
+struct ApiError {
+    code: Option<u64>,
+    message: String,
+    #[serde(default)]
+    error_chain: Vec<ApiError>,
+}
+
+/// The page of a listing returned by the CloudFlare API.
+#[derive(Deserialize, Debug)]
+struct ResultInfo {
+    page: u64,
+    total_pages: u64,
+}
+
+/// A zone of CloudFlare.
+#[derive(Deserialize, Debug)]
+struct ApiZone {
+    id: String,
+    name: String,
+    #[serde(default)]
+    name_servers: Vec<FullDomainName>,
+}
+
+/// An account of CloudFlare.
+#[derive(Deserialize, Debug)]
+struct ApiAccount {
+    id: String,
+}
+
+/// A DNS record of CloudFlare.
+#[derive(Deserialize, Debug)]
+struct ApiRecord {
+    id: String,
+    zone_name: ZoneDomainName,
+    name: FullDomainName,
+    /// The type of the record; `None` for types that ARES does not know, such as CAA or HTTPS.
+    #[serde(rename="type", deserialize_with="deserialize_record_type")]
+    record_type: Option<RecordType>,
+    content: String,
+    ttl: u64,
+    #[serde(default)]
+    proxiable: bool,
+    #[serde(default)]
+    proxied: bool,
+}
+
+/// Deserialize the type of a DNS record of CloudFlare, leniently, so that records of types that
+/// ARES does not know don't keep the records of a zone from being listed.
+fn deserialize_record_type<'de, D>(deserializer: D) ->
+        std::result::Result<Option<RecordType>, D::Error> where D: Deserializer<'de> {
+    let record_type = String::deserialize(deserializer)?;
+    Ok(serde_json::from_value(serde_json::Value::String(record_type)).ok())
+}
+
+/// A DNS record to create at CloudFlare.
+#[derive(Serialize, Debug)]
+struct NewApiRecord<'a> {
+    #[serde(rename="type")]
+    record_type: &'a RecordType,
+    name: &'a str,
+    content: &'a str,
+    ttl: u64,
+    #[serde(skip_serializing_if="Option::is_none")]
+    proxied: Option<bool>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    data: Option<SshfpData<'a>>,
+    #[serde(skip_serializing_if="Option::is_none")]
+    comment: Option<String>,
+    #[serde(skip_serializing_if="Vec::is_empty")]
+    tags: Vec<String>,
+}
+
+/// The ID of a DNS record of CloudFlare, as given to delete the record in a batch.
+#[derive(Serialize, Debug)]
+struct ApiRecordId {
+    id: String,
+}
+
+/// An update of a DNS record of CloudFlare, as given to update the record in a batch. Fields
+/// left out of the update, such as the proxied state when not given, are kept as they are.
+#[derive(Serialize, Debug)]
+struct ApiRecordPatch<'a> {
+    id: String,
+    #[serde(flatten)]
+    record: NewApiRecord<'a>,
+}
+
+/// A batch of changes to the DNS records of a zone. CloudFlare makes every deletion of a batch
+/// before any update, and every update before any creation, and makes either every change of
+/// the batch or none of them.
+#[derive(Serialize, Debug)]
+struct ApiBatch<'a> {
+    deletes: &'a [ApiRecordId],
+    patches: &'a [ApiRecordPatch<'a>],
+    posts: &'a [NewApiRecord<'a>],
+}
+
+/// The fields of an SSHFP record, which CloudFlare takes as structured data rather than as
+/// content.
+#[derive(Serialize, Debug)]
+struct SshfpData<'a> {
+    algorithm: u8,
+    #[serde(rename="type")]
+    fingerprint_type: u8,
+    fingerprint: &'a str,
+}
+
+impl<T> ApiResponse<T> {
+    /// Get the error reported by CloudFlare. The innermost cause of the first error is reported
+    /// when given, as it is the most specific. Errors with a code known to be caused by missing
+    /// objects, invalid credentials or conflicting records are returned as a ProviderError.
+    fn error(&self) -> anyhow::Error {
+        let error = match self.errors.first() {
+            Some(error) => error.error_chain.first().unwrap_or(error),
+            None => return anyhow!("CloudFlare reported an error without a message"),
+        };
+        let code = match error.code {
+            Some(code) => code,
+            None => return anyhow!("{}", error.message),
+        };
+        let message = format!("{} (code {})", error.message, code);
+        match code {
+            7003 | 81044 => ProviderError::NotFound(message).into(),
+            9103 | 9106 | 9109 | 10000 => ProviderError::AuthFailed(message).into(),
+            81053 | 81057 | 81058 => ProviderError::Conflict(message).into(),
+            _ => anyhow!(message),
+        }
+    }
+
+    /// Check that an API call was successful, returning the error reported by CloudFlare
+    /// otherwise.
+    fn check(&self) -> Result<()> {
+        if self.success {
+            Ok(())
+        } else {
+            Err(self.error())
+        }
+    }
+
+    /// Get the result of a successful API call, returning the error reported by CloudFlare
+    /// otherwise.
+    fn into_result(self) -> Result<T> {
+        self.check()?;
+        self.result.ok_or(anyhow!("Missing result in CloudFlare response"))
+    }
+}
+
+impl<'a> TryFrom<&'a Record> for NewApiRecord<'a> {
+    type Error = anyhow::Error;
+
+    fn try_from(record: &'a Record) -> Result<NewApiRecord<'a>> {
+        let data = match record.record_type {
+            // CloudFlare does not accept SSHFP content directly; the fields of the record have
+            // to be passed as structured data instead.
+            RecordType::SSHFP => {
+                let fields: Vec<&str> = record.value.split_whitespace().collect();
+                if let [algorithm, fingerprint_type, fingerprint] = fields[..] {
+                    Some(SshfpData {
+                        algorithm: algorithm.parse()?,
+                        fingerprint_type: fingerprint_type.parse()?,
+                        fingerprint: fingerprint,
+                    })
+                } else {
+                    return Err(anyhow!("Invalid SSHFP record value: {}", record.value));
+                }
+            },
+            _ => None,
+        };
+        Ok(NewApiRecord {
+            record_type: &record.record_type,
+            name: &record.fqdn,
+            content: &record.value,
+            ttl: record.ttl,
+            proxied: record.proxied,
+            data: data,
+            comment: record.heritage.as_ref().map(heritage_comment),
+            tags: vec![],
+        })
+    }
+}
+
+/// Describe the ownership of a record for the comment of the record, such as "Managed by ARES
+/// (owner: default, record: default/www)". The comment is cut short when longer than CloudFlare
+/// allows.
+fn heritage_comment(heritage: &Heritage) -> String {
+    let mut details = vec![];
+    if let Some(owner) = &heritage.owner {
+        details.push(format!("owner: {}", owner));
+    }
+    if let Some(resource) = &heritage.resource {
+        details.push(format!("record: {}", resource));
+    }
+    let comment = if details.is_empty() {
+        "Managed by ARES".to_string()
+    } else {
+        format!("Managed by ARES ({})", details.join(", "))
+    };
+    comment.chars().take(COMMENT_LENGTH).collect()
+}
+
+/// Get the tags of a record describing its ownership, such as "ares-owner:default".
+fn heritage_tags(heritage: &Heritage) -> Vec<String> {
+    let fields = [("ares-owner", &heritage.owner), ("ares-cluster", &heritage.cluster),
+                  ("ares-record", &heritage.resource)];
+    fields
+        .iter()
+        .filter_map(|(name, value)| value.as_ref().map(|value| format!("{}:{}", name, value)))
+        .collect()
+}
+
+impl ApiRecord {
+    /// Convert the record to a Record. Records that can't be proxied are reported as not
+    /// proxied, which is left out so that they never differ from the proxied state of their
+    /// Record. Records of types that ARES does not know are skipped.
+    fn into_record(self) -> Option<Record> {
+        let mut record = Record::new(self.zone_name, self.name, self.ttl, self.record_type?,
+                                     self.content);
+        if self.proxiable {
+            record.proxied = Some(self.proxied);
+        }
+        Some(record)
+    }
+}
+// }}}
+
 impl CloudFlareConfig {
-    /// Get a Zone ID for a given domain name.
+    /// Get a key identifying the credentials of the configuration, without holding the
+    /// credentials themselves, so that zones are cached separately for every set of credentials.
+    fn cache_key(&self) -> String {
+        let credentials = match self {
+            CloudFlareConfig::Token { api_token, .. } => api_token.clone(),
+            CloudFlareConfig::EmailKey { email, api_key, .. } => format!("{}:{}", email, api_key),
+        };
+        // the same zone may exist in more than one account available to the credentials
+        let credentials = match self.account_id() {
+            Some(account_id) => format!("{}:{}", credentials, account_id),
+            None => credentials,
+        };
+        Sha256::digest(credentials.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Get the ID of the account zones are looked up in, if any.
+    fn account_id(&self) -> Option<&str> {
+        match self {
+            CloudFlareConfig::Token { account_id, .. } => account_id.as_deref(),
+            CloudFlareConfig::EmailKey { account_id, .. } => account_id.as_deref(),
+        }
+    }
+
+    /// Check whether records are tagged with their ownership.
+    fn record_tags(&self) -> bool {
+        match self {
+            CloudFlareConfig::Token { record_tags, .. } => *record_tags,
+            CloudFlareConfig::EmailKey { record_tags, .. } => *record_tags,
+        }
+    }
+
+    /// Build the DNS record to create or update a record with, tagged with the ownership of the
+    /// record if enabled.
+    fn new_record<'a>(&self, record: &'a Record) -> Result<NewApiRecord<'a>> {
+        let mut new_record = NewApiRecord::try_from(record)?;
+        if let (true, Some(heritage)) = (self.record_tags(), &record.heritage) {
+            new_record.tags = heritage_tags(heritage);
+        }
+        Ok(new_record)
+    }
+
+    /// Get the query to look up a zone by name with, limited to the account of the configuration
+    /// if one is given.
+    fn zone_query<'a>(&'a self, name: &'a str) -> Vec<(&'a str, &'a str)> {
+        let mut query = vec![("name", name)];
+        if let Some(account_id) = self.account_id() {
+            query.push(("account.id", account_id));
+        }
+        query
+    }
+
+    /// Get a Zone ID for a given domain name. The ID is cached for `ZONE_ID_TTL`.
     async fn get_zone(&self, c: &reqwest::Client, zone: &ZoneDomainName) -> Result<String> {
-        let result: Value = c.get(format!("{}/zones?name={}", BASE_URL, zone).as_str())
-            .send().await?
-            .json().await?;
-        let zone_id = result
-            .xpath("/result/0/id")?
-            .as_str()
-            .ok_or(anyhow!("Unable to convert zone ID to string"))?;
-        Ok(zone_id.to_string())
+        let key = (self.cache_key(), zone.clone());
+        if let Some((zone_id, cached_at)) = ZONE_IDS.lock().unwrap().get(&key) {
+            if cached_at.elapsed() < ZONE_ID_TTL {
+                return Ok(zone_id.clone())
+            }
+        }
+        let response = send(c.get(format!("{}/zones", BASE_URL).as_str())
+            .query(&self.zone_query(zone)))
+            .await?;
+        let zones: Vec<ApiZone> = parse(response).await?.into_result()?;
+        let zone_id = zones
+            .into_iter()
+            .next()
+            .ok_or_else(|| ProviderError::NotFound(format!("Unable to find zone: {}", zone)))?
+            .id;
+        ZONE_IDS.lock().unwrap().insert(key, (zone_id.clone(), Instant::now()));
+        Ok(zone_id)
+    }
+
+    /// Forget the cached ID of a zone when a request for the zone was answered with 404, as the
+    /// zone may have been deleted, or created again with a new ID.
+    fn check_zone_response(&self, zone: &ZoneDomainName, response: &reqwest::Response) {
+        if response.status() == StatusCode::NOT_FOUND {
+            ZONE_IDS.lock().unwrap().remove(&(self.cache_key(), zone.clone()));
+        }
+    }
+
+    /// List the DNS records of a zone matching a query, following every page of the results.
+    async fn list_records(&self, c: &reqwest::Client, zone: &ZoneDomainName,
+                          query: &[(&str, &str)]) -> Result<Vec<ApiRecord>> {
+        let mut records = vec![];
+        let mut page: u64 = 1;
+        loop {
+            let (listing, last_page) = self.list_page(c, zone, query, page).await?;
+            records.extend(listing);
+            if last_page {
+                break
+            }
+            page += 1;
+        }
+        Ok(records)
+    }
+
+    /// List a page of the DNS records of a zone matching a query, along with whether it is the
+    /// last page.
+    async fn list_page(&self, c: &reqwest::Client, zone: &ZoneDomainName,
+                       query: &[(&str, &str)], page: u64) -> Result<(Vec<ApiRecord>, bool)> {
+        let zone_id = self.get_zone(c, zone).await?;
+        let url = format!("{}/zones/{}/dns_records", BASE_URL, zone_id);
+        let page_number = page.to_string();
+        let response = send(c.get(url.as_str())
+            .query(query)
+            .query(&[("page", page_number.as_str()), ("per_page", PER_PAGE)]))
+            .await?;
+        self.check_zone_response(zone, &response);
+        let listing: ApiResponse<Vec<ApiRecord>> = parse(response).await?;
+        listing.check()?;
+        let last_page = listing
+            .result_info
+            .as_ref()
+            .map(|info| info.page >= info.total_pages)
+            .unwrap_or(true);
+        Ok((listing.result.unwrap_or_default(), last_page))
+    }
+
+    /// Find the IDs of DNS records, as CloudFlare changes records by ID. The records of each
+    /// domain name are listed once, then matched by type and value.
+    async fn find_record_ids(&self, c: &reqwest::Client, zone: &ZoneDomainName,
+                             records: &[&Record]) -> Result<Vec<ApiRecordId>> {
+        let mut listings: HashMap<&str, Vec<ApiRecord>> = HashMap::new();
+        let mut record_ids = Vec::with_capacity(records.len());
+        for record in records {
+            if !listings.contains_key(record.fqdn.as_str()) {
+                let listing = self
+                    .list_records(c, zone, &[("name", record.fqdn.as_str())])
+                    .await?;
+                listings.insert(record.fqdn.as_str(), listing);
+            }
+            let record_id = listings[record.fqdn.as_str()]
+                .iter()
+                .find(|x| {
+                    x.record_type.as_ref() == Some(&record.record_type) && x.content == record.value
+                })
+                .ok_or(anyhow!("Unable to find {:?} record {} with value {}",
+                               record.record_type, record.fqdn, record.value))?
+                .id
+                .clone();
+            record_ids.push(ApiRecordId { id: record_id });
+        }
+        Ok(record_ids)
     }
 
     /// Create a Reqwest client using the cloudflare::client_builder!().
     fn get_client(&self) -> Result<reqwest::Client> {
         match self {
-            CloudFlareConfig::Token { api_token } => {
-                Ok(client_builder!(auth::bearer(auth_token => api_token)).build()?)
+            CloudFlareConfig::Token { api_token, http, .. } => {
+                Ok(client_builder!(auth::bearer(auth_token => api_token), http).build()?)
             },
-            CloudFlareConfig::EmailKey { email, api_key } => {
-                Ok(client_builder!(auth::key(auth_email => email, auth_key => api_key)).build()?)
+            CloudFlareConfig::EmailKey { email, api_key, http, .. } => {
+                let builder = client_builder!(auth::key(auth_email => email, auth_key => api_key),
+                                              http);
+                Ok(builder.build()?)
             }
         }
     }
 }
 
+/// Send a request to CloudFlare. Server errors and rate limits are returned as a ProviderError,
+/// so that the call is retried by the Retrying wrapper, which waits at least as long as given by
+/// the Retry-After header.
+async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+    check_available(request.send().await?)
+}
+
+/// Get the time to wait before calling CloudFlare again given by the Retry-After header of a
+/// response, if any.
+fn retry_after(response: &reqwest::Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+/// Check that CloudFlare answered a request, rather than failing with a server error or by
+/// being rate limited.
+fn check_available(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    let message = format!("CloudFlare responded with {}", status);
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(ProviderError::RateLimited {
+            message: message,
+            retry_after: retry_after(&response),
+        }.into())
+    }
+    if status.is_server_error() {
+        return Err(ProviderError::Transient {
+            status: status.as_u16(),
+            message: message,
+        }.into())
+    }
+    Ok(response)
+}
+
+/// Parse the response of an API call, naming the HTTP status of the response if the response is
+/// not the expected shape.
+async fn parse<T: DeserializeOwned>(response: reqwest::Response) -> Result<ApiResponse<T>> {
+    let status = response.status();
+    response
+        .json()
+        .await
+        .map_err(|e| anyhow!("Unable to parse CloudFlare response ({}): {}", status, e))
+}
+
 #[async_trait::async_trait]
 impl ProviderBackend for CloudFlareConfig {
+    fn kind(&self) -> &'static str {
+        "cloudflare"
+    }
+
+    fn validate_options(&self) -> Result<()> {
+        match self {
+            CloudFlareConfig::Token { api_token, .. } if api_token.trim().is_empty() => {
+                Err(anyhow!("Empty apiToken"))
+            },
+            CloudFlareConfig::EmailKey { email, .. } if !email.contains('@') => {
+                Err(anyhow!("Invalid email: {}", email))
+            },
+            CloudFlareConfig::EmailKey { api_key, .. } if api_key.trim().is_empty() => {
+                Err(anyhow!("Empty apiKey"))
+            },
+            CloudFlareConfig::Token { http, .. } | CloudFlareConfig::EmailKey { http, .. } => {
+                http.validate()
+            },
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            record_types: Some(vec![
+                RecordType::A, RecordType::AAAA, RecordType::CNAME, RecordType::MX,
+                RecordType::NS, RecordType::PTR, RecordType::SRV, RecordType::SSHFP,
+                RecordType::TXT, RecordType::DNSKEY, RecordType::DS,
+            ]),
+            min_ttl: Some(60),
+            max_ttl: Some(86400),
+            automatic_ttl: Some(1),
+            multi_value: true,
+            updates: true,
+            batch_size: Some(BATCH_SIZE),
+        }
+    }
+
+    #[instrument(skip(self))]
     async fn get_zone(&self, domain: &FullDomainName) -> Result<ZoneDomainName> {
         // bubble up for every segment of the domain name
         // eventually we should hit a valid record
-        let mut index = 0;
+        // a wildcard label can never be a zone, so it is skipped
+        let mut index = if domain.starts_with("*.") { 2 } else { 0 };
         let len = domain.len();
         let client = self.get_client()?;
         while index != len {
             let substr = &domain[index..len];
-            let result: Value = client.get(format!("{}/zones?name={}", BASE_URL, substr).as_str())
-                .send().await?
-                .json().await?;
-            // check for error
-            if result.xpath("/success")?.as_bool()
-                     .ok_or(anyhow!("Unable to convert success to bool"))? {
-                return Ok(result
-                    .xpath("/result/0/name")?
-                    .as_str()
-                    .ok_or(anyhow!("Unable to convert result.name to str"))?
-                    .to_string());
+            let response = send(client.get(format!("{}/zones", BASE_URL).as_str())
+                .query(&self.zone_query(substr)))
+                .await?;
+            // check for error, and for a successful query with no zones
+            let zones: ApiResponse<Vec<ApiZone>> = parse(response).await?;
+            match zones.into_result() {
+                Ok(zones) => if let Some(zone) = zones.into_iter().next() {
+                    return Ok(zone.name)
+                },
+                // credentials refused for one domain name are refused for every other
+                Err(e) if matches!(ProviderError::find(&e), Some(ProviderError::AuthFailed(_))) => {
+                    return Err(e)
+                },
+                Err(_) => {},
             }
             if let Some(offset) = substr.find(".") {
                 // increment offset to capture the period
@@ -164,107 +691,158 @@ impl ProviderBackend for CloudFlareConfig {
                 break
             }
         }
-        Err(anyhow!("Unable to find DNS Zone for: {}", domain))
+        Err(ProviderError::NotFound(format!("Unable to find DNS Zone for: {}", domain)).into())
     }
 
+    #[instrument(skip(self))]
     async fn get_records(&self, domain: &ZoneDomainName, name: &SubDomainName) ->
             Result<Vec<Record>> {
         let client = self.get_client()?;
-        // Get Zone ID
-        let result: Value = client.get(format!("{}/zones?name={}", BASE_URL, domain).as_str())
-            .send().await?
-            .json().await?;
-        let zone_id = result
-            .xpath("/result/0/id")?
-            .as_str()
-            .ok_or(anyhow!("Unable to convert zone ID to string"))?;
-
-        // Get Domain Name from Zone ID
-        let result: Value = client.get(format!("{}/zones/{}/dns_records?name={}",
-                                               BASE_URL, zone_id, name).as_str())
-            .send().await?
-            .json().await?;
-
-        let record_count = result
-            .xpath("/result_info/count")?
-            .as_u64()
-            .ok_or(anyhow!("Unable to convert result_info.count to u64"))?;
-
-        let mut records: Vec<Record> = Vec::with_capacity(record_count as usize);
-        // TODO: implement pagination
-
-        for record in result
-                .xpath("/result")?
-                .as_array()
-                .ok_or(anyhow!("Unable to convert result to array"))? {
-            // try xpath impl
-            records.push(Record::new(
-                record
-                    .xpath("/zone_name")?
-                    .as_str()
-                    .ok_or(anyhow!("Unable to convert record[].zone_name to str"))?.to_string(),
-                record
-                    .xpath("/name")?
-                    .as_str()
-                    .ok_or(anyhow!("Unable to convert record[].name to str"))?.to_string(),
-                record
-                    .xpath("/ttl")?
-                    .as_u64()
-                    .ok_or(anyhow!("Unable to convert result to u64"))?,
-                from_value(record.xpath("/type")?.clone())?,
-                record
-                    .xpath("/content")?
-                    .as_str()
-                    .ok_or(anyhow!("Unable to convert record[].content to str"))?.into()
-                    ));
-        }
+        // the name is passed as a query parameter so that wildcard names are
+        // correctly encoded
+        let records = self.list_records(&client, domain, &[("name", name)]).await?;
+        Ok(records.into_iter().filter_map(ApiRecord::into_record).collect())
+    }
 
-        Ok(records)
+    #[instrument(skip(self))]
+    async fn get_records_page(&self, domain: &ZoneDomainName, page: Option<&str>) ->
+            Result<RecordPage> {
+        let client = self.get_client()?;
+        let page: u64 = match page {
+            Some(page) => page.parse().map_err(|_| anyhow!("Invalid page: {}", page))?,
+            None => 1,
+        };
+        let (listing, last_page) = self.list_page(&client, domain, &[], page).await?;
+        Ok(RecordPage {
+            records: listing.into_iter().filter_map(ApiRecord::into_record).collect(),
+            next: if last_page { None } else { Some((page + 1).to_string()) },
+        })
     }
 
-    async fn get_all_records(&self, domain: &ZoneDomainName) ->
-            Result<std::collections::HashMap<SubDomainName, Vec<Record>>> {
-        // pass
-        unimplemented!();
+    #[instrument(skip(self))]
+    async fn create_zone(&self, zone: &ZoneDomainName) -> Result<()> {
+        let client = self.get_client()?;
+        // Zones are created in the account of the configuration, or otherwise in the first
+        // account available to the credentials.
+        let account_id = match self.account_id() {
+            Some(account_id) => account_id.to_string(),
+            None => {
+                let response = send(client.get(format!("{}/accounts", BASE_URL).as_str()))
+                    .await?;
+                let accounts: Vec<ApiAccount> = parse(response).await?.into_result()?;
+                accounts
+                    .into_iter()
+                    .next()
+                    .ok_or(anyhow!("No account available to create zone: {}", zone))?
+                    .id
+            },
+        };
+        let response = send(client.post(format!("{}/zones", BASE_URL).as_str())
+            .json(&serde_json::json!({
+                "name": zone,
+                "account": {
+                    "id": account_id,
+                },
+                "jump_start": false,
+            })))
+            .await?;
+        parse::<IgnoredAny>(response).await?.check()
     }
 
+    #[instrument(skip(self))]
+    async fn get_nameservers(&self, zone: &ZoneDomainName) -> Result<Vec<FullDomainName>> {
+        let client = self.get_client()?;
+        let response = send(client.get(format!("{}/zones", BASE_URL).as_str())
+            .query(&self.zone_query(zone)))
+            .await?;
+        let zones: Vec<ApiZone> = parse(response).await?.into_result()?;
+        zones
+            .into_iter()
+            .next()
+            .map(|zone| zone.name_servers)
+            .ok_or_else(|| ProviderError::NotFound(format!("Unable to find zone: {}", zone)).into())
+    }
+
+    #[instrument(skip(self))]
     async fn _add_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
-        // pass
         let client = self.get_client()?;
         let zone_id = self.get_zone(&client, domain).await?;
         let url = format!("{}/zones/{}/dns_records", BASE_URL, zone_id);
-        let mut data = std::collections::HashMap::<&str, serde_json::Value>::new();
-        data.insert("type", serde_json::to_value(&record.record_type)?);
-        data.insert("name", serde_json::to_value(&record.fqdn)?);
-        data.insert("content", serde_json::to_value(&record.value)?);
-        data.insert("ttl", serde_json::to_value(record.ttl)?);
-        let result: Value = client.post(url.as_str())
-            .json(&data)
-            .send()
-            .await?
-            .json()
+        let response = send(client.post(url.as_str())
+            .json(&self.new_record(record)?))
             .await?;
-        if result.xpath("/success")?.as_bool()
-                 .ok_or(anyhow!("Unable to convert success to bool"))? {
-            Ok(())
-        } else {
-            if let Ok(error_object) = result.xpath("/errors/0/error_chain/0/message") {
-                let error_str = error_object
-                    .as_str()
-                    .ok_or(anyhow!("Unable to convert errors/0/error_chain/0/message to str"))?;
-                Err(anyhow!("{}", error_str))
-            } else {
-                let error_str = result
-                    .xpath("/errors/0/message")?
-                    .as_str()
-                    .ok_or(anyhow!("Unable to convert errors/0/message to str"))?;
-                Err(anyhow!("{}", error_str))
-            }
-        }
+        self.check_zone_response(domain, &response);
+        parse::<IgnoredAny>(response).await?.check()
     }
 
+    #[instrument(skip(self))]
     async fn _delete_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
-        // pass
-        unimplemented!();
+        let client = self.get_client()?;
+        let zone_id = self.get_zone(&client, domain).await?;
+        let record_id = self
+            .find_record_ids(&client, domain, &[record])
+            .await?
+            .remove(0)
+            .id;
+        let response = send(client.delete(format!("{}/zones/{}/dns_records/{}",
+                                                  BASE_URL, zone_id, record_id).as_str()))
+            .await?;
+        self.check_zone_response(domain, &response);
+        parse::<IgnoredAny>(response).await?.check()
+    }
+
+    #[instrument(skip(self))]
+    async fn _update_record(&self, domain: &ZoneDomainName, record: &Record,
+                            update: &Record) -> Result<()> {
+        let client = self.get_client()?;
+        let zone_id = self.get_zone(&client, domain).await?;
+        let record_id = self
+            .find_record_ids(&client, domain, &[record])
+            .await?
+            .remove(0)
+            .id;
+        let response = send(client.patch(format!("{}/zones/{}/dns_records/{}",
+                                                 BASE_URL, zone_id, record_id).as_str())
+            .json(&self.new_record(update)?))
+            .await?;
+        self.check_zone_response(domain, &response);
+        parse::<IgnoredAny>(response).await?.check()
+    }
+
+    #[instrument(skip(self))]
+    async fn _apply_batch(&self, domain: &ZoneDomainName, changes: Vec<Change>) -> Result<()> {
+        if changes.is_empty() {
+            return Ok(())
+        }
+        let client = self.get_client()?;
+        let zone_id = self.get_zone(&client, domain).await?;
+        let url = format!("{}/zones/{}/dns_records/batch", BASE_URL, zone_id);
+        let (updates, deletes, adds) = pair_updates(&changes);
+        let delete_ids = self.find_record_ids(&client, domain, &deletes).await?;
+        let updated: Vec<&Record> = updates.iter().map(|(record, _)| *record).collect();
+        let update_ids = self.find_record_ids(&client, domain, &updated).await?;
+        let record_patches = update_ids
+            .into_iter()
+            .zip(updates.iter())
+            .map(|(record_id, (_, update))| Ok(ApiRecordPatch {
+                id: record_id.id,
+                record: self.new_record(update)?,
+            }))
+            .collect::<Result<Vec<_>>>()?;
+        let new_records = adds
+            .into_iter()
+            .map(|record| self.new_record(record))
+            .collect::<Result<Vec<_>>>()?;
+        // CloudFlare makes every deletion of a batch first, then every update and creation
+        let batch = ApiBatch {
+            deletes: &delete_ids[..],
+            patches: &record_patches[..],
+            posts: &new_records[..],
+        };
+        let response = send(client.post(url.as_str())
+            .json(&batch))
+            .await?;
+        self.check_zone_response(domain, &response);
+        parse::<IgnoredAny>(response).await?.check()
     }
 }
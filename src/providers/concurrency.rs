@@ -0,0 +1,112 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! A wrapper around a ProviderBackend limiting the number of calls made to the provider at once
+//! with a semaphore, configured with the `maxConcurrentRequests` of an AresConfig, for providers
+//! that throttle parallel writes. Calls made while every permit is taken wait until a call
+//! finishes.
+
+// {{{ imports
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use tokio::sync::Semaphore;
+
+use super::rate_limit::RateLimit;
+use super::util::{Capabilities, Change, FullDomainName, ProviderBackend, Record, RecordPage,
+                  ZoneDomainName};
+// }}}
+
+/// A ProviderBackend whose calls to the provider are limited once a number of calls is set with
+/// `set_max_concurrent_requests()`. Clones share the same permits, so that every copy of a
+/// provider is limited together. A permit is held for the length of a single call or batch, so
+/// the changes of a sync made one at a time by `apply_changes()` let other calls in between.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct ConcurrencyLimited<P> {
+    inner: P,
+    #[serde(skip)]
+    semaphore: Arc<Mutex<Option<Arc<Semaphore>>>>,
+}
+
+impl<P: ProviderBackend> ConcurrencyLimited<P> {
+    /// Make a call to the provider once a permit is available, if the number of calls is
+    /// limited.
+    async fn limit<T>(&self, call: impl Future<Output = Result<T>>) -> Result<T> {
+        let semaphore = self.semaphore.lock().unwrap().clone();
+        match semaphore {
+            Some(semaphore) => {
+                let _permit = semaphore.acquire().await;
+                call.await
+            },
+            None => call.await,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: ProviderBackend> ProviderBackend for ConcurrencyLimited<P> {
+    fn kind(&self) -> &'static str {
+        self.inner.kind()
+    }
+
+    fn validate_options(&self) -> Result<()> {
+        self.inner.validate_options()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_rate_limit(&self, rate_limit: &RateLimit) {
+        self.inner.set_rate_limit(rate_limit)
+    }
+
+    fn set_max_concurrent_requests(&self, max_concurrent_requests: usize) {
+        *self.semaphore.lock().unwrap() = Some(Arc::new(Semaphore::new(max_concurrent_requests)));
+    }
+
+    fn skips_changes(&self) -> bool {
+        self.inner.skips_changes()
+    }
+
+    async fn get_zone(&self, domain: &FullDomainName) -> Result<ZoneDomainName> {
+        self.limit(self.inner.get_zone(domain)).await
+    }
+
+    async fn create_zone(&self, zone: &ZoneDomainName) -> Result<()> {
+        self.limit(self.inner.create_zone(zone)).await
+    }
+
+    async fn get_nameservers(&self, zone: &ZoneDomainName) -> Result<Vec<FullDomainName>> {
+        self.limit(self.inner.get_nameservers(zone)).await
+    }
+
+    async fn get_records(&self, domain: &ZoneDomainName, name: &FullDomainName) ->
+            Result<Vec<Record>> {
+        self.limit(self.inner.get_records(domain, name)).await
+    }
+
+    async fn get_records_page(&self, domain: &ZoneDomainName, page: Option<&str>) ->
+            Result<RecordPage> {
+        self.limit(self.inner.get_records_page(domain, page)).await
+    }
+
+    async fn _add_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
+        self.limit(self.inner._add_record(domain, record)).await
+    }
+
+    async fn _delete_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
+        self.limit(self.inner._delete_record(domain, record)).await
+    }
+
+    async fn _update_record(&self, domain: &ZoneDomainName, record: &Record,
+                            update: &Record) -> Result<()> {
+        self.limit(self.inner._update_record(domain, record, update)).await
+    }
+
+    async fn _apply_batch(&self, domain: &ZoneDomainName, changes: Vec<Change>) -> Result<()> {
+        self.limit(self.inner._apply_batch(domain, changes)).await
+    }
+}
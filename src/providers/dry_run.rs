@@ -0,0 +1,152 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! A wrapper around a ProviderBackend skipping every call that would change the records of the
+//! provider when ARES runs with `--dry-run` or `ares plan`, and for providers that never make
+//! changes, such as the noop provider. Skipped calls are logged along with the records they would
+//! have changed, and counted as metrics labeled by the kind of the provider and the operation,
+//! while calls reading from the provider are made as usual.
+
+// {{{ imports
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use tracing::info;
+
+use super::util::{Capabilities, Change, ChangeKind, FullDomainName, ProviderBackend, Record,
+                  RecordPage, ZoneDomainName};
+use super::rate_limit::RateLimit;
+use crate::metrics::DRY_RUN_OPERATIONS;
+// }}}
+
+/// Whether calls changing the records of providers are skipped, for every provider.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Skip every call changing the records of a provider from now on.
+pub fn enable() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Check whether calls changing the records of providers are skipped.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// A ProviderBackend whose calls changing the records of the provider are skipped when dry runs
+/// are enabled, or when the provider skips changes. Only the methods making calls to the
+/// provider are wrapped; the default methods of ProviderBackend, such as `apply_changes()`, are
+/// built on them, and are skipped through them.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct DryRun<P> {
+    inner: P,
+}
+
+impl<P: ProviderBackend> DryRun<P> {
+    /// Check whether calls changing the records of the provider are skipped.
+    fn skipping(&self) -> bool {
+        enabled() || self.inner.skips_changes()
+    }
+
+    /// Log and count a skipped change of a record, such as "create" or "delete".
+    fn skip(&self, operation: &str, action: &str, record: &Record) {
+        DRY_RUN_OPERATIONS.with_label_values(&[self.inner.kind(), operation]).inc();
+        info!(provider = self.inner.kind(), operation = operation, action = action,
+              fqdn = %record.fqdn, zone = %record.zone, record_type = ?record.record_type,
+              value = %record.value, "Dry run: skipped change");
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: ProviderBackend> ProviderBackend for DryRun<P> {
+    fn kind(&self) -> &'static str {
+        self.inner.kind()
+    }
+
+    fn validate_options(&self) -> Result<()> {
+        self.inner.validate_options()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_rate_limit(&self, rate_limit: &RateLimit) {
+        self.inner.set_rate_limit(rate_limit)
+    }
+
+    fn set_max_concurrent_requests(&self, max_concurrent_requests: usize) {
+        self.inner.set_max_concurrent_requests(max_concurrent_requests)
+    }
+
+    fn skips_changes(&self) -> bool {
+        self.inner.skips_changes()
+    }
+
+    async fn get_zone(&self, domain: &FullDomainName) -> Result<ZoneDomainName> {
+        self.inner.get_zone(domain).await
+    }
+
+    async fn create_zone(&self, zone: &ZoneDomainName) -> Result<()> {
+        if !self.skipping() {
+            return self.inner.create_zone(zone).await
+        }
+        DRY_RUN_OPERATIONS.with_label_values(&[self.inner.kind(), "create_zone"]).inc();
+        info!(provider = self.inner.kind(), operation = "create_zone", zone = %zone,
+              "Dry run: skipped change");
+        Ok(())
+    }
+
+    async fn get_nameservers(&self, zone: &ZoneDomainName) -> Result<Vec<FullDomainName>> {
+        self.inner.get_nameservers(zone).await
+    }
+
+    async fn get_records(&self, domain: &ZoneDomainName, name: &FullDomainName) ->
+            Result<Vec<Record>> {
+        self.inner.get_records(domain, name).await
+    }
+
+    async fn get_records_page(&self, domain: &ZoneDomainName, page: Option<&str>) ->
+            Result<RecordPage> {
+        self.inner.get_records_page(domain, page).await
+    }
+
+    async fn _add_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
+        if !self.skipping() {
+            return self.inner._add_record(domain, record).await
+        }
+        self.skip("add_record", "create", record);
+        Ok(())
+    }
+
+    async fn _delete_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
+        if !self.skipping() {
+            return self.inner._delete_record(domain, record).await
+        }
+        self.skip("delete_record", "delete", record);
+        Ok(())
+    }
+
+    async fn _update_record(&self, domain: &ZoneDomainName, record: &Record,
+                            update: &Record) -> Result<()> {
+        if !self.skipping() {
+            return self.inner._update_record(domain, record, update).await
+        }
+        self.skip("update_record", "update", update);
+        Ok(())
+    }
+
+    async fn _apply_batch(&self, domain: &ZoneDomainName, changes: Vec<Change>) -> Result<()> {
+        if !self.skipping() {
+            return self.inner._apply_batch(domain, changes).await
+        }
+        for change in changes.iter() {
+            let action = match change.kind {
+                ChangeKind::Create => "create",
+                ChangeKind::Delete => "delete",
+            };
+            self.skip("apply_batch", action, &change.record);
+        }
+        Ok(())
+    }
+}
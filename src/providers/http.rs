@@ -0,0 +1,106 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Options of the HTTP clients of providers, given alongside the other options of a provider,
+//! for providers reached through a proxy or serving a certificate signed by a private CA. By
+//! default, requests never time out, are sent through the proxies given by the `HTTP_PROXY` and
+//! `HTTPS_PROXY` environment variables, and trust the root certificates of the system.
+
+// {{{ imports
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use serde::{Serialize, Deserialize};
+// }}}
+
+/// Options of the HTTP client of a provider.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all(serialize="camelCase", deserialize="camelCase"))]
+pub struct HttpOptions {
+    /// Time after which a request to the provider fails, in seconds.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub timeout_seconds: Option<u64>,
+
+    /// URL of the proxy that every request to the provider is sent through, overriding the
+    /// proxies given by the environment.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub proxy: Option<String>,
+
+    /// PEM certificates of CAs to trust alongside the root certificates of the system.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub ca_bundle: Option<String>,
+
+    /// Path to a file of PEM certificates of CAs to trust alongside the root certificates of the
+    /// system, such as a mounted ConfigMap.
+    #[serde(default, skip_serializing_if="Option::is_none")]
+    pub ca_file: Option<String>,
+
+    /// Accept any certificate served by the provider, even if invalid or expired. Only meant for
+    /// testing, as requests may then be intercepted.
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+}
+
+impl HttpOptions {
+    /// Check the options without making any requests, such as for an invalid proxy URL or CA
+    /// bundle.
+    pub fn validate(&self) -> Result<()> {
+        if self.timeout_seconds == Some(0) {
+            return Err(anyhow!("Invalid timeoutSeconds: 0"))
+        }
+        if let Some(proxy) = &self.proxy {
+            reqwest::Proxy::all(proxy.as_str())
+                .map_err(|e| anyhow!("Invalid proxy {}: {}", proxy, e))?;
+        }
+        self.certificates()?;
+        Ok(())
+    }
+
+    /// Apply the options to the builder of a client.
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> Result<reqwest::ClientBuilder> {
+        let mut builder = builder;
+        if let Some(timeout_seconds) = self.timeout_seconds {
+            builder = builder.timeout(Duration::from_secs(timeout_seconds));
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy.as_str())?);
+        }
+        for certificate in self.certificates()? {
+            builder = builder.add_root_certificate(certificate);
+        }
+        if self.insecure_skip_verify {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        Ok(builder)
+    }
+
+    /// Get the certificates of the CA bundle and the CA file.
+    fn certificates(&self) -> Result<Vec<reqwest::Certificate>> {
+        let mut certificates = vec![];
+        if let Some(ca_bundle) = &self.ca_bundle {
+            certificates.extend(parse_certificates(ca_bundle)
+                .map_err(|e| anyhow!("Invalid caBundle: {}", e))?);
+        }
+        if let Some(ca_file) = &self.ca_file {
+            let pem = std::fs::read_to_string(ca_file)
+                .map_err(|e| anyhow!("Unable to read caFile {}: {}", ca_file, e))?;
+            certificates.extend(parse_certificates(&pem)
+                .map_err(|e| anyhow!("Invalid caFile {}: {}", ca_file, e))?);
+        }
+        Ok(certificates)
+    }
+}
+
+/// Parse every certificate of a PEM bundle, as Reqwest only parses one certificate at a time.
+fn parse_certificates(pem: &str) -> Result<Vec<reqwest::Certificate>> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let certificates = pem
+        .split(END)
+        .filter(|block| block.contains("-----BEGIN CERTIFICATE-----"))
+        .map(|block| format!("{}{}\n", block.trim(), END))
+        .map(|block| reqwest::Certificate::from_pem(block.as_bytes()))
+        .collect::<reqwest::Result<Vec<_>>>()?;
+    if certificates.is_empty() {
+        return Err(anyhow!("No certificates found"))
+    }
+    Ok(certificates)
+}
@@ -0,0 +1,108 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! A wrapper around a ProviderBackend recording the time taken by every call to the provider,
+//! and the calls that failed, as metrics labeled by the kind of the provider and the operation.
+
+// {{{ imports
+use std::future::Future;
+
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+use super::util::{Capabilities, Change, FullDomainName, ProviderBackend, Record,
+                  RecordPage, ZoneDomainName};
+use super::rate_limit::RateLimit;
+use crate::error::ProviderError;
+use crate::metrics::{PROVIDER_REQUEST_DURATION, PROVIDER_REQUEST_ERRORS};
+// }}}
+
+/// A ProviderBackend whose calls to the provider are measured. Only the methods making a single
+/// call to the provider are measured; the default methods of ProviderBackend, such as
+/// `apply_changes()`, are built on them, so that each of their calls is measured on its own.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct Instrumented<P> {
+    inner: P,
+}
+
+impl<P: ProviderBackend> Instrumented<P> {
+    /// Measure a call to the provider.
+    async fn observe<T>(&self, operation: &str, call: impl Future<Output = Result<T>>) ->
+            Result<T> {
+        let labels = [self.inner.kind(), operation];
+        let timer = PROVIDER_REQUEST_DURATION.with_label_values(&labels).start_timer();
+        let result = call.await;
+        timer.observe_duration();
+        if let Err(e) = &result {
+            let error = ProviderError::find(e).map(ProviderError::kind).unwrap_or("other");
+            PROVIDER_REQUEST_ERRORS.with_label_values(&[labels[0], labels[1], error]).inc();
+        }
+        result
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: ProviderBackend> ProviderBackend for Instrumented<P> {
+    fn kind(&self) -> &'static str {
+        self.inner.kind()
+    }
+
+    fn validate_options(&self) -> Result<()> {
+        self.inner.validate_options()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_rate_limit(&self, rate_limit: &RateLimit) {
+        self.inner.set_rate_limit(rate_limit)
+    }
+
+    fn set_max_concurrent_requests(&self, max_concurrent_requests: usize) {
+        self.inner.set_max_concurrent_requests(max_concurrent_requests)
+    }
+
+    fn skips_changes(&self) -> bool {
+        self.inner.skips_changes()
+    }
+
+    async fn get_zone(&self, domain: &FullDomainName) -> Result<ZoneDomainName> {
+        self.observe("get_zone", self.inner.get_zone(domain)).await
+    }
+
+    async fn create_zone(&self, zone: &ZoneDomainName) -> Result<()> {
+        self.observe("create_zone", self.inner.create_zone(zone)).await
+    }
+
+    async fn get_nameservers(&self, zone: &ZoneDomainName) -> Result<Vec<FullDomainName>> {
+        self.observe("get_nameservers", self.inner.get_nameservers(zone)).await
+    }
+
+    async fn get_records(&self, domain: &ZoneDomainName, name: &FullDomainName) ->
+            Result<Vec<Record>> {
+        self.observe("get_records", self.inner.get_records(domain, name)).await
+    }
+
+    async fn get_records_page(&self, domain: &ZoneDomainName, page: Option<&str>) ->
+            Result<RecordPage> {
+        self.observe("get_records_page", self.inner.get_records_page(domain, page)).await
+    }
+
+    async fn _add_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
+        self.observe("add_record", self.inner._add_record(domain, record)).await
+    }
+
+    async fn _delete_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
+        self.observe("delete_record", self.inner._delete_record(domain, record)).await
+    }
+
+    async fn _update_record(&self, domain: &ZoneDomainName, record: &Record,
+                            update: &Record) -> Result<()> {
+        self.observe("update_record", self.inner._update_record(domain, record, update)).await
+    }
+
+    async fn _apply_batch(&self, domain: &ZoneDomainName, changes: Vec<Change>) -> Result<()> {
+        self.observe("apply_batch", self.inner._apply_batch(domain, changes)).await
+    }
+}
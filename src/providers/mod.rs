@@ -1,20 +1,47 @@
 // vim:set et sw=4 ts=4 foldmethod=marker:
 
+//! Providers of DNS records. Each provider is built by a factory registered under the name given
+//! as `provider` in the configuration, so that providers can be added with `register_provider()`
+//! without changing the code loading the configuration.
+
 // {{{ imports
-use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, RwLock};
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use serde::{de::{self, DeserializeOwned}, Serialize, Serializer, Deserialize, Deserializer};
 
 pub mod cloudflare;
+pub mod concurrency;
+pub mod dry_run;
+pub mod http;
+pub mod instrumented;
+pub mod noop;
+pub mod rate_limit;
+pub mod retry;
 // }}}
 
 pub mod util { // {{{
+    use std::fmt;
+    use std::net::IpAddr;
+
     use anyhow::{anyhow, Result};
 
+    use futures::stream::{self, BoxStream, StreamExt};
     use serde::{Serialize, Deserialize};
+    use tokio::sync::mpsc::UnboundedSender;
+    use tracing::info;
+
+    use super::rate_limit::RateLimit;
+
     pub type ZoneDomainName = String;
     pub type FullDomainName = String;
     pub type SubDomainName = String;
 
-    #[derive(Serialize, Deserialize, Clone, Debug)]
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
     pub enum RecordType {
         // Standard
         A,
@@ -26,6 +53,7 @@ pub mod util { // {{{
         PTR,
         SOA,
         SRV,
+        SSHFP,
         TXT,
         // DNSSEC types
         DNSKEY,
@@ -36,13 +64,24 @@ pub mod util { // {{{
         RRSIG,
     }
 
-    #[derive(Serialize, Deserialize, Debug)]
+    #[derive(Serialize, Deserialize, Clone, Debug)]
     pub struct Record {
         pub fqdn: FullDomainName,
         pub zone: ZoneDomainName,
         pub record_type: RecordType,
         pub ttl: u64,
         pub value: String,
+        /// Whether traffic to the record is proxied by the provider, for
+        /// providers that proxy traffic such as CloudFlare. `None` if the
+        /// provider does not proxy traffic, or if the state is left to the
+        /// provider.
+        #[serde(default)]
+        pub proxied: Option<bool>,
+        /// The ownership information of the record, for providers able to
+        /// annotate records, such as with the comments of CloudFlare. `None`
+        /// for records read from a provider.
+        #[serde(default, skip_serializing_if="Option::is_none")]
+        pub heritage: Option<Heritage>,
     }
 
     #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -52,6 +91,311 @@ pub mod util { // {{{
         pub record_type: RecordType,
         pub ttl: Option<u64>,
         pub value: Option<String>,
+        pub reverse: bool,
+        pub max_changes: Option<usize>,
+        pub policy: Policy,
+        pub heritage: Heritage,
+        pub import_external_dns: Option<String>,
+        pub proxied: Option<bool>,
+        #[serde(skip)]
+        pub changes: Vec<UnboundedSender<Change>>,
+    }
+
+    /// What a provider supports, to check Records against the provider they
+    /// are synced with before making any change.
+    #[derive(Clone, Debug)]
+    pub struct Capabilities {
+        /// Types of records the provider supports; every type if `None`.
+        pub record_types: Option<Vec<RecordType>>,
+        /// Lowest TTL the provider accepts, if limited.
+        pub min_ttl: Option<u64>,
+        /// Highest TTL the provider accepts, if limited.
+        pub max_ttl: Option<u64>,
+        /// TTL standing for the automatic TTL of the provider, which is
+        /// accepted regardless of the limits, such as 1 for CloudFlare.
+        pub automatic_ttl: Option<u64>,
+        /// Whether a domain name may have more than one value of a type.
+        pub multi_value: bool,
+        /// Whether records are updated in place, rather than replaced.
+        pub updates: bool,
+        /// Largest number of changes made in one call to `_apply_batch()`,
+        /// for providers able to make many changes in one request.
+        pub batch_size: Option<usize>,
+    }
+
+    impl Default for Capabilities {
+        fn default() -> Self {
+            Capabilities {
+                record_types: None,
+                min_ttl: None,
+                max_ttl: None,
+                automatic_ttl: None,
+                multi_value: true,
+                updates: false,
+                batch_size: None,
+            }
+        }
+    }
+
+    /// Kind of change made to the records of a provider.
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+    pub enum ChangeKind {
+        #[serde(rename="create")]
+        Create,
+        #[serde(rename="delete")]
+        Delete,
+    }
+
+    /// A record created or deleted by a sync, along with the Heritage of the
+    /// Record that the change was made for.
+    #[derive(Serialize, Deserialize, Clone, Debug)]
+    pub struct Change {
+        pub kind: ChangeKind,
+        pub record: Record,
+        pub heritage: Heritage,
+    }
+
+    /// A page of the records of a zone, along with the token of the page
+    /// following it, if any. Tokens are opaque to everything but the provider
+    /// that returned them.
+    #[derive(Clone, Debug)]
+    pub struct RecordPage {
+        pub records: Vec<Record>,
+        pub next: Option<String>,
+    }
+
+    /// Policy controlling which changes ARES may make to the records of a
+    /// provider.
+    #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+    pub enum Policy {
+        /// Add missing records and remove records that are no longer wanted.
+        #[serde(rename="sync")]
+        Sync,
+        /// Add missing records, but never remove records.
+        #[serde(rename="upsert-only")]
+        UpsertOnly,
+        /// Only add records for domain names without existing records, never
+        /// changing or removing records once created.
+        #[serde(rename="create-only")]
+        CreateOnly,
+    }
+
+    impl Default for Policy {
+        fn default() -> Self {
+            Policy::Sync
+        }
+    }
+
+    /// Ownership information stored in the tracking record of a domain name,
+    /// formatted similarly to the heritage records of external-dns, such as
+    /// "heritage=ares,ares/owner=default,ares/resource=record/default/www,
+    /// ares/uid=...". Tracking records created before heritage was recorded
+    /// contain only "ares", and are parsed without an owner.
+    #[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+    pub struct Heritage {
+        pub owner: Option<String>,
+        pub cluster: Option<String>,
+        pub resource: Option<String>,
+        pub uid: Option<String>,
+    }
+
+    impl Heritage {
+        /// Parse the value of a tracking record. `None` is returned if the
+        /// record was not created by ARES.
+        pub fn parse(value: &str) -> Option<Heritage> {
+            let value = value.trim_matches('"');
+            if value == "ares" {
+                return Some(Heritage::default())
+            }
+            let mut fields = value.split(',');
+            if fields.next()? != "heritage=ares" {
+                return None
+            }
+            let mut heritage = Heritage::default();
+            for field in fields {
+                let mut pair = field.splitn(2, '=');
+                let (key, value) = (pair.next()?, pair.next()?.to_string());
+                match key {
+                    "ares/owner" => heritage.owner = Some(value),
+                    "ares/cluster" => heritage.cluster = Some(value),
+                    "ares/resource" => heritage.resource = Some(value
+                        .trim_start_matches("record/")
+                        .to_string()),
+                    "ares/uid" => heritage.uid = Some(value),
+                    _ => {},
+                }
+            }
+            Some(heritage)
+        }
+
+        /// Check whether the domain name of a tracking record may be managed
+        /// with this Heritage. Tracking records without an owner predate
+        /// owner IDs, and may be managed by any owner.
+        pub fn owns(&self, tracking: &Heritage) -> bool {
+            tracking.owner.is_none() || tracking.owner == self.owner
+        }
+
+        /// Find the tracking record that may be managed with this Heritage.
+        pub fn find_owned<'a>(&self, tracking_records: &'a [Record]) -> Option<&'a Record> {
+            tracking_records.iter().find(|x| {
+                Heritage::parse(&x.value).map(|h| self.owns(&h)).unwrap_or(false)
+            })
+        }
+    }
+
+    impl fmt::Display for Heritage {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "heritage=ares")?;
+            if let Some(owner) = &self.owner {
+                write!(f, ",ares/owner={}", owner)?;
+            }
+            if let Some(cluster) = &self.cluster {
+                write!(f, ",ares/cluster={}", cluster)?;
+            }
+            if let Some(resource) = &self.resource {
+                write!(f, ",ares/resource=record/{}", resource)?;
+            }
+            if let Some(uid) = &self.uid {
+                write!(f, ",ares/uid={}", uid)?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Get the owner ID of a heritage TXT record created by the TXT registry of
+    /// external-dns, such as "heritage=external-dns,external-dns/owner=default".
+    /// `None` is returned for any other value.
+    pub fn external_dns_owner(value: &str) -> Option<String> {
+        let mut fields = value.trim_matches('"').split(',');
+        if fields.next()? != "heritage=external-dns" {
+            return None
+        }
+        fields
+            .find(|field| field.starts_with("external-dns/owner="))
+            .map(|field| field["external-dns/owner=".len()..].to_string())
+    }
+
+    /// Error returned when syncing a domain name would add or remove more
+    /// records than allowed by a single sync, such as when a bad selector
+    /// suddenly matches no Pods. No records are changed.
+    #[derive(Debug)]
+    pub struct TooManyChanges {
+        pub fqdn: FullDomainName,
+        pub changes: usize,
+        pub max_changes: usize,
+    }
+
+    impl fmt::Display for TooManyChanges {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "Refusing to make {} changes to {}, more than the maximum of {}",
+                   self.changes, self.fqdn, self.max_changes)
+        }
+    }
+
+    impl std::error::Error for TooManyChanges {}
+
+    /// Get the name of the PTR record for an IPv4 or IPv6 address, such as
+    /// `4.3.2.1.in-addr.arpa` for `1.2.3.4`.
+    pub fn reverse_pointer(address: &str) -> Result<FullDomainName> {
+        match address.parse::<IpAddr>()? {
+            IpAddr::V4(v4) => {
+                let octets: Vec<String> = v4
+                    .octets()
+                    .iter()
+                    .rev()
+                    .map(|octet| octet.to_string())
+                    .collect();
+                Ok(format!("{}.in-addr.arpa", octets.join(".")))
+            },
+            IpAddr::V6(v6) => {
+                let nibbles: Vec<String> = v6
+                    .octets()
+                    .iter()
+                    .rev()
+                    .flat_map(|octet| vec![octet & 0xf, octet >> 4])
+                    .map(|nibble| format!("{:x}", nibble))
+                    .collect();
+                Ok(format!("{}.ip6.arpa", nibbles.join(".")))
+            },
+        }
+    }
+
+    /// Normalize a domain name so that it can be compared against other domain
+    /// names and passed to providers consistently. Surrounding whitespace and
+    /// trailing dots are removed, and labels are lowercased and converted to
+    /// punycode, such that "München.example.com." becomes
+    /// "xn--mnchen-3ya.example.com". A leading wildcard label or a leading dot
+    /// (as used in selectors) is kept as-is.
+    pub fn normalize_domain(name: &str) -> Result<FullDomainName> {
+        let name = name.trim().trim_end_matches('.');
+        let (prefix, rest) = if name.starts_with("*.") {
+            name.split_at(2)
+        } else if name.starts_with('.') {
+            name.split_at(1)
+        } else {
+            ("", name)
+        };
+        let ascii = idna::domain_to_ascii(rest)
+            .map_err(|e| anyhow!("Unable to normalize domain name {}: {:?}", name, e))?;
+        Ok(format!("{}{}", prefix, ascii))
+    }
+
+    /// Normalize the value of a record so that values given to ARES can be
+    /// compared against the values returned by providers, which often differ
+    /// only in form. Surrounding whitespace is removed, addresses are written
+    /// in their canonical form (such as "2001:db8::1" for
+    /// "2001:0db8:0:0:0:0:0:1"), and domain names are lowercased without a
+    /// trailing dot, including the targets of MX and SRV records. Values that
+    /// can't be parsed are only trimmed.
+    pub fn normalize_value(record_type: &RecordType, value: &str) -> String {
+        let value = value.trim();
+        let domain = |name: &str| name.trim_end_matches('.').to_lowercase();
+        match record_type {
+            RecordType::A | RecordType::AAAA => value
+                .parse::<IpAddr>()
+                .map(|address| address.to_string())
+                .unwrap_or_else(|_| value.to_string()),
+            RecordType::ALIAS | RecordType::CNAME | RecordType::NS | RecordType::PTR => {
+                domain(value)
+            },
+            // the target is the last field, after the priority (and weight and port)
+            RecordType::MX | RecordType::SRV => {
+                let mut fields: Vec<String> = value
+                    .split_whitespace()
+                    .map(str::to_string)
+                    .collect();
+                if let Some(target) = fields.last_mut() {
+                    *target = domain(target);
+                }
+                fields.join(" ")
+            },
+            _ => value.to_string(),
+        }
+    }
+
+    /// Get the name of the tracking record for a record. Wildcard records
+    /// can't have a tracking record directly under them, as a wildcard label
+    /// must be the leftmost label of a domain name, so the tracking record for
+    /// "*.example.com" is instead "_owner._wildcard.example.com".
+    pub fn tracking_domain(fqdn: &FullDomainName) -> FullDomainName {
+        if fqdn.starts_with("*.") {
+            format!("_owner._wildcard.{}", &fqdn[2..])
+        } else {
+            format!("_owner.{}", fqdn)
+        }
+    }
+
+    /// Get the name of the record tracked by a tracking record, reversing
+    /// `tracking_domain()`. `None` is returned for names that are not
+    /// tracking records.
+    pub fn tracked_domain(tracking: &FullDomainName) -> Option<FullDomainName> {
+        if tracking.starts_with("_owner._wildcard.") {
+            Some(format!("*.{}", &tracking["_owner._wildcard.".len()..]))
+        } else if tracking.starts_with("_owner.") {
+            Some(tracking["_owner.".len()..].to_string())
+        } else {
+            None
+        }
     }
 
     impl Record {
@@ -63,9 +407,21 @@ pub mod util { // {{{
                 ttl: ttl,
                 record_type: _type,
                 value: value,
+                proxied: None,
+                heritage: None,
             }
         }
 
+        /// Normalize the domain names and the value of the record with
+        /// `normalize_value()`, so that records differing only in form compare
+        /// equal.
+        pub fn normalized(mut self) -> Record {
+            self.fqdn = self.fqdn.trim().trim_end_matches('.').to_lowercase();
+            self.zone = self.zone.trim().trim_end_matches('.').to_lowercase();
+            self.value = normalize_value(&self.record_type, &self.value);
+            self
+        }
+
         pub fn builder(fqdn: FullDomainName,
                        zone: ZoneDomainName,
                        record_type: RecordType) -> RecordBuilder {
@@ -75,6 +431,13 @@ pub mod util { // {{{
                 record_type: record_type,
                 ttl: None,
                 value: None,
+                reverse: false,
+                max_changes: None,
+                policy: Policy::default(),
+                heritage: Heritage::default(),
+                import_external_dns: None,
+                proxied: None,
+                changes: vec![],
             }
         }
     }
@@ -94,22 +457,330 @@ pub mod util { // {{{
             }
         }
 
+        /// Manage a PTR record for every address alongside the record itself.
+        pub fn reverse(self, reverse: bool) -> Self {
+            RecordBuilder {
+                reverse: reverse,
+                ..self
+            }
+        }
+
+        /// Limit the number of records added or removed by a single sync.
+        pub fn max_changes(self, max_changes: Option<usize>) -> Self {
+            RecordBuilder {
+                max_changes: max_changes,
+                ..self
+            }
+        }
+
+        /// Restrict the changes made by a sync to those allowed by a Policy.
+        pub fn policy(self, policy: Policy) -> Self {
+            RecordBuilder {
+                policy: policy,
+                ..self
+            }
+        }
+
+        /// Record ownership of created domain names with a Heritage.
+        pub fn heritage(self, heritage: Heritage) -> Self {
+            RecordBuilder {
+                heritage: heritage,
+                ..self
+            }
+        }
+
+        /// Take ownership of domain names owned by external-dns with the given
+        /// owner ID, rather than refusing to modify them.
+        pub fn import_external_dns(self, owner: Option<String>) -> Self {
+            RecordBuilder {
+                import_external_dns: owner,
+                ..self
+            }
+        }
+
+        /// Proxy traffic to the records through the provider, for providers
+        /// that proxy traffic. The proxied state of existing records of the
+        /// domain name is kept if not given.
+        pub fn proxied(self, proxied: Option<bool>) -> Self {
+            RecordBuilder {
+                proxied: proxied,
+                ..self
+            }
+        }
+
+        /// Report every record created or deleted by a sync to each channel.
+        pub fn changes(self, changes: Vec<UnboundedSender<Change>>) -> Self {
+            RecordBuilder {
+                changes: changes,
+                ..self
+            }
+        }
+
+        /// Build a change of a record made for the Heritage of the
+        /// RecordBuilder.
+        pub fn change(&self, kind: ChangeKind, record: &Record) -> Change {
+            Change {
+                kind: kind,
+                record: record.clone(),
+                heritage: self.heritage.clone(),
+            }
+        }
+
+        /// Log a change, and report it to the channels given to `changes()`.
+        pub fn report(&self, kind: ChangeKind, record: &Record) {
+            let action = match kind {
+                ChangeKind::Create => "create",
+                ChangeKind::Delete => "delete",
+            };
+            // changes skipped by dry runs are still reported, such as to plan them
+            let message = if super::dry_run::enabled() {
+                "Planned change"
+            } else {
+                "Changed record"
+            };
+            info!(action = action, fqdn = %record.fqdn, zone = %record.zone,
+                  record_type = ?record.record_type, value = %record.value, "{}", message);
+            for changes in self.changes.iter() {
+                // The receiving end only closes when shutting down.
+                let _ = changes.send(self.change(kind, record));
+            }
+        }
+
         pub fn try_build(self) -> Result<Record> {
             let ttl = self.ttl.ok_or(anyhow!("Missing TTL"))?;
             let value = self.value.ok_or(anyhow!("Missing value"))?;
-            Ok(Record::new(self.zone,
-                           self.fqdn,
-                           ttl,
-                           self.record_type,
-                           value))
+            let mut record = Record::new(self.zone,
+                                         self.fqdn,
+                                         ttl,
+                                         self.record_type,
+                                         value);
+            record.proxied = self.proxied;
+            record.heritage = Some(self.heritage);
+            Ok(record)
+        }
+    }
+
+    /// Compare remote records against the given values, returning the remote
+    /// records that should be removed and the values that should be added,
+    /// as allowed by the Policy of the RecordBuilder. Values are compared once
+    /// normalized with `normalize_value()`, and the values to add are
+    /// returned normalized. With the `sync` policy, records proxied
+    /// differently than given to the RecordBuilder, or with another TTL, are
+    /// replaced; providers able to update records in place update them
+    /// instead.
+    pub fn plan_changes(record_builder: &RecordBuilder, remote_records: &[Record],
+                        records: &[String]) -> (Vec<Record>, Vec<String>) {
+        let sync = record_builder.policy == Policy::Sync;
+        let records = normalize_values(&record_builder.record_type, records);
+        let value = |x: &Record| normalize_value(&x.record_type, &x.value);
+        let matches = |x: &Record| {
+            let proxied_matches = match (record_builder.proxied, x.proxied) {
+                (Some(wanted), Some(proxied)) => !sync || wanted == proxied,
+                _ => true,
+            };
+            // proxied records always have the automatic TTL of the provider
+            let ttl_matches = match record_builder.ttl {
+                Some(ttl) if x.proxied != Some(true) => !sync || x.ttl == ttl,
+                _ => true,
+            };
+            proxied_matches && ttl_matches
+        };
+        let stale: Vec<Record> = match record_builder.policy {
+            Policy::Sync => remote_records
+                .iter()
+                .filter(|x| !records.contains(&value(x)) || !matches(x))
+                .cloned()
+                .collect(),
+            Policy::UpsertOnly | Policy::CreateOnly => vec![],
+        };
+        let missing: Vec<String> = match record_builder.policy {
+            Policy::CreateOnly if !remote_records.is_empty() => vec![],
+            _ => records
+                .iter()
+                .filter(|record| {
+                    remote_records.iter().all(|x| value(x) != **record || !matches(x))
+                })
+                .cloned()
+                .collect(),
+        };
+        (stale, missing)
+    }
+
+    /// The changes that syncing the values of a RecordBuilder makes to the
+    /// records of its domain name, planned before any change is made.
+    #[derive(Clone, Debug)]
+    pub struct SyncPlan {
+        /// Every change to make, including to the tracking record once
+        /// planned with `track()`.
+        pub changes: Vec<Change>,
+        /// The remote records to remove.
+        pub stale: Vec<Record>,
+        /// The record of every value, whether or not it is added.
+        pub records: Vec<Record>,
+        /// The values to add.
+        pub missing: Vec<String>,
+    }
+
+    impl SyncPlan {
+        /// Plan the changes to the records of the domain name of a
+        /// RecordBuilder against its remote records, failing if more changes
+        /// would be made than allowed by a single sync.
+        pub fn new(record_builder: &RecordBuilder, remote_records: &[Record],
+                   records: &[String]) -> Result<SyncPlan> {
+            let (stale, missing) = plan_changes(record_builder, remote_records, records);
+            if let Some(max_changes) = record_builder.max_changes {
+                if stale.len() + missing.len() > max_changes {
+                    return Err(TooManyChanges {
+                        fqdn: record_builder.fqdn.clone(),
+                        changes: stale.len() + missing.len(),
+                        max_changes: max_changes,
+                    }.into())
+                }
+            }
+            // Records replacing existing records are proxied like the existing records, unless
+            // the RecordBuilder says otherwise.
+            let proxied = record_builder.proxied.or_else(|| {
+                remote_records
+                    .iter()
+                    .filter(|x| x.record_type == record_builder.record_type)
+                    .find_map(|x| x.proxied)
+            });
+            let mut record_entries = Vec::with_capacity(records.len());
+            for record in normalize_values(&record_builder.record_type, records) {
+                record_entries.push(record_builder
+                    .clone()
+                    .value(record)
+                    .ttl(record_builder.ttl.unwrap_or(1))
+                    .proxied(proxied)
+                    .try_build()?);
+            }
+            let changes: Vec<Change> = stale
+                .iter()
+                .map(|x| record_builder.change(ChangeKind::Delete, x))
+                .chain(record_entries
+                    .iter()
+                    .filter(|x| missing.contains(&x.value))
+                    .map(|x| record_builder.change(ChangeKind::Create, x)))
+                .collect();
+            Ok(SyncPlan {
+                changes: changes,
+                stale: stale,
+                records: record_entries,
+                missing: missing,
+            })
+        }
+
+        /// Plan the changes to the tracking record of the domain name, given
+        /// the remote records of the domain name and of its tracking record:
+        /// a tracking record is created along with the first value of the
+        /// domain name, and removed along with its last value. Domain names
+        /// owned by someone else are never changed.
+        pub fn track(&mut self, record_builder: &RecordBuilder, remote_records: &[Record],
+                     tracking_records: &[Record]) -> Result<()> {
+            if self.changes.is_empty() {
+                return Ok(())
+            }
+            let fqdn = &record_builder.fqdn;
+            let tracking_domain = tracking_domain(fqdn);
+            match record_builder.heritage.find_owned(tracking_records) {
+                Some(tracking_record) => {
+                    if self.stale.len() == remote_records.len() && self.missing.is_empty() {
+                        self.changes.push(record_builder.change(ChangeKind::Delete,
+                                                                tracking_record));
+                    }
+                },
+                None if !self.stale.is_empty() => {
+                    return Err(anyhow!("Missing tracking record: {}", tracking_domain))
+                },
+                None => {
+                    if tracking_records.get(0).is_some() {
+                        return Err(anyhow!("Found existing tracking record: {}",
+                                           tracking_domain));
+                    }
+                    if remote_records.get(0).is_some() {
+                        return Err(anyhow!("Found existing untracked record: {}", fqdn));
+                    }
+                    let tracking_entry = Record::builder(tracking_domain,
+                                                         record_builder.zone.clone(),
+                                                         RecordType::TXT)
+                        .value(record_builder.heritage.to_string())
+                        .ttl(1)
+                        .try_build()?;
+                    self.changes.insert(0, record_builder.change(ChangeKind::Create,
+                                                                 &tracking_entry));
+                },
+            }
+            Ok(())
+        }
+    }
+
+    /// Normalize values with `normalize_value()`, dropping values that only
+    /// differed in form from an earlier value.
+    pub fn normalize_values(record_type: &RecordType, values: &[String]) -> Vec<String> {
+        let mut normalized: Vec<String> = Vec::with_capacity(values.len());
+        for value in values {
+            let value = normalize_value(record_type, value);
+            if !normalized.contains(&value) {
+                normalized.push(value);
+            }
         }
+        normalized
+    }
+
+    /// Pair DNS records being deleted with DNS records being added with the
+    /// same name and type, so that providers able to update records in place
+    /// update them instead of replacing them. Returns the pairs of the record
+    /// to update and its update, along with the records left to delete and to
+    /// add.
+    pub fn pair_updates<'a>(changes: &'a [Change]) ->
+            (Vec<(&'a Record, &'a Record)>, Vec<&'a Record>, Vec<&'a Record>) {
+        let records = |kind: ChangeKind| changes
+            .iter()
+            .filter(move |x| x.kind == kind)
+            .map(|x| &x.record);
+        let mut updates = vec![];
+        let mut unpaired_deletes = vec![];
+        let mut unpaired_adds: Vec<&Record> = records(ChangeKind::Create).collect();
+        for record in records(ChangeKind::Delete) {
+            let update = unpaired_adds.iter().position(|x| {
+                x.fqdn.eq_ignore_ascii_case(&record.fqdn) && x.record_type == record.record_type
+            });
+            match update {
+                Some(index) => updates.push((record, unpaired_adds.remove(index))),
+                None => unpaired_deletes.push(record),
+            }
+        }
+        (updates, unpaired_deletes, unpaired_adds)
+    }
+
+    /// Split changes into batches of at most `batch_size` records to delete,
+    /// update and add, counting both changes of an update once. Deletions are
+    /// put in the first batches, followed by updates and additions, so that
+    /// every deletion is made before any creation even across batches.
+    pub fn split_batches(changes: &[Change], batch_size: usize) -> Vec<Vec<Change>> {
+        let (updates, deletes, adds) = pair_updates(changes);
+        // every record is borrowed from its change by pair_updates()
+        let change = |record: &Record| changes.iter().find(|x| std::ptr::eq(&x.record, record));
+        let units: Vec<Vec<&Record>> = deletes
+            .into_iter()
+            .map(|record| vec![record])
+            .chain(updates.into_iter().map(|(record, update)| vec![record, update]))
+            .chain(adds.into_iter().map(|record| vec![record]))
+            .collect();
+        units
+            .chunks(batch_size.max(1))
+            .map(|chunk| chunk.iter().flatten().filter_map(|x| change(*x)).cloned().collect())
+            .collect()
     }
 
     /// Generate a Reqwest client for use in Providers. Providers that
     /// implement an authentication logic should build their clients using a
     /// custom client_builder!() macro for each provider and, if necessary,
     /// create a get_client() function that can perform any necessary
-    /// handshakes.
+    /// handshakes. Clients built during a reconciliation send its
+    /// correlation ID with every request. Providers given HttpOptions build
+    /// their clients with `reqwest_client_builder!(options)`, which fails on
+    /// invalid options.
     #[macro_export]
     macro_rules! reqwest_client_builder {
         () => ({
@@ -120,6 +791,10 @@ pub mod util { // {{{
                     "/",
                     env!("CARGO_PKG_VERSION"),
                 ))
+                .default_headers($crate::correlation::headers())
+        });
+        ($http:expr) => ({
+            $http.apply($crate::reqwest_client_builder!())?
         });
     }
 
@@ -133,14 +808,99 @@ pub mod util { // {{{
         /// than is required.
         async fn get_zone(&self, domain: &FullDomainName) -> Result<ZoneDomainName>;
 
+        /// Get the kind of the provider, such as "cloudflare", as given in the
+        /// configuration.
+        fn kind(&self) -> &'static str;
+
+        /// Check the options of the provider without making any requests,
+        /// such as for missing credentials.
+        fn validate_options(&self) -> Result<()> {
+            Ok(())
+        }
+
+        /// Get what the provider supports. Providers support every type of
+        /// record and TTL by default.
+        fn capabilities(&self) -> Capabilities {
+            Capabilities::default()
+        }
+
+        /// Limit the rate of calls made to the provider. Providers ignore the
+        /// limit unless wrapped with `RateLimited`.
+        fn set_rate_limit(&self, _rate_limit: &RateLimit) {}
+
+        /// Limit the number of calls made to the provider at once. Providers
+        /// ignore the limit unless wrapped with `ConcurrencyLimited`.
+        fn set_max_concurrent_requests(&self, _max_concurrent_requests: usize) {}
+
+        /// Check whether changes to the records of the provider are never
+        /// made, such as by the noop provider. Such changes are skipped by
+        /// `DryRun` like in dry runs.
+        fn skips_changes(&self) -> bool {
+            false
+        }
+
+        /// Check that the provider supports records of a type.
+        fn check_record(&self, record_type: &RecordType) -> Result<()> {
+            if let Some(record_types) = &self.capabilities().record_types {
+                if !record_types.contains(record_type) {
+                    return Err(anyhow!("Provider {} does not support {:?} records",
+                                       self.kind(), record_type))
+                }
+            }
+            Ok(())
+        }
+
+        /// Clamp a TTL to the TTLs that the provider accepts. The automatic
+        /// TTL of the provider is accepted as is.
+        fn clamp_ttl(&self, ttl: u64) -> u64 {
+            let capabilities = self.capabilities();
+            if capabilities.automatic_ttl == Some(ttl) {
+                return ttl
+            }
+            let ttl = capabilities.min_ttl.map_or(ttl, |min_ttl| ttl.max(min_ttl));
+            capabilities.max_ttl.map_or(ttl, |max_ttl| ttl.min(max_ttl))
+        }
+
+        /// Create a DNS zone. Providers that are unable to create zones return
+        /// an error.
+        async fn create_zone(&self, zone: &ZoneDomainName) -> Result<()> {
+            Err(anyhow!("Unable to create zone with this provider: {}", zone))
+        }
+
+        /// Get the nameservers that a zone hosted by the provider is served
+        /// from. Providers that are unable to report nameservers return an
+        /// error.
+        async fn get_nameservers(&self, zone: &ZoneDomainName) -> Result<Vec<FullDomainName>> {
+            Err(anyhow!("Unable to get nameservers with this provider: {}", zone))
+        }
+
         /// Get a deployed record from the backend service.
         async fn get_records(&self, domain: &ZoneDomainName, name: &FullDomainName) ->
                 Result<Vec<Record>>;
 
-        /// Get all records from the backend service, as a pairing of record entry
-        /// to record value.
-        async fn get_all_records(&self, domain: &ZoneDomainName) ->
-                Result<std::collections::HashMap<SubDomainName, Vec<Record>>>;
+        /// Get a page of the records of a zone from the backend service,
+        /// starting from the first page when no page is given.
+        async fn get_records_page(&self, domain: &ZoneDomainName, page: Option<&str>) ->
+                Result<RecordPage>;
+
+        /// Stream all records of a zone from the backend service, fetching each
+        /// page as the records before it are consumed, so that large zones are
+        /// never held in memory at once. The stream ends after the first error.
+        fn get_all_records<'a>(&'a self, domain: &'a ZoneDomainName) ->
+                BoxStream<'a, Result<Record>> {
+            stream::unfold(Some(None), move |page: Option<Option<String>>| async move {
+                let page = page?;
+                match self.get_records_page(domain, page.as_deref()).await {
+                    Ok(listing) => Some((Ok(listing.records), listing.next.map(Some))),
+                    Err(e) => Some((Err(e), None)),
+                }
+            })
+            .flat_map(|listing| stream::iter(match listing {
+                Ok(records) => records.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            }))
+            .boxed()
+        }
 
         /// Add a DNS Record.
         async fn _add_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()>;
@@ -148,43 +908,167 @@ pub mod util { // {{{
         /// Delete a DNS Record.
         async fn _delete_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()>;
 
-        /// Add a DNS record and tracking record.
-        async fn add_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
-            // TODO more heritage information in DNS record
-            let tracking_domain = format!("{}.{}", "_owner", &record.fqdn);
+        /// Update a DNS Record in place, changing it to the given update, for
+        /// providers whose capabilities include updates.
+        async fn _update_record(&self, domain: &ZoneDomainName, _record: &Record,
+                                _update: &Record) -> Result<()> {
+            Err(anyhow!("Unable to update records in place with this provider: {}", domain))
+        }
+
+        /// Apply a batch of changes in one request, for providers with a
+        /// `batch_size`. Batches are built by `apply_changes()`, and hold at
+        /// most `batch_size` records to delete, update and add, counting both
+        /// changes of an update once.
+        async fn _apply_batch(&self, domain: &ZoneDomainName, _changes: Vec<Change>) ->
+                Result<()> {
+            Err(anyhow!("Unable to apply a batch of changes with this provider: {}", domain))
+        }
+
+        /// Change a DNS record to the given update, in place for providers
+        /// able to update records; otherwise, the record is replaced, leaving
+        /// a short time in which the record does not resolve.
+        async fn change_record(&self, domain: &ZoneDomainName, record: &Record,
+                               update: &Record) -> Result<()> {
+            if self.capabilities().updates {
+                return self._update_record(domain, record, update).await
+            }
+            self._delete_record(domain, record).await?;
+            self._add_record(domain, update).await
+        }
+
+        /// Apply the changes planned by a sync to the records of a zone,
+        /// making every deletion before any creation. Records deleted and
+        /// created with the same name and type are updated in place instead.
+        /// Providers with a `batch_size` are sent the changes in batches with
+        /// `_apply_batch()`; otherwise, every record is changed one at a time.
+        /// Each call is made on its own, so that wrappers such as Retrying
+        /// never make a call again once it succeeded.
+        async fn apply_changes(&self, domain: &ZoneDomainName, changes: Vec<Change>) ->
+                Result<()> {
+            if let Some(batch_size) = self.capabilities().batch_size {
+                for batch in split_batches(&changes, batch_size) {
+                    self._apply_batch(domain, batch).await?;
+                }
+                return Ok(())
+            }
+            let (updates, deletes, adds) = pair_updates(&changes);
+            for record in deletes {
+                self._delete_record(domain, record).await?;
+            }
+            for (record, update) in updates {
+                self.change_record(domain, record, update).await?;
+            }
+            for record in adds {
+                self._add_record(domain, record).await?;
+            }
+            Ok(())
+        }
+
+        /// Add a DNS record and tracking record. The tracking record is shared
+        /// by every value of a domain name, and is only created along with the
+        /// first value; a domain name that already has records without a
+        /// tracking record, or with a tracking record of another owner, is not
+        /// owned by ARES, and is not modified.
+        async fn add_record(&self, domain: &ZoneDomainName, record: &Record,
+                            heritage: &Heritage) -> Result<()> {
+            let tracking_domain = tracking_domain(&record.fqdn);
             let tracking_record = self
                 .get_records(domain, &tracking_domain)
                 .await?;
-            if let Some(r) = tracking_record.get(0) {
-                // we have a tracking record, we should *not* have a tracking record.
-                return Err(anyhow!("Found existing tracking record: {}", tracking_domain));
-            }
-            let record_builder = Record::builder(tracking_domain, domain.clone(),
-                                                 RecordType::TXT)
-                .value("ares".to_string())
-                .ttl(1);
-            self._add_record(domain, &record_builder.try_build()?).await?;
+            if heritage.find_owned(&tracking_record).is_none() {
+                if tracking_record.get(0).is_some() {
+                    return Err(anyhow!("Found existing tracking record: {}", tracking_domain));
+                }
+                if self.get_records(domain, &record.fqdn).await?.get(0).is_some() {
+                    return Err(anyhow!("Found existing untracked record: {}", record.fqdn));
+                }
+                let record_builder = Record::builder(tracking_domain, domain.clone(),
+                                                     RecordType::TXT)
+                    .value(heritage.to_string())
+                    .ttl(1);
+                self._add_record(domain, &record_builder.try_build()?).await?;
+            }
             self._add_record(domain, record).await?;
             Ok(())
         }
 
-        /// Remove a DNS record and tracking record.
-        async fn delete_record(&self, domain: &ZoneDomainName, record: &Record) ->
-                Result<()> {
-            let tracking_domain = format!("{}.{}", "_owner", &record.fqdn);
+        /// Update a DNS record in place, such as to change its value or TTL.
+        /// Domain names without a tracking record of the owner are not owned
+        /// by ARES, and are not modified.
+        async fn update_record(&self, domain: &ZoneDomainName, record: &Record,
+                               update: &Record, heritage: &Heritage) -> Result<()> {
+            let tracking_domain = tracking_domain(&record.fqdn);
             let tracking_record = self
                 .get_records(domain, &tracking_domain)
                 .await?;
-            match tracking_record.iter().filter(|x| x.value == "ares".to_string()).next() {
+            match heritage.find_owned(&tracking_record) {
+                Some(_) => self.change_record(domain, record, update).await,
+                None => Err(anyhow!("Missing tracking record: {}", tracking_domain))
+            }
+        }
+
+        /// Remove a DNS record, and the tracking record if no other values
+        /// remain for the domain name.
+        async fn delete_record(&self, domain: &ZoneDomainName, record: &Record,
+                               heritage: &Heritage) -> Result<()> {
+            let tracking_domain = tracking_domain(&record.fqdn);
+            let tracking_record = self
+                .get_records(domain, &tracking_domain)
+                .await?;
+            match heritage.find_owned(&tracking_record) {
                 Some(r) => {
                     self._delete_record(domain, record).await?;
-                    self._delete_record(domain, r).await?;
+                    if self.get_records(domain, &record.fqdn).await?.is_empty() {
+                        self._delete_record(domain, r).await?;
+                    }
                     Ok(())
                 },
                 None => Err(anyhow!("Missing tracking record: {}", tracking_domain))
             }
         }
 
+        /// Remove every value of a domain name along with the tracking record.
+        /// Domain names without a tracking record of the owner are not owned by
+        /// ARES, and are left in place.
+        async fn delete_records(&self, record_builder: &RecordBuilder) -> Result<()> {
+            let tracking_domain = tracking_domain(&record_builder.fqdn);
+            let tracking_record = self
+                .get_records(&record_builder.zone, &tracking_domain)
+                .await?;
+            if record_builder.heritage.find_owned(&tracking_record).is_none() {
+                return Ok(())
+            }
+            self.sync_records(record_builder, &vec![]).await
+        }
+
+        /// Stop managing the records of a domain name without removing
+        /// them, by removing the tracking record owned by the Heritage of the
+        /// RecordBuilder. The records are then left alone by ARES, including
+        /// by the collection of orphaned records.
+        async fn release_records(&self, record_builder: &RecordBuilder) -> Result<()> {
+            let tracking_domain = tracking_domain(&record_builder.fqdn);
+            let tracking_records = self
+                .get_records(&record_builder.zone, &tracking_domain)
+                .await?;
+            if let Some(tracking_record) = record_builder.heritage.find_owned(&tracking_records) {
+                self._delete_record(&record_builder.zone, tracking_record).await?;
+            }
+            Ok(())
+        }
+
+        /// Get the changes that `sync_records()` would make without making
+        /// them, as the records that would be removed along with the values
+        /// that would be added.
+        async fn plan_records(&self, record_builder: &RecordBuilder,
+                              records: &Vec<String>) -> Result<(Vec<Record>, Vec<String>)> {
+            let remote_records: Vec<Record> = self
+                .get_records(&record_builder.zone, &record_builder.fqdn)
+                .await?
+                .into_iter()
+                .filter(|x| x.record_type == record_builder.record_type)
+                .collect();
+            Ok(plan_changes(record_builder, &remote_records, records))
+        }
 
         /// Get records from the remote server and ensure that the remote records
         /// match the given records.
@@ -192,33 +1076,273 @@ pub mod util { // {{{
                               records: &Vec<String>) -> Result<()> {
             let fqdn = &record_builder.fqdn;
             let zone = &record_builder.zone;
-            let remote_records = self.get_records(zone, fqdn).await?;
-            for record in remote_records.iter().filter(|x| !records.contains(&x.value)) {
-                self.delete_record(zone, record).await?;
-            }
-            for record in records {
-                if remote_records.iter().filter(|x| x.value == *record).next().is_none() {
-                    let record_entry = record_builder
-                        .clone()
-                        .value(record.clone())
-                        .ttl(1) // TODO: custom TTL
-                        .try_build()?;
-                    self.add_record(zone, &record_entry).await?;
+            let records = &normalize_values(&record_builder.record_type, records);
+            let mut remote_records = self.get_records(zone, fqdn).await?;
+            if remote_records.iter().any(|x| external_dns_owner(&x.value).is_some()) {
+                self.import_external_dns_records(record_builder, &remote_records).await?;
+                remote_records = self.get_records(zone, fqdn).await?;
+            }
+            if records.len() > 1 && !self.capabilities().multi_value {
+                return Err(anyhow!("Provider {} does not support more than one value: {}",
+                                   self.kind(), fqdn))
+            }
+            // Only records of the type of the RecordBuilder are synced; records of other types
+            // of the domain name are still checked for ownership by `track()`.
+            let typed_records: Vec<Record> = remote_records
+                .iter()
+                .filter(|x| x.record_type == record_builder.record_type)
+                .cloned()
+                .collect();
+            let mut plan = SyncPlan::new(record_builder, &typed_records, records)?;
+            // Every change of the domain name, including to the tracking record, is planned
+            // before any is made, so that providers able to make many changes in one request
+            // can apply the whole plan at once.
+            if !plan.changes.is_empty() {
+                let tracking_records = self.get_records(zone, &tracking_domain(fqdn)).await?;
+                plan.track(record_builder, &remote_records, &tracking_records)?;
+                info!(fqdn = %fqdn, zone = %zone, deletes = plan.stale.len(),
+                      creates = plan.missing.len(), "Applying changes");
+                self.apply_changes(zone, plan.changes.clone()).await?;
+            }
+            self.finish_sync(record_builder, &plan).await
+        }
+
+        /// Report the changes of a SyncPlan once they were applied, and sync
+        /// the PTR records of its records if enabled.
+        async fn finish_sync(&self, record_builder: &RecordBuilder, plan: &SyncPlan) ->
+                Result<()> {
+            for record in plan.stale.iter() {
+                record_builder.report(ChangeKind::Delete, record);
+                if record_builder.reverse {
+                    let heritage = &record_builder.heritage;
+                    if let Some(ptr_record) = self.delete_reverse_record(record, heritage).await? {
+                        record_builder.report(ChangeKind::Delete, &ptr_record);
+                    }
+                }
+            }
+            for record_entry in plan.records.iter() {
+                if plan.missing.contains(&record_entry.value) {
+                    record_builder.report(ChangeKind::Create, record_entry);
+                }
+                // PTR records are checked even when the record already exists, in case
+                // reverse records were enabled after the record was created.
+                if record_builder.reverse {
+                    let heritage = &record_builder.heritage;
+                    if let Some(ptr_record) = self.add_reverse_record(record_entry, heritage)
+                            .await? {
+                        record_builder.report(ChangeKind::Create, &ptr_record);
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        /// Take ownership of a domain name owned by external-dns, replacing the
+        /// heritage record of external-dns with a tracking record. Domain names
+        /// owned by external-dns are only imported if the owner ID matches
+        /// the owner ID being imported; otherwise, they are not modified.
+        async fn import_external_dns_records(&self, record_builder: &RecordBuilder,
+                                             remote_records: &Vec<Record>) -> Result<()> {
+            let fqdn = &record_builder.fqdn;
+            let zone = &record_builder.zone;
+            let heritage_records: Vec<&Record> = remote_records
+                .iter()
+                .filter(|x| external_dns_owner(&x.value).is_some())
+                .collect();
+            for heritage_record in heritage_records.iter() {
+                let owner = external_dns_owner(&heritage_record.value);
+                if owner.is_none() || owner != record_builder.import_external_dns {
+                    return Err(anyhow!("Found record owned by external-dns owner {}: {}",
+                                       owner.unwrap_or_default(), fqdn));
+                }
+            }
+            let tracking_domain = tracking_domain(fqdn);
+            let tracking_record = self.get_records(zone, &tracking_domain).await?;
+            if record_builder.heritage.find_owned(&tracking_record).is_none() {
+                if tracking_record.get(0).is_some() {
+                    return Err(anyhow!("Found existing tracking record: {}", tracking_domain));
                 }
+                let tracking_entry = Record::builder(tracking_domain, zone.clone(),
+                                                     RecordType::TXT)
+                    .value(record_builder.heritage.to_string())
+                    .ttl(1)
+                    .try_build()?;
+                self._add_record(zone, &tracking_entry).await?;
+            }
+            for heritage_record in heritage_records {
+                self._delete_record(zone, heritage_record).await?;
             }
             Ok(())
         }
+
+        /// Add a PTR record pointing back to an A or AAAA record, if the provider hosts the
+        /// reverse zone for the address of the record. Records of other types are ignored.
+        /// Returns the PTR record if it was added.
+        async fn add_reverse_record(&self, record: &Record, heritage: &Heritage) ->
+                Result<Option<Record>> {
+            let ptr_record = match self.build_reverse_record(record).await? {
+                Some(r) => r,
+                None => return Ok(None),
+            };
+            let existing = self.get_records(&ptr_record.zone, &ptr_record.fqdn).await?;
+            if existing.iter().filter(|x| x.value == ptr_record.value).next().is_some() {
+                return Ok(None)
+            }
+            self.add_record(&ptr_record.zone, &ptr_record, heritage).await?;
+            Ok(Some(ptr_record))
+        }
+
+        /// Remove the PTR record pointing back to an A or AAAA record, if it exists. Returns
+        /// the PTR record if it was removed.
+        async fn delete_reverse_record(&self, record: &Record, heritage: &Heritage) ->
+                Result<Option<Record>> {
+            let ptr_record = match self.build_reverse_record(record).await? {
+                Some(r) => r,
+                None => return Ok(None),
+            };
+            let existing = self.get_records(&ptr_record.zone, &ptr_record.fqdn).await?;
+            match existing.into_iter().find(|x| x.value == ptr_record.value) {
+                Some(r) => {
+                    self.delete_record(&ptr_record.zone, &r, heritage).await?;
+                    Ok(Some(r))
+                },
+                None => Ok(None),
+            }
+        }
+
+        /// Build the PTR record for an A or AAAA record. `None` is returned when the record is
+        /// of another type, or when the provider does not host the matching reverse zone.
+        async fn build_reverse_record(&self, record: &Record) -> Result<Option<Record>> {
+            match record.record_type {
+                RecordType::A | RecordType::AAAA => {},
+                _ => return Ok(None),
+            }
+            let ptr_name = reverse_pointer(&record.value)?;
+            let ptr_zone = match self.get_zone(&ptr_name).await {
+                Ok(z) => z,
+                Err(_) => return Ok(None),
+            };
+            let ptr_record = Record::builder(ptr_name, ptr_zone, RecordType::PTR)
+                .value(record.fqdn.clone())
+                .ttl(record.ttl)
+                .try_build()?;
+            Ok(Some(ptr_record))
+        }
     }
 } // }}}
 
 use util::ProviderBackend;
-use cloudflare::CloudFlareConfig as CloudFlare;
+use concurrency::ConcurrencyLimited;
+use dry_run::DryRun;
+use instrumented::Instrumented;
+use rate_limit::RateLimited;
+use retry::Retrying;
 
-trait_enum::trait_enum! {
-    #[derive(Serialize, Deserialize, Clone, Debug)]
-    #[serde(tag="provider", content="providerOptions")]
-    pub enum ProviderConfig: ProviderBackend {
-        #[serde(rename="cloudflare")]
-        CloudFlare,
+// Every provider is wrapped to skip changes in dry runs, to retry calls that failed transiently,
+// to limit the number and the rate of the calls that are made, and to record metrics of them.
+type Wrapped<P> = DryRun<Retrying<ConcurrencyLimited<RateLimited<Instrumented<P>>>>>;
+
+/// Build a provider from its `providerOptions`.
+pub type ProviderFactory = fn(serde_json::Value) -> Result<Box<dyn ProviderBackend>>;
+
+lazy_static! {
+    /// Factories of every known provider, keyed by the name given as `provider` in the
+    /// configuration.
+    static ref PROVIDERS: RwLock<HashMap<String, ProviderFactory>> = {
+        let mut providers: HashMap<String, ProviderFactory> = HashMap::new();
+        providers.insert("cloudflare".to_string(), build_provider::<cloudflare::CloudFlareConfig>);
+        providers.insert("noop".to_string(), build_provider::<noop::NoopProvider>);
+        RwLock::new(providers)
+    };
+}
+
+/// Build a provider by deserializing its options, wrapped like every other provider. Providers
+/// that are deserialized from their options are registered with this as their factory.
+pub fn build_provider<P>(options: serde_json::Value) -> Result<Box<dyn ProviderBackend>>
+        where P: ProviderBackend + DeserializeOwned + 'static {
+    let provider: Wrapped<P> = serde_json::from_value(options)?;
+    Ok(Box::new(provider))
+}
+
+/// Register the factory of a provider under a name, replacing any provider of the same name.
+/// Providers must be registered before the configuration is loaded.
+pub fn register_provider(name: &str, factory: ProviderFactory) {
+    PROVIDERS.write().unwrap().insert(name.to_string(), factory);
+}
+
+/// Get the names of every registered provider, sorted.
+pub fn provider_names() -> Vec<String> {
+    let mut names: Vec<String> = PROVIDERS.read().unwrap().keys().cloned().collect();
+    names.sort();
+    names
+}
+
+/// The form of a ProviderConfig in the configuration.
+#[derive(Serialize, Deserialize)]
+struct RawProviderConfig {
+    provider: String,
+    #[serde(rename="providerOptions")]
+    options: serde_json::Value,
+}
+
+/// A provider built by the registered factory of the provider named in the configuration, along
+/// with the options that it was built from. Clones share the same provider.
+#[derive(Clone)]
+pub struct ProviderConfig {
+    name: String,
+    options: serde_json::Value,
+    backend: Arc<dyn ProviderBackend>,
+}
+
+impl ProviderConfig {
+    /// Build a provider with the factory registered under a name.
+    pub fn new(name: &str, options: serde_json::Value) -> Result<ProviderConfig> {
+        let factory = PROVIDERS.read().unwrap().get(name).copied();
+        let factory = factory.ok_or_else(|| {
+            anyhow!("Unknown provider: {} (expected one of: {})", name, provider_names().join(", "))
+        })?;
+        let backend = factory(options.clone())
+            .map_err(|e| anyhow!("Invalid providerOptions for {}: {}", name, e))?;
+        Ok(ProviderConfig {
+            name: name.to_string(),
+            options: options,
+            backend: Arc::from(backend),
+        })
+    }
+
+    /// Get the name of the provider, as given in the configuration.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Deref for ProviderConfig {
+    type Target = dyn ProviderBackend;
+
+    fn deref(&self) -> &Self::Target {
+        self.backend.as_ref()
+    }
+}
+
+// The options of a provider hold credentials, and are never printed.
+impl fmt::Debug for ProviderConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProviderConfig").field("provider", &self.name).finish()
+    }
+}
+
+impl Serialize for ProviderConfig {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        RawProviderConfig {
+            provider: self.name.clone(),
+            options: self.options.clone(),
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProviderConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = RawProviderConfig::deserialize(deserializer)?;
+        ProviderConfig::new(&raw.provider, raw.options).map_err(de::Error::custom)
     }
 }
@@ -0,0 +1,75 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! A provider hosting no records and never making changes, to try ARES or a configuration
+//! without credentials for a real provider. Every change is skipped by `DryRun` like in dry runs,
+//! and is logged and counted the same way.
+
+// {{{ imports
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+use super::util::{FullDomainName, ProviderBackend, Record, RecordPage, ZoneDomainName};
+use crate::error::ProviderError;
+// }}}
+
+/// Options of the noop provider.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all(serialize="camelCase", deserialize="camelCase"))]
+pub struct NoopProvider {
+    /// Zones hosted by the provider. Domain names in none of them are not found.
+    #[serde(default)]
+    pub zones: Vec<ZoneDomainName>,
+}
+
+#[async_trait::async_trait]
+impl ProviderBackend for NoopProvider {
+    fn kind(&self) -> &'static str {
+        "noop"
+    }
+
+    fn skips_changes(&self) -> bool {
+        true
+    }
+
+    async fn get_zone(&self, domain: &FullDomainName) -> Result<ZoneDomainName> {
+        let domain = domain.trim_end_matches('.');
+        self.zones
+            .iter()
+            .map(|zone| zone.trim_end_matches('.'))
+            .filter(|zone| domain == *zone || domain.ends_with(&format!(".{}", zone)))
+            .max_by_key(|zone| zone.len())
+            .map(str::to_string)
+            .ok_or_else(|| {
+                ProviderError::NotFound(format!("Unable to find DNS Zone for: {}", domain)).into()
+            })
+    }
+
+    async fn create_zone(&self, _zone: &ZoneDomainName) -> Result<()> {
+        Ok(())
+    }
+
+    async fn get_nameservers(&self, _zone: &ZoneDomainName) -> Result<Vec<FullDomainName>> {
+        Ok(vec![])
+    }
+
+    async fn get_records(&self, _domain: &ZoneDomainName, _name: &FullDomainName) ->
+            Result<Vec<Record>> {
+        Ok(vec![])
+    }
+
+    async fn get_records_page(&self, _domain: &ZoneDomainName, _page: Option<&str>) ->
+            Result<RecordPage> {
+        Ok(RecordPage {
+            records: vec![],
+            next: None,
+        })
+    }
+
+    async fn _add_record(&self, _domain: &ZoneDomainName, _record: &Record) -> Result<()> {
+        Ok(())
+    }
+
+    async fn _delete_record(&self, _domain: &ZoneDomainName, _record: &Record) -> Result<()> {
+        Ok(())
+    }
+}
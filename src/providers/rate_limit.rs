@@ -0,0 +1,187 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! A wrapper around a ProviderBackend limiting the rate of calls to the provider with a token
+//! bucket, configured with the `rateLimit` of an AresConfig, so that many Records syncing at once
+//! don't get the credentials of a provider banned. Every call to the provider takes a token;
+//! calls made without a token wait until one is available.
+
+// {{{ imports
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use serde::{Serialize, Deserialize};
+
+use super::util::{Capabilities, Change, FullDomainName, ProviderBackend, Record,
+                  RecordPage, ZoneDomainName};
+// }}}
+
+/// Limit of the rate of calls made to the providers of an AresConfig.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all(serialize="camelCase", deserialize="camelCase"))]
+pub struct RateLimit {
+    /// Number of calls made to the provider per second, on average.
+    pub requests_per_second: f64,
+
+    /// Number of calls that may be made at once after the provider was left idle; defaults to
+    /// the number of calls per second, and to at least one.
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+impl RateLimit {
+    /// Check that calls are allowed at all.
+    pub fn validate(&self) -> Result<()> {
+        if self.requests_per_second.is_nan() || self.requests_per_second <= 0.0 {
+            return Err(anyhow!("Invalid requestsPerSecond: {}", self.requests_per_second))
+        }
+        if self.burst == Some(0) {
+            return Err(anyhow!("Invalid burst: 0"))
+        }
+        Ok(())
+    }
+}
+
+/// Token bucket refilled at the rate of a RateLimit, holding up to its burst.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    /// Build a full bucket.
+    fn new(rate_limit: &RateLimit) -> TokenBucket {
+        let burst = rate_limit
+            .burst
+            .map(f64::from)
+            .unwrap_or(rate_limit.requests_per_second.ceil())
+            .max(1.0);
+        TokenBucket {
+            rate: rate_limit.requests_per_second,
+            burst: burst,
+            tokens: burst,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// Take a token, returning how long to wait before trying again if none is available.
+    fn take(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+/// A ProviderBackend whose calls to the provider are limited once a RateLimit is set with
+/// `set_rate_limit()`. Clones share the same bucket, so that every copy of a provider is limited
+/// together. Only the methods making a single call to the provider take a token; the default
+/// methods of ProviderBackend, such as `apply_changes()`, are built on them, and take a token for
+/// every call or batch they make.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct RateLimited<P> {
+    inner: P,
+    #[serde(skip)]
+    bucket: Arc<Mutex<Option<TokenBucket>>>,
+}
+
+impl<P: ProviderBackend> RateLimited<P> {
+    /// Wait for a token, if the rate of calls is limited.
+    async fn acquire(&self) {
+        loop {
+            let wait = match self.bucket.lock().unwrap().as_mut() {
+                Some(bucket) => bucket.take(),
+                None => None,
+            };
+            match wait {
+                Some(wait) => tokio::time::delay_for(wait).await,
+                None => return,
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: ProviderBackend> ProviderBackend for RateLimited<P> {
+    fn kind(&self) -> &'static str {
+        self.inner.kind()
+    }
+
+    fn validate_options(&self) -> Result<()> {
+        self.inner.validate_options()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_rate_limit(&self, rate_limit: &RateLimit) {
+        *self.bucket.lock().unwrap() = Some(TokenBucket::new(rate_limit));
+    }
+
+    fn set_max_concurrent_requests(&self, max_concurrent_requests: usize) {
+        self.inner.set_max_concurrent_requests(max_concurrent_requests)
+    }
+
+    fn skips_changes(&self) -> bool {
+        self.inner.skips_changes()
+    }
+
+    async fn get_zone(&self, domain: &FullDomainName) -> Result<ZoneDomainName> {
+        self.acquire().await;
+        self.inner.get_zone(domain).await
+    }
+
+    async fn create_zone(&self, zone: &ZoneDomainName) -> Result<()> {
+        self.acquire().await;
+        self.inner.create_zone(zone).await
+    }
+
+    async fn get_nameservers(&self, zone: &ZoneDomainName) -> Result<Vec<FullDomainName>> {
+        self.acquire().await;
+        self.inner.get_nameservers(zone).await
+    }
+
+    async fn get_records(&self, domain: &ZoneDomainName, name: &FullDomainName) ->
+            Result<Vec<Record>> {
+        self.acquire().await;
+        self.inner.get_records(domain, name).await
+    }
+
+    async fn get_records_page(&self, domain: &ZoneDomainName, page: Option<&str>) ->
+            Result<RecordPage> {
+        self.acquire().await;
+        self.inner.get_records_page(domain, page).await
+    }
+
+    async fn _add_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
+        self.acquire().await;
+        self.inner._add_record(domain, record).await
+    }
+
+    async fn _delete_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
+        self.acquire().await;
+        self.inner._delete_record(domain, record).await
+    }
+
+    async fn _update_record(&self, domain: &ZoneDomainName, record: &Record,
+                            update: &Record) -> Result<()> {
+        self.acquire().await;
+        self.inner._update_record(domain, record, update).await
+    }
+
+    async fn _apply_batch(&self, domain: &ZoneDomainName, changes: Vec<Change>) -> Result<()> {
+        self.acquire().await;
+        self.inner._apply_batch(domain, changes).await
+    }
+}
@@ -0,0 +1,239 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! A wrapper around a ProviderBackend retrying calls to the provider that failed transiently,
+//! such as with a server error, with an exponential backoff and jitter. Calls are only retried
+//! when they are known not to have been made, such as when rate limited, unless making them
+//! twice is harmless. Retries are limited by a budget earned by calls, so that an outage of a
+//! provider does not multiply the calls made to it.
+
+// {{{ imports
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+use tracing::warn;
+
+use super::rate_limit::RateLimit;
+use super::util::{Capabilities, Change, FullDomainName, ProviderBackend, Record,
+                  RecordPage, ZoneDomainName};
+use crate::error::ProviderError;
+use crate::metrics::PROVIDER_REQUEST_RETRIES;
+// }}}
+
+/// Number of times a call is made before giving up.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Longest time waited before the first retry of a call.
+const BASE_DELAY: Duration = Duration::from_millis(200);
+
+/// Longest time waited before any retry of a call.
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Largest number of retries that can be saved up while calls succeed.
+const BUDGET_MAX: f64 = 10.0;
+
+/// Number of retries earned by each call, so that at most one call in ten is retried once the
+/// budget is spent.
+const BUDGET_RATIO: f64 = 0.1;
+
+/// Whether a failed call may be retried.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Retry {
+    /// The call was not made, such as when rate limited or unable to connect.
+    Always,
+    /// The call failed transiently, but may have been made.
+    IfIdempotent,
+    /// The call failed in a way that retrying won't fix, such as with invalid credentials.
+    Never,
+}
+
+/// Classify the error of a failed call by the first cause reported by the provider or known to
+/// be transient.
+fn classify(e: &anyhow::Error) -> Retry {
+    for cause in e.chain() {
+        if let Some(e) = cause.downcast_ref::<ProviderError>() {
+            return match e {
+                ProviderError::RateLimited { .. } => Retry::Always,
+                ProviderError::Transient { .. } => Retry::IfIdempotent,
+                _ => Retry::Never,
+            }
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            if e.is_connect() {
+                return Retry::Always
+            }
+            if e.is_timeout() {
+                return Retry::IfIdempotent
+            }
+            match e.status() {
+                Some(status) if status.as_u16() == 429 => return Retry::Always,
+                Some(status) if status.is_server_error() => return Retry::IfIdempotent,
+                _ => {},
+            }
+        }
+    }
+    Retry::Never
+}
+
+/// Retries left to make, shared by every call to a provider.
+#[derive(Debug)]
+struct RetryBudget {
+    tokens: Mutex<f64>,
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        RetryBudget {
+            tokens: Mutex::new(BUDGET_MAX),
+        }
+    }
+}
+
+impl RetryBudget {
+    /// Earn a fraction of a retry for a call.
+    fn deposit(&self) {
+        let mut tokens = self.tokens.lock().unwrap();
+        *tokens = (*tokens + BUDGET_RATIO).min(BUDGET_MAX);
+    }
+
+    /// Spend a retry, returning whether any was left.
+    fn withdraw(&self) -> bool {
+        let mut tokens = self.tokens.lock().unwrap();
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A ProviderBackend whose failed calls to the provider are retried. Clones share the same
+/// budget. Only the methods making a single call to the provider are retried; the default
+/// methods of ProviderBackend, such as `apply_changes()`, are built on them, so that a call is
+/// never made again once it succeeded.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct Retrying<P> {
+    inner: P,
+    #[serde(skip)]
+    budget: Arc<RetryBudget>,
+}
+
+impl<P: ProviderBackend> Retrying<P> {
+    /// Make a call to the provider, retrying it while it fails transiently. Calls that are not
+    /// idempotent are only retried when they are known not to have been made, and rate limited
+    /// calls are retried no sooner than asked by the provider.
+    async fn retry<T, F, Fut>(&self, operation: &str, idempotent: bool, call: F) -> Result<T>
+            where F: Fn() -> Fut, Fut: Future<Output = Result<T>> {
+        let mut delay = BASE_DELAY;
+        let mut attempt = 1;
+        self.budget.deposit();
+        loop {
+            let e = match call().await {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            let retryable = match classify(&e) {
+                Retry::Always => true,
+                Retry::IfIdempotent => idempotent,
+                Retry::Never => false,
+            };
+            if !retryable || attempt >= MAX_ATTEMPTS || !self.budget.withdraw() {
+                return Err(e)
+            }
+            // full jitter, so that calls failing together are not retried together, but never
+            // sooner than asked by the provider
+            let retry_after = match ProviderError::find(&e) {
+                Some(ProviderError::RateLimited { retry_after: Some(retry_after), .. }) => {
+                    *retry_after
+                },
+                _ => Duration::from_secs(0),
+            };
+            let wait = delay.mul_f64(rand::random::<f64>()).max(retry_after);
+            PROVIDER_REQUEST_RETRIES.with_label_values(&[self.inner.kind(), operation]).inc();
+            warn!(provider = self.inner.kind(), operation = operation, attempt = attempt,
+                  wait = ?wait, "Retrying failed call: {}", e);
+            tokio::time::delay_for(wait).await;
+            delay = (delay * 2).min(MAX_DELAY);
+            attempt += 1;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: ProviderBackend> ProviderBackend for Retrying<P> {
+    fn kind(&self) -> &'static str {
+        self.inner.kind()
+    }
+
+    fn validate_options(&self) -> Result<()> {
+        self.inner.validate_options()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn set_rate_limit(&self, rate_limit: &RateLimit) {
+        self.inner.set_rate_limit(rate_limit)
+    }
+
+    fn set_max_concurrent_requests(&self, max_concurrent_requests: usize) {
+        self.inner.set_max_concurrent_requests(max_concurrent_requests)
+    }
+
+    fn skips_changes(&self) -> bool {
+        self.inner.skips_changes()
+    }
+
+    async fn get_zone(&self, domain: &FullDomainName) -> Result<ZoneDomainName> {
+        self.retry("get_zone", true, move || self.inner.get_zone(domain)).await
+    }
+
+    async fn create_zone(&self, zone: &ZoneDomainName) -> Result<()> {
+        self.retry("create_zone", false, move || self.inner.create_zone(zone)).await
+    }
+
+    async fn get_nameservers(&self, zone: &ZoneDomainName) -> Result<Vec<FullDomainName>> {
+        self.retry("get_nameservers", true, move || self.inner.get_nameservers(zone)).await
+    }
+
+    async fn get_records(&self, domain: &ZoneDomainName, name: &FullDomainName) ->
+            Result<Vec<Record>> {
+        self.retry("get_records", true, move || self.inner.get_records(domain, name)).await
+    }
+
+    async fn get_records_page(&self, domain: &ZoneDomainName, page: Option<&str>) ->
+            Result<RecordPage> {
+        self.retry("get_records_page", true, move || self.inner.get_records_page(domain, page))
+            .await
+    }
+
+    async fn _add_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
+        self.retry("add_record", false, move || self.inner._add_record(domain, record)).await
+    }
+
+    async fn _delete_record(&self, domain: &ZoneDomainName, record: &Record) -> Result<()> {
+        self.retry("delete_record", true, move || self.inner._delete_record(domain, record))
+            .await
+    }
+
+    async fn _update_record(&self, domain: &ZoneDomainName, record: &Record,
+                            update: &Record) -> Result<()> {
+        // Records are only updated in place by providers able to, so that an update is a single
+        // call; other providers replace them through `_delete_record()` and `_add_record()`.
+        let idempotent = self.inner.capabilities().updates;
+        self.retry("update_record", idempotent, move || {
+            self.inner._update_record(domain, record, update)
+        }).await
+    }
+
+    async fn _apply_batch(&self, domain: &ZoneDomainName, changes: Vec<Change>) -> Result<()> {
+        self.retry("apply_batch", false, move || {
+            self.inner._apply_batch(domain, changes.clone())
+        }).await
+    }
+}
@@ -3,33 +3,52 @@
 // vim:set foldmethod=marker:
 
 // {{{ imports
+use std::collections::BTreeMap;
 use std::ops::Deref;
+use std::sync::Arc;
 
-use crate::cli::Opts;
+use crate::program_config::AresConfig;
 use crate::providers::{
-    util::{ProviderBackend, FullDomainName, ZoneDomainName, RecordBuilder, RecordType},
+    util::{
+        ProviderBackend, FullDomainName, Policy, RecordBuilder, RecordType, normalize_domain,
+    },
     ProviderConfig,
 };
 
-use futures::{
-    future::FutureExt,
-    pin_mut,
-    select,
-};
-
 use anyhow::{anyhow, Result};
-use k8s_openapi::api::core::v1::{Pod, Node};
-use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::{
+    Resource,
+    api::core::v1::{ConfigMap, Pod, Node},
+    apiextensions_apiserver::pkg::apis::apiextensions::v1::{
+        CustomResourceDefinition, CustomResourceValidation,
+    },
+};
 use kube::{
-    api::{Api, ListParams, WatchEvent, ObjectMeta},
+    api::{Api, ListParams, ObjectMeta},
     Client,
 };
+use kube_runtime::reflector::{ObjectRef, Store};
 use kube_derive::CustomResource;
 use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 // }}}
 
 type Selector = std::collections::HashMap<String, String>;
 
+/// State shared by every RecordValueCollector: the Client shared by the whole process, caches
+/// of the objects most collectors read, kept up to date by reflectors in the controller, the
+/// configured providers, and Clients for the additional clusters of the AresConfig being synced.
+#[derive(Clone)]
+pub struct CollectorContext {
+    pub client: Client,
+    pub pods: Store<Pod>,
+    pub nodes: Store<Node>,
+    pub records: Store<Record>,
+    pub config: Vec<Arc<AresConfig>>,
+    pub clusters: Vec<Client>,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug)]
 enum ExpressionOperator {
     In,
@@ -52,36 +71,31 @@ impl Expression {
     /// of Lables and Expressions *together* define what should be returned.
     pub fn match_value(&self, input: Option<&String>) -> bool {
         match &self.operator {
-            In => {
+            ExpressionOperator::In => {
                 input
                     .and_then(|x| Some(self.values.contains(x)))
                     .unwrap_or(false)
             },
-            NotIn => {
+            ExpressionOperator::NotIn => {
                 // must exist, see:
                 // https://kubernetes.io/docs/concepts/overview/working-with-objects/labels/#resources-that-support-set-based-requirements
                 input
                     .and_then(|x| Some(!self.values.contains(x)))
                     .unwrap_or(false)
             },
-            Exists => {
+            ExpressionOperator::Exists => {
                 input.is_some()
             },
-            DoesNotExist => {
+            ExpressionOperator::DoesNotExist => {
                 input.is_none()
             }
         }
     }
 }
 
-pub enum RecordChange<'a> {
-    Add(&'a String),
-    Remove(&'a String)
-}
-
 /// `RecordValueCollector` is a trait representing a function that collects values from a dynamic
-/// source (the variant of the enum RecordValueFrom), or watches over a set of values and
-/// calls a function with the changes that should be made to the relevant records.
+/// source (the variant of the enum RecordValueFrom), and declares which objects the values are
+/// collected from, so that the controller can sync the relevant records when those objects change.
 ///
 /// Kubernetes specifies
 /// [here](https://kubernetes.io/docs/concepts/overview/working-with-objects/labels)
@@ -90,35 +104,24 @@ pub enum RecordChange<'a> {
 /// implementing a value acquirer.
 #[async_trait::async_trait]
 pub trait RecordValueCollector: Send + Sync {
-    /// Return a default ListParams object. This should be overridden per-instance of
-    /// RecordValueCollector if the matchLabels arguments can be passed through ListParams, as
-    /// otherwise they will need to be parsed manually after acquiring all matching resources.
-    fn get_list_parameters(&self) -> ListParams {
-        ListParams::default()
-    }
-
     /// Return the values that should be records for a RecordValueCollector. The ObjectMeta
     /// passed to the function should be the ObjectMeta of the Record. This is so namespaced
-    /// attributes have an object with which to tie their reference.
-
-    async fn get_values(&self, meta: &ObjectMeta) -> Result<Vec<String>>;
-
-    /// Synchronize the remote Records with the correct Values. This should be run once, when
-    /// initializing a RecordValueCollector, as further requests will introduce a large amount
-    /// of traffic to the backend provider.
-    ///
-    /// This command can also be run in a timed loop during watch_values when a watcher over
-    /// a resource is not available, but for the aforementioned reasons this is not recommended.
-    async fn sync(&self, meta: &ObjectMeta, provider_config: &ProviderConfig,
-                  record_builder: &mut RecordBuilder) -> Result<()>;
-
-    /// Ensure by watching relevant objects (such as Pods) have a Record for every instance, and
-    /// that if an object no longer has a connection to the relevant record (such as a Pod no
-    /// longer existing on a Node) that the Record is removed. The ObjectMeta passed to the
-    /// function should be the ObjectMeta of the Record. This is so namespaced attributes have an
-    /// object with which to tie their reference.
-    async fn watch_values(&self, meta: &ObjectMeta, provider_config: &ProviderConfig,
-                          record_builder: &mut RecordBuilder) -> Result<Record>;
+    /// attributes have an object with which to tie their reference. Objects should be read
+    /// from the caches of the CollectorContext where possible.
+    async fn get_values(&self, ctx: &CollectorContext, meta: &ObjectMeta) ->
+            Result<Vec<String>>;
+
+    /// Synchronize the remote Records with the correct Values, returning the values that were
+    /// synced. This is run by the controller every time the Record is reconciled, so it must be
+    /// idempotent.
+    async fn sync(&self, ctx: &CollectorContext, meta: &ObjectMeta,
+                  provider_config: &ProviderConfig, record_builder: &mut RecordBuilder) ->
+            Result<Vec<String>>;
+
+    /// Check whether a change to an object (such as a Pod) may change the values collected for
+    /// the Record. The first ObjectMeta passed to the function should be the ObjectMeta of the
+    /// Record, and `kind` is the kind of the changed object, such as `Pod`.
+    fn watches_object(&self, meta: &ObjectMeta, kind: &str, object: &ObjectMeta) -> bool;
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -129,279 +132,345 @@ pub struct PodSelector {
     match_expressions: Option<Expressions>,
 }
 
-#[async_trait::async_trait]
-impl RecordValueCollector for PodSelector {
-    /// Create a set of ListParams based on the match_labels values passed to the Record
-    /// resource. List parameters are used to slim down the amount of values returned by
-    /// the Kubernetes API, but come with the potential downside of relying on the Kubernetes
-    /// API to filter by label.
-    fn get_list_parameters(&self) -> ListParams {
-        let mut list_params = ListParams::default();
-        if let Some(match_labels) = &self.match_labels {
-            for (label, value) in match_labels {
-                list_params = list_params.labels(format!("{}={}", label, value).as_str());
-            }
+/// Add the external IP addresses of a Node to a list of addresses, skipping addresses that
+/// have already been seen. This is not likely given that each Node is only read once, but it may
+/// be possible that multiple Nodes share a floating IP for some reason. This is for the most
+/// part a sanity check, and will not be practical for most instances.
+fn add_external_ips(node: Node, ips: &mut Vec<String>) -> Result<()> {
+    let node_addresses = node
+        .status
+        .and_then(|status| status.addresses)
+        .ok_or(anyhow!("Unable to get node.status.addresses"))?;
+    for node_ip in node_addresses.iter().filter(|addr| addr.type_ == "ExternalIP") {
+        if !ips.contains(&node_ip.address) {
+            ips.push(node_ip.address.clone());
         }
-        list_params
     }
+    Ok(())
+}
 
-    /// Query IP addresses from Nodes that are running Pods. The matchLabels field will be passed
-    /// to the Kubernetes server through ListParams, and the matchExpressions field will be run
-    /// through the Expression::match_value() function.
-    async fn get_values(&self, meta: &ObjectMeta) -> Result<Vec<String>> {
-        let list_params = self.get_list_parameters();
-
-        let pods: Api<Pod> = Api::namespaced(Client::try_default().await?,
-                                             meta
-                                                .namespace
-                                                .as_ref()
-                                                .ok_or(anyhow!("Missing meta.namespace"))?
-                                                .as_str());
-        let nodes: Api<Node> = Api::all(Client::try_default().await?);
-
-        let pod_list = pods.list(&list_params).await?;
-
-        let mut ips: Vec<String> = Vec::with_capacity(pod_list.items.len());
-        let mut node_names: Vec<String> = Vec::with_capacity(pod_list.items.len());
-
-        'outer: for pod in pods.list(&list_params).await? {
-            let pod_labels = pod
-                .metadata
-                .labels
-                .ok_or(anyhow!("Unable to get pod.metadata.lables"))?;
-            if let Some(match_expressions) = &self.match_expressions {
-                for expr in match_expressions {
-                        let value = pod_labels.get(&expr.key);
-                        // invalid match, we don't want this pod; by the Kubernetes spec, we only
-                        // want things that match BOTH all values AND all expressions.
-                        if !expr.match_value(value) {
-                            continue 'outer;
-                        }
-                }
-            }
-            let node_name = pod
-                .spec
-                .and_then(|spec| spec.node_name)
-                .ok_or(anyhow!("Unable to get pod.spec.node_name"))?;
-            if node_names.contains(&node_name) { // do not re-query a node already seen
+impl PodSelector {
+    /// Get the names of the Nodes running Pods that match the selector, without duplicates.
+    /// Pods that have not yet been scheduled do not have a Node, and are skipped.
+    fn node_names(&self, pods: Vec<Pod>) -> Vec<String> {
+        let mut node_names: Vec<String> = Vec::with_capacity(pods.len());
+        for pod in pods {
+            if !self.matches_labels(&pod.metadata.labels.unwrap_or_default()) {
                 continue;
             }
-            let node = nodes.get(&node_name).await?;
-            node_names.push(node_name);
-            let node_addresses = node
-                .status
-                .and_then(|status| status.addresses)
-                .ok_or(anyhow!("Unable to get node.status.addresses"))?;
-            for node_ip in node_addresses.iter().filter(|addr| addr.type_ == "ExternalIP") {
-                if !ips.contains(&node_ip.address) {
-                    // do not add the same IP if it has been seen before; this is not likely given
-                    // the node_names de-duplication above, but it may be possible that multiple
-                    // nodes share a floating IP for some reason. this is for the most part a
-                    // sanity check, and will not be practical for most instances.
-                    ips.push(node_ip.address.clone());
+            if let Some(node_name) = pod.spec.and_then(|spec| spec.node_name) {
+                if !node_names.contains(&node_name) {
+                    node_names.push(node_name);
                 }
             }
         }
+        node_names
+    }
+
+    /// Check whether a set of labels matches every value of matchLabels and every expression of
+    /// matchExpressions.
+    fn matches_labels(&self, labels: &BTreeMap<String, String>) -> bool {
+        let labels_match = self
+            .match_labels
+            .as_ref()
+            .map(|match_labels| match_labels
+                .iter()
+                .all(|(label, value)| labels.get(label) == Some(value)))
+            .unwrap_or(true);
+        // by the Kubernetes spec, we only want things that match BOTH all values AND all
+        // expressions.
+        let expressions_match = self
+            .match_expressions
+            .as_ref()
+            .map(|match_expressions| match_expressions
+                .iter()
+                .all(|expr| expr.match_value(labels.get(&expr.key))))
+            .unwrap_or(true);
+        labels_match && expressions_match
+    }
+}
+
+#[async_trait::async_trait]
+impl RecordValueCollector for PodSelector {
+    /// Query IP addresses from Nodes that are running Pods. Both Pods and Nodes are read from
+    /// the caches of the CollectorContext, and Pods are matched against both the matchLabels
+    /// and matchExpressions fields. Pods in the same namespace of each additional cluster of the
+    /// CollectorContext are matched as well, and are read directly from that cluster.
+    async fn get_values(&self, ctx: &CollectorContext, meta: &ObjectMeta) ->
+            Result<Vec<String>> {
+        let namespace = meta.namespace.as_ref().ok_or(anyhow!("Missing meta.namespace"))?;
+        let pod_list: Vec<Pod> = ctx
+            .pods
+            .state()
+            .into_iter()
+            .filter(|pod| pod.metadata.namespace.as_ref() == Some(namespace))
+            .collect();
+
+        let mut ips: Vec<String> = Vec::with_capacity(pod_list.len());
+        for node_name in self.node_names(pod_list) {
+            let node = ctx
+                .nodes
+                .get(&ObjectRef::new(&node_name))
+                .ok_or(anyhow!("Unable to find Node: {}", node_name))?;
+            add_external_ips(node, &mut ips)?;
+        }
+
+        for cluster in ctx.clusters.iter() {
+            let pods: Api<Pod> = Api::namespaced(cluster.clone(), namespace);
+            let nodes: Api<Node> = Api::all(cluster.clone());
+            let pod_list = pods.list(&ListParams::default()).await?.items;
+            for node_name in self.node_names(pod_list) {
+                add_external_ips(nodes.get(&node_name).await?, &mut ips)?;
+            }
+        }
 
         Ok(ips)
     }
 
-    async fn sync(&self, meta: &ObjectMeta, provider_config: &ProviderConfig,
-                  record_builder: &mut RecordBuilder) -> Result<()> {
-        let values = self.get_values(meta).await?;
+    async fn sync(&self, ctx: &CollectorContext, meta: &ObjectMeta,
+                  provider_config: &ProviderConfig, record_builder: &mut RecordBuilder) ->
+            Result<Vec<String>> {
+        let values = self.get_values(ctx, meta).await?;
         let provider: &dyn ProviderBackend = provider_config.deref();
         provider.sync_records(record_builder, &values).await?;
-        Ok(())
+        Ok(values)
     }
 
-    /// Watch over changes to all Pods to determine whether or not a new IP address has been
-    /// added or whether an old IP address no longer hosts an instance of the pod.
-    async fn watch_values(&self, meta: &ObjectMeta, provider_config: &ProviderConfig,
-                          record_builder: &mut RecordBuilder) -> Result<Record> {
-        let mut current_values = self.get_values(meta).await?;
-        current_values.sort();
+    /// Pods in the namespace of the Record with matching labels can change which Nodes the
+    /// values are collected from.
+    fn watches_object(&self, meta: &ObjectMeta, kind: &str, object: &ObjectMeta) -> bool {
+        kind == Pod::KIND
+            && object.namespace == meta.namespace
+            && self.matches_labels(object.labels.as_ref().unwrap_or(&BTreeMap::new()))
+    }
+}
 
-        let record_name: &str = meta.name.as_ref().ok_or(anyhow!("Missing record.meta.name"))?;
-        let record_namespace: &str = meta
-            .namespace
-            .as_ref()
-            .ok_or(anyhow!("Missing record.meta.namespace"))?;
-        let record_list_params = ListParams::default();
-        let records: Api<Record> = Api::namespaced(Client::try_default().await?,
-                                                   record_namespace);
-        let mut record_watcher = records.watch(&record_list_params, "0").await?.boxed().fuse();
-
-        let list_params = self.get_list_parameters();
-        let pods: Api<Pod> = Api::all(Client::try_default().await?);
-        let mut pod_watcher = pods.watch(&list_params, "0").await?.boxed().fuse();
-
-        loop {
-            #[derive(Debug)]
-            enum Event {
-                Pod(WatchEvent<Pod>),
-                Record(WatchEvent<Record>),
+/// Annotation read from a Node by the HostKeySelector when no other annotation is given.
+static HOST_KEY_ANNOTATION: &str = "ares.syntixi.io/ssh-host-keys";
+
+/// Convert an OpenSSH public key line, such as the contents of `ssh_host_ed25519_key.pub`, into
+/// the value of an SSHFP record. Only the SHA-256 fingerprint type is generated, as SHA-1
+/// fingerprints are no longer trusted by OpenSSH.
+fn sshfp_from_public_key(line: &str) -> Result<String> {
+    let mut parts = line.split_whitespace();
+    let key_type = parts.next().ok_or(anyhow!("Missing key type in host key"))?;
+    let key_data = parts.next().ok_or(anyhow!("Missing key data in host key"))?;
+    // https://www.iana.org/assignments/dns-sshfp-rr-parameters
+    let algorithm = match key_type {
+        "ssh-rsa" => 1,
+        "ssh-dss" => 2,
+        "ecdsa-sha2-nistp256" | "ecdsa-sha2-nistp384" | "ecdsa-sha2-nistp521" => 3,
+        "ssh-ed25519" => 4,
+        "ssh-ed448" => 6,
+        other => return Err(anyhow!("Unsupported host key type: {}", other)),
+    };
+    let fingerprint: String = Sha256::digest(&base64::decode(key_data)?)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok(format!("{} 2 {}", algorithm, fingerprint))
+}
+
+/// `HostKeySelector` publishes SSHFP records for the SSH host keys of a machine, so that SSH
+/// clients using `VerifyHostKeyDNS` can verify the machine without a known_hosts entry. Keys are
+/// read either from every value of a ConfigMap in the namespace of the Record, or from an
+/// annotation on a Node; in both cases, one OpenSSH public key is expected per line.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct HostKeySelector {
+    #[serde(rename="configMapName")]
+    config_map_name: Option<String>,
+    #[serde(rename="nodeName")]
+    node_name: Option<String>,
+    annotation: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl RecordValueCollector for HostKeySelector {
+    /// Read public keys from the configured source and convert them to SSHFP values.
+    async fn get_values(&self, ctx: &CollectorContext, meta: &ObjectMeta) ->
+            Result<Vec<String>> {
+        let keys: String = match (&self.config_map_name, &self.node_name) {
+            (Some(config_map_name), _) => {
+                let config_maps: Api<ConfigMap> = Api::namespaced(
+                    ctx.client.clone(),
+                    meta
+                        .namespace
+                        .as_ref()
+                        .ok_or(anyhow!("Missing meta.namespace"))?
+                        .as_str());
+                config_maps
+                    .get(config_map_name)
+                    .await?
+                    .data
+                    .unwrap_or_default()
+                    .values()
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            },
+            (None, Some(node_name)) => {
+                let annotation = self
+                    .annotation
+                    .as_ref()
+                    .map(String::as_str)
+                    .unwrap_or(HOST_KEY_ANNOTATION);
+                ctx
+                    .nodes
+                    .get(&ObjectRef::new(node_name))
+                    .ok_or(anyhow!("Unable to find Node: {}", node_name))?
+                    .metadata
+                    .annotations
+                    .and_then(|annotations| annotations.get(annotation).cloned())
+                    .ok_or(anyhow!("Unable to get node.metadata.annotations.{}", annotation))?
+            },
+            (None, None) => return Err(anyhow!("Missing configMapName or nodeName")),
+        };
+
+        let mut values: Vec<String> = vec![];
+        for line in keys.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
             }
+            let value = sshfp_from_public_key(line)?;
+            if !values.contains(&value) {
+                values.push(value);
+            }
+        }
+        Ok(values)
+    }
 
-            let event: Event = select! {
-                pod_status_result = pod_watcher.try_next() => {
-                    Event::Pod(match pod_status_result {
-                        Ok(v) => match v {
-                            Some(v) => v,
-                            None => return Err(anyhow!("Found None")),
-                        },
-                        Err(e) => return Err(e.into()),
-                    })
-                },
-                record_status_result = record_watcher.try_next() => {
-                    Event::Record(match record_status_result {
-                        Ok(v) => match v {
-                            Some(v) => v,
-                            None => return Err(anyhow!("Found None")),
-                        },
-                        Err(e) => return Err(e.into()),
-                    })
-                },
-            };
-
-            match event {
-                Event::Pod(pod_status) => {
-                    match pod_status {
-                        | WatchEvent::Added(_)
-                        | WatchEvent::Deleted(_) => {
-                            // Regardless of the event, we need to re-sync the list of Pods and
-                            // call RecordChange on any added/removed values. We do this
-                            // generically rather than determining the IP that a Pod exists on,
-                            // because multiple Pods can exist on the same machine. If we were to
-                            // indiscriminantly remove the IP address, this could lead to moving
-                            // from two Pods to one, but the IP still being removed.
-                            let mut new_values = self.get_values(&meta).await?;
-                            new_values.sort();
-                            let (mut left_index, mut right_index) = (0, 0);
-                            loop {
-                                // Check if old_values differs from new_values. If new_values
-                                // does not contain the value at the current index, it was removed.
-                                // If old_values does not contain the value at the current index,
-                                // it was added.  We do not have a guarantee that multiple
-                                // addresses were not added at once, and while I don't think it's
-                                // possible, better safe than sorry.
-                                let ip_left = current_values.get(left_index);
-                                let ip_right = new_values.get(right_index);
-                                let ev = match (ip_left, ip_right) {
-                                    (None, None) => {
-                                        break
-                                    },
-                                    (Some(left), None) => {
-                                        // Old value exists, new value does not. Increment left
-                                        // index and delete record.
-                                        left_index += 1;
-                                        Some(RecordChange::Remove(left))
-                                    },
-                                    (None, Some(right)) => {
-                                        // New value exists, old value does not. Increment right
-                                        // index and add record.
-                                        Some(RecordChange::Add(right))
-                                    },
-                                    (Some(left), Some(right)) => {
-                                        // If the value at the left is less than the value at the
-                                        // right, that means that when sorted, a similar value on
-                                        // the right was not found. Similarly, if a value at the
-                                        // left is greater than the value at the right, a similar
-                                        // value on the left was not found.  Because the values
-                                        // on the left are "old" records, matching values on the
-                                        // right not being found means that those records should
-                                        // be removed. Because the values on the right are "new"
-                                        // records, matching values on the left not being found
-                                        // means that those records should be created.
-                                        if left < right {
-                                            // See above; old exists, new doesn't
-                                            left_index += 1;
-                                            Some(RecordChange::Remove(left))
-                                        } else if left > right {
-                                            // See above; new exists, old doesn't
-                                            right_index += 1;
-                                            Some(RecordChange::Add(right))
-                                        } else {
-                                            // Both indexes are the same. Increment each index by
-                                            // one, and do not produce an event.
-                                            left_index += 1;
-                                            right_index += 1;
-                                            None
-                                        }
-                                    }
-                                }; // let ev
-                                if let Some(event) = ev {
-                                    // pass
-                                    let provider: &dyn ProviderBackend = provider_config.deref();
-                                    match event {
-                                        RecordChange::Add(value) => {
-                                            let new_value = value.clone();
-                                            let record = record_builder
-                                                .clone()
-                                                .value(new_value)
-                                                .ttl(1) // ::TODO:: custom TTL
-                                                .try_build()?;
-                                            provider.add_record(&record.zone, &record).await?;
-                                        },
-                                        RecordChange::Remove(value) => {
-                                            let new_value = value.clone();
-                                            let record = record_builder
-                                                .clone()
-                                                .value(new_value)
-                                                .ttl(1) // ::TODO:: custom TTL
-                                                .try_build()?;
-                                            provider.delete_record(&record.zone, &record).await?;
-                                        }
-                                    }
-                                }
-                            }
-                            current_values = new_values;
-                        },
-                        | WatchEvent::Modified(_)
-                        | WatchEvent::Bookmark(_) => {
-                            // Do nothing. Pods being Modified can't change Nodes.
-                        },
-                        WatchEvent::Error(e) => {
-                            // We got an error when watching. While this shouldn't happen often,
-                            // it should be bubbled up and handled by the controller, which will
-                            // then restart the watcher.
-                            return Err(e.into())
-                        },
-                    }
-                },
-                Event::Record(record_status) => {
-                    match record_status {
-                        WatchEvent::Added(new) => {
-                            // verify that live record matches the current record
-                            if new.metadata.uid == meta.uid {
-                                if (new.metadata.resource_version != meta.resource_version) {
-                                    // The record was deleted in-between starting watch_values
-                                    // and starting the actual watcher.
-                                    return Ok(new)
-                                }
-                            }
-                        },
-                        | WatchEvent::Bookmark(_) => {
-                            // do nothing
-                        },
-                        WatchEvent::Modified(modified) => {
-                            if modified.metadata.uid == meta.uid {
-                                return Ok(modified)
-                            }
-                        },
-                        WatchEvent::Deleted(deleted) => {
-                            if deleted.metadata.uid == meta.uid {
-                                return Err(anyhow!("Record deleted"));
-                            }
-                        },
-                        WatchEvent::Error(e) => {
-                            return Err(e.into())
-                        },
-                    }
-                },
+    async fn sync(&self, ctx: &CollectorContext, meta: &ObjectMeta,
+                  provider_config: &ProviderConfig, record_builder: &mut RecordBuilder) ->
+            Result<Vec<String>> {
+        let values = self.get_values(ctx, meta).await?;
+        let provider: &dyn ProviderBackend = provider_config.deref();
+        provider.sync_records(record_builder, &values).await?;
+        Ok(values)
+    }
+
+    /// The ConfigMap or the Node that host keys are read from.
+    fn watches_object(&self, meta: &ObjectMeta, kind: &str, object: &ObjectMeta) -> bool {
+        match (&self.config_map_name, &self.node_name) {
+            (Some(config_map_name), _) => {
+                kind == ConfigMap::KIND
+                    && object.namespace == meta.namespace
+                    && object.name.as_ref() == Some(config_map_name)
+            },
+            (None, Some(node_name)) => {
+                kind == Node::KIND && object.name.as_ref() == Some(node_name)
+            },
+            (None, None) => false,
+        }
+    }
+}
+
+/// `DelegationSelector` delegates a subdomain to a child zone, by publishing NS records for the
+/// nameservers of the child zone in the parent zone. The child zone must be hosted by the
+/// provider of an AresConfig with a Selector matching the zone.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct DelegationSelector {
+    zone: FullDomainName,
+}
+
+#[async_trait::async_trait]
+impl RecordValueCollector for DelegationSelector {
+    /// Query the nameservers of the child zone from the first provider hosting the zone.
+    async fn get_values(&self, ctx: &CollectorContext, _meta: &ObjectMeta) ->
+            Result<Vec<String>> {
+        let zone = normalize_domain(&self.zone)?;
+        for ares in ctx.config.iter().filter(|ares| ares.matches_selector(&zone)) {
+            for provider_config in ares.providers() {
+                let provider: &dyn ProviderBackend = provider_config.deref();
+                match provider.get_nameservers(&zone).await {
+                    Ok(nameservers) if !nameservers.is_empty() => return Ok(nameservers),
+                    _ => continue,
+                }
             }
         }
+        Err(anyhow!("Unable to find nameservers for zone: {}", zone))
+    }
+
+    async fn sync(&self, ctx: &CollectorContext, meta: &ObjectMeta,
+                  provider_config: &ProviderConfig, record_builder: &mut RecordBuilder) ->
+            Result<Vec<String>> {
+        if record_builder.record_type != RecordType::NS {
+            return Err(anyhow!("Delegation requires a Record of type NS"));
+        }
+        let values = self.get_values(ctx, meta).await?;
+        let provider: &dyn ProviderBackend = provider_config.deref();
+        provider.sync_records(record_builder, &values).await?;
+        Ok(values)
+    }
+
+    /// Nameservers are read from providers rather than Kubernetes objects, and are synced
+    /// periodically instead.
+    fn watches_object(&self, _meta: &ObjectMeta, _kind: &str, _object: &ObjectMeta) -> bool {
+        false
+    }
+}
+
+/// `RecordRefSelector` copies the values of another Record, so that records derived from the
+/// same source stay in lockstep. The referenced Record must be of the same type, and can't itself
+/// reference a Record.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct RecordRefSelector {
+    name: String,
+    /// Namespace of the referenced Record; defaults to the namespace of the Record.
+    namespace: Option<String>,
+}
 
-        records.get(record_name.as_ref()).await.map_err(|x| x.into()) // cycle refresh
+impl RecordRefSelector {
+    /// Get the namespace of the referenced Record.
+    fn namespace(&self, meta: &ObjectMeta) -> Option<String> {
+        self.namespace.clone().or_else(|| meta.namespace.clone())
+    }
+
+    /// Get the referenced Record from the cache of the CollectorContext.
+    fn target(&self, ctx: &CollectorContext, meta: &ObjectMeta) -> Result<Record> {
+        let namespace = self.namespace(meta).ok_or(anyhow!("Missing meta.namespace"))?;
+        ctx.records
+            .get(&ObjectRef::new(&self.name).within(&namespace))
+            .ok_or(anyhow!("Unable to find Record: {}/{}", namespace, self.name))
+    }
+}
+
+#[async_trait::async_trait]
+impl RecordValueCollector for RecordRefSelector {
+    /// Copy the values of the referenced Record, collecting them if the Record has a valueFrom.
+    async fn get_values(&self, ctx: &CollectorContext, meta: &ObjectMeta) ->
+            Result<Vec<String>> {
+        let target = self.target(ctx, meta)?;
+        match &target.spec.value_from {
+            // References are only followed once, so that references can't form a cycle.
+            Some(RecordValueFrom::RecordRefSelector(_)) => {
+                Err(anyhow!("Record {} references another Record", self.name))
+            },
+            Some(collector) => collector.get_values(ctx, &target.metadata).await,
+            None => Ok(target.spec.value.clone().unwrap_or_default()),
+        }
+    }
+
+    async fn sync(&self, ctx: &CollectorContext, meta: &ObjectMeta,
+                  provider_config: &ProviderConfig, record_builder: &mut RecordBuilder) ->
+            Result<Vec<String>> {
+        let target = self.target(ctx, meta)?;
+        if target.spec.type_ != record_builder.record_type {
+            return Err(anyhow!("Record {} is of type {:?} rather than {:?}", self.name,
+                               target.spec.type_, record_builder.record_type));
+        }
+        let values = self.get_values(ctx, meta).await?;
+        let provider: &dyn ProviderBackend = provider_config.deref();
+        provider.sync_records(record_builder, &values).await?;
+        Ok(values)
+    }
+
+    /// Changes to the referenced Record can change its values. Changes to the objects the values
+    /// of the referenced Record are collected from are picked up when the referenced Record is
+    /// synced, as only the referenced Record watches those objects.
+    fn watches_object(&self, meta: &ObjectMeta, kind: &str, object: &ObjectMeta) -> bool {
+        kind == Record::KIND
+            && object.name.as_ref() == Some(&self.name)
+            && object.namespace == self.namespace(meta)
     }
 }
 
@@ -410,17 +479,288 @@ trait_enum::trait_enum! {
     pub enum RecordValueFrom: RecordValueCollector {
         #[serde(rename = "podSelector")]
         PodSelector,
+        #[serde(rename = "hostKeys")]
+        HostKeySelector,
+        #[serde(rename = "delegation")]
+        DelegationSelector,
+        #[serde(rename = "recordRef")]
+        RecordRefSelector,
     }
 }
 
 #[derive(CustomResource, Clone, Deserialize, Serialize, Debug)]
-#[kube(group="syntixi.io", version="v1alpha1", namespaced)]
+#[kube(group="syntixi.io", version="v1alpha1", namespaced, status="RecordStatus")]
+#[kube(shortname="rec")]
+#[kube(printcolumn=r#"{"name":"FQDN", "type":"string", "jsonPath":".spec.fqdn"}"#)]
+#[kube(printcolumn=r#"{"name":"Type", "type":"string", "jsonPath":".spec.type"}"#)]
+#[kube(printcolumn=r#"{"name":"TTL", "type":"integer", "jsonPath":".spec.ttl"}"#)]
+#[kube(printcolumn=r#"{"name":"Ready", "type":"boolean", "jsonPath":".status.ready"}"#)]
 pub struct RecordSpec {
     pub fqdn: FullDomainName,
-    pub ttl: u32,
+    /// TTL of the records, defaulting to the default TTL of the Zone of the Record, if any.
+    pub ttl: Option<u32>,
     #[serde(rename = "type")]
     pub type_: RecordType,
     pub value: Option<Vec<String>>,
     #[serde(rename = "valueFrom")]
     pub value_from: Option<RecordValueFrom>,
+    /// Also manage PTR records for A and AAAA values, when the provider hosts the matching
+    /// `in-addr.arpa` or `ip6.arpa` zone.
+    #[serde(default)]
+    pub reverse: bool,
+    /// Name of the AresConfig to sync the Record with, rather than every AresConfig with a
+    /// Selector matching the FQDN.
+    #[serde(rename = "providerRef")]
+    pub provider_ref: Option<String>,
+    /// Changes that ARES may make to the records of the Record, overriding the policy of the
+    /// AresConfig.
+    pub policy: Option<Policy>,
+    /// Proxy traffic to the records through the provider, for providers that proxy traffic such
+    /// as CloudFlare. Existing records keep their proxied state if not given.
+    pub proxied: Option<bool>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, Default)]
+pub struct RecordStatus {
+    /// The FQDN of the records last synced to the provider.
+    pub fqdn: Option<FullDomainName>,
+    /// The type of the records last synced to the provider.
+    #[serde(rename = "type")]
+    pub type_: Option<RecordType>,
+    /// The number of consecutive failed syncs, if the last sync failed.
+    pub failures: Option<u32>,
+    /// The result of the last sync with each provider matching the FQDN.
+    pub providers: Option<Vec<ProviderStatus>>,
+    /// The changes waiting for approval, for providers that require approval.
+    pub plan: Option<RecordPlan>,
+    /// The latest values synced for each of the last generations of the Record, oldest first.
+    pub history: Option<Vec<AppliedValues>>,
+    /// Whether the records were synced to every provider by the last sync.
+    pub ready: Option<bool>,
+    /// The error of the most recent failed sync, which is kept once the Record is synced again.
+    #[serde(rename = "lastError", skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<LastError>,
+}
+
+/// The error of a failed sync of a Record.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct LastError {
+    /// The error of the sync.
+    pub message: String,
+    /// When the sync failed, in RFC 3339 format.
+    pub time: String,
+}
+
+/// The values last synced to the provider for a generation of a Record.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct AppliedValues {
+    /// The generation of the Record that the values were synced for.
+    pub generation: i64,
+    /// The values synced to the provider.
+    pub values: Vec<String>,
+}
+
+/// Changes to the records of a Record that have to be approved before they are made.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct RecordPlan {
+    /// The hash of the changes, which the `ares.syntixi.io/approve` annotation must be set to.
+    pub hash: String,
+    /// A description of every change.
+    pub changes: Vec<String>,
+}
+
+/// The result of the last sync of a Record with a single provider.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct ProviderStatus {
+    /// The name of the AresConfig of the provider.
+    pub name: String,
+    /// Whether the records were synced to the provider.
+    pub synced: bool,
+    /// The error of the last sync, if the sync failed.
+    pub error: Option<String>,
+    /// The provider in the failover chain that the records were last written to, either
+    /// "primary" or "standby-N".
+    #[serde(rename = "servedBy")]
+    pub served_by: Option<String>,
+    /// A problem with the Record that the provider worked around, such as a TTL that the
+    /// provider does not accept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+impl Record {
+    /// Normalize the FQDN of the Record with `normalize_domain()`. This should be done before
+    /// matching the Record against Selectors or passing it to a provider.
+    pub fn normalized(mut self) -> Result<Record> {
+        self.spec.fqdn = normalize_domain(&self.spec.fqdn)?;
+        Ok(self)
+    }
+}
+
+/// Schema of a label selector, as used by `podSelector`.
+fn selector_schema() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "matchLabels": {
+                "type": "object",
+                "additionalProperties": {"type": "string"},
+            },
+            "matchExpressions": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["key", "operator"],
+                    "properties": {
+                        "key": {"type": "string"},
+                        "operator": {
+                            "type": "string",
+                            "enum": ["In", "NotIn", "Exists", "DoesNotExist"],
+                        },
+                        "values": {"type": "array", "items": {"type": "string"}},
+                    },
+                },
+            },
+        },
+        "anyOf": [
+            {"required": ["matchLabels"]},
+            {"required": ["matchExpressions"]},
+        ],
+    })
+}
+
+/// Schema of the spec of a Record, rejecting invalid Records when they are applied rather than
+/// when they are synced.
+fn spec_schema() -> Value {
+    json!({
+        "type": "object",
+        "required": ["fqdn", "type"],
+        "properties": {
+            "fqdn": {"type": "string", "minLength": 1},
+            "type": {
+                "type": "string",
+                "enum": ["A", "AAAA", "ALIAS", "CNAME", "MX", "NS", "PTR", "SOA", "SRV", "SSHFP",
+                         "TXT", "DNSKEY", "DS", "NSEC", "NSEC3", "NSEC3PARAM", "RRSIG"],
+            },
+            "ttl": {"type": "integer", "minimum": 1},
+            "reverse": {"type": "boolean"},
+            "providerRef": {"type": "string"},
+            "policy": {"type": "string", "enum": ["sync", "upsert-only", "create-only"]},
+            "proxied": {"type": "boolean"},
+            "value": {"type": "array", "items": {"type": "string"}},
+            "valueFrom": {
+                "type": "object",
+                "properties": {
+                    "podSelector": selector_schema(),
+                    "hostKeys": {
+                        "type": "object",
+                        "properties": {
+                            "configMapName": {"type": "string"},
+                            "nodeName": {"type": "string"},
+                            "annotation": {"type": "string"},
+                        },
+                        "oneOf": [
+                            {"required": ["configMapName"]},
+                            {"required": ["nodeName"]},
+                        ],
+                    },
+                    "delegation": {
+                        "type": "object",
+                        "required": ["zone"],
+                        "properties": {
+                            "zone": {"type": "string"},
+                        },
+                    },
+                    "recordRef": {
+                        "type": "object",
+                        "required": ["name"],
+                        "properties": {
+                            "name": {"type": "string"},
+                            "namespace": {"type": "string"},
+                        },
+                    },
+                },
+                "oneOf": [
+                    {"required": ["podSelector"]},
+                    {"required": ["hostKeys"]},
+                    {"required": ["delegation"]},
+                    {"required": ["recordRef"]},
+                ],
+            },
+        },
+        "oneOf": [
+            {"required": ["value"]},
+            {"required": ["valueFrom"]},
+        ],
+    })
+}
+
+/// Schema of the status of a Record.
+fn status_schema() -> Value {
+    let strings = json!({"type": "array", "items": {"type": "string"}});
+    json!({
+        "type": "object",
+        "properties": {
+            "fqdn": {"type": "string"},
+            "type": {"type": "string"},
+            "failures": {"type": "integer"},
+            "providers": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "name": {"type": "string"},
+                        "synced": {"type": "boolean"},
+                        "error": {"type": "string"},
+                        "servedBy": {"type": "string"},
+                        "warning": {"type": "string"},
+                    },
+                },
+            },
+            "plan": {
+                "type": "object",
+                "properties": {
+                    "hash": {"type": "string"},
+                    "changes": strings,
+                },
+            },
+            "history": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "generation": {"type": "integer"},
+                        "values": strings,
+                    },
+                },
+            },
+            "ready": {"type": "boolean"},
+            "lastError": {
+                "type": "object",
+                "properties": {
+                    "message": {"type": "string"},
+                    "time": {"type": "string", "format": "date-time"},
+                },
+            },
+        },
+    })
+}
+
+/// Add the schema of Records to every version of a CustomResourceDefinition generated by
+/// kube-derive, for Records and ClusterRecords.
+pub fn with_schema(mut crd: CustomResourceDefinition) -> Result<CustomResourceDefinition> {
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "spec": spec_schema(),
+            "status": status_schema(),
+        },
+    });
+    let validation = CustomResourceValidation {
+        open_api_v3_schema: Some(serde_json::from_value(schema)?),
+    };
+    for version in crd.spec.versions.iter_mut() {
+        version.schema = Some(validation.clone());
+    }
+    Ok(crd)
 }
@@ -0,0 +1,255 @@
+//! CRD and Code for recordtemplates.syntixi.io/v1alpha1
+
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+// {{{ imports
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use k8s_openapi::{
+    Resource,
+    api::core::v1::{Namespace, Node},
+    apimachinery::pkg::apis::meta::v1::{LabelSelector, OwnerReference},
+};
+use kube::{
+    api::{Api, DeleteParams, ListParams, Meta, ObjectMeta, PatchParams, PostParams},
+    Client,
+};
+use kube_derive::CustomResource;
+use kube_runtime::{
+    reflector::{reflector, store::Writer, Store},
+    watcher,
+};
+use serde::{Serialize, Deserialize};
+use tracing::{info, warn};
+
+use crate::controller;
+use crate::record_spec::{Record, RecordSpec};
+use crate::zone_policy::matches_labels;
+// }}}
+
+/// Label added to every Record generated from a RecordTemplate, set to the name of the
+/// RecordTemplate.
+pub static TEMPLATE_LABEL: &str = "ares.syntixi.io/template";
+
+/// Time to wait after a change before generating Records, so that bursts of changes (such as
+/// when every object is listed) only generate Records once.
+const SYNC_DELAY: Duration = Duration::from_secs(5);
+
+/// Kind of the objects that a RecordTemplate generates a Record for.
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+pub enum TemplateKind {
+    Namespace,
+    Node,
+}
+
+impl Default for TemplateKind {
+    fn default() -> Self {
+        TemplateKind::Namespace
+    }
+}
+
+/// Generates a Record for every Namespace or Node matching a selector. `{name}` in the template
+/// is replaced with the name of the object, and `{namespace}` with the namespace the Record is
+/// created in. Generated Records are removed once the object no longer matches.
+#[derive(CustomResource, Clone, Deserialize, Serialize, Debug)]
+#[kube(group="syntixi.io", version="v1alpha1")]
+pub struct RecordTemplateSpec {
+    #[serde(rename = "forEach", default)]
+    pub for_each: TemplateKind,
+    /// Selector of the labels of the objects to generate a Record for; every object matches if
+    /// not given.
+    pub selector: Option<LabelSelector>,
+    /// Namespace to create the Records generated for Nodes in.
+    #[serde(rename = "recordNamespace")]
+    pub record_namespace: Option<String>,
+    pub template: RecordSpec,
+}
+
+impl RecordTemplate {
+    /// Check whether the labels of an object match the selector of the RecordTemplate.
+    fn matches(&self, meta: &ObjectMeta) -> bool {
+        match &self.spec.selector {
+            Some(selector) => matches_labels(selector, &meta.labels.clone().unwrap_or_default()),
+            None => true,
+        }
+    }
+
+    /// Generate the Record for an object, returning the Record by namespace and name.
+    fn generate(&self, meta: &ObjectMeta) -> Result<((String, String), Record)> {
+        let template_name = Meta::name(self);
+        let object_name = meta.name.clone().ok_or(anyhow!("Missing meta.name"))?;
+        let (namespace, name) = match self.spec.for_each {
+            TemplateKind::Namespace => (object_name.clone(), template_name.clone()),
+            TemplateKind::Node => {
+                let namespace = self
+                    .spec
+                    .record_namespace
+                    .clone()
+                    .ok_or(anyhow!("recordNamespace is required for Node templates"))?;
+                (namespace, format!("{}-{}", template_name, object_name))
+            },
+        };
+        let template = serde_json::to_string(&self.spec.template)?
+            .replace("{name}", &object_name)
+            .replace("{namespace}", &namespace);
+        let mut record = Record::new(&name, serde_json::from_str(&template)?);
+        record.metadata.namespace = Some(namespace.clone());
+        let mut labels = BTreeMap::new();
+        labels.insert(TEMPLATE_LABEL.to_string(), template_name.clone());
+        record.metadata.labels = Some(labels);
+        // Generated Records are deleted along with the RecordTemplate.
+        record.metadata.owner_references = Some(vec![OwnerReference {
+            api_version: RecordTemplate::API_VERSION.to_string(),
+            kind: RecordTemplate::KIND.to_string(),
+            name: template_name,
+            uid: self.metadata.uid.clone().unwrap_or_default(),
+            ..OwnerReference::default()
+        }]);
+        Ok(((namespace, name), record))
+    }
+}
+
+/// Check whether a Record was generated from a RecordTemplate, by its owner references.
+fn is_generated(record: &Record) -> bool {
+    record.metadata.owner_references.iter().flatten().any(|owner| {
+        owner.api_version == RecordTemplate::API_VERSION && owner.kind == RecordTemplate::KIND
+    })
+}
+
+/// Check whether a watcher event lists every object, such as when the watcher starts.
+fn is_listing<K>(event: &watcher::Event<K>) -> bool {
+    match event {
+        watcher::Event::Restarted(_) => true,
+        _ => false,
+    }
+}
+
+/// Create, update and remove Records so that every RecordTemplate has a Record for each
+/// matching object, and no other Records generated from a RecordTemplate exist.
+async fn sync_templates(client: &Client, templates: &Store<RecordTemplate>,
+                        namespaces: &Store<Namespace>, nodes: &Store<Node>) -> Result<()> {
+    let (namespaces, nodes) = (namespaces.state(), nodes.state());
+    let mut wanted: HashMap<(String, String), Record> = HashMap::new();
+    for template in templates.state() {
+        let objects: Vec<&ObjectMeta> = match template.spec.for_each {
+            TemplateKind::Namespace => namespaces.iter().map(|x| &x.metadata).collect(),
+            TemplateKind::Node => nodes.iter().map(|x| &x.metadata).collect(),
+        };
+        for meta in objects.into_iter().filter(|meta| template.matches(meta)) {
+            match template.generate(meta) {
+                Ok((key, record)) => {
+                    wanted.insert(key, record);
+                },
+                Err(e) => warn!(template = %Meta::name(&template),
+                                "Unable to generate Record: {}", e),
+            }
+        }
+    }
+
+    // Records labeled by users rather than generated are left alone.
+    let existing: Vec<Record> = Api::<Record>::all(client.clone())
+        .list(&ListParams::default().labels(TEMPLATE_LABEL))
+        .await?
+        .items
+        .into_iter()
+        .filter(is_generated)
+        .collect();
+    for record in existing.iter() {
+        let key = (Meta::namespace(record).unwrap_or_default(), Meta::name(record));
+        let records: Api<Record> = Api::namespaced(client.clone(), &key.0);
+        match wanted.remove(&key) {
+            Some(generated) => {
+                if serde_json::to_value(&generated.spec)? != serde_json::to_value(&record.spec)? {
+                    info!(record = %key.1, "Updating generated Record");
+                    let patch = serde_json::json!({
+                        "spec": generated.spec,
+                    });
+                    records.patch(&key.1, &PatchParams::default(), serde_json::to_vec(&patch)?)
+                        .await?;
+                }
+            },
+            None => {
+                info!(record = %key.1, "Removing generated Record");
+                records.delete(&key.1, &DeleteParams::default()).await?;
+            },
+        }
+    }
+    for ((namespace, name), record) in wanted {
+        info!(record = %name, "Creating generated Record");
+        let records: Api<Record> = Api::namespaced(client.clone(), &namespace);
+        records.create(&PostParams::default(), &record).await?;
+    }
+    Ok(())
+}
+
+/// Watch over RecordTemplates, Namespaces and Nodes, generating Records shortly after any of
+/// them change, as well as periodically.
+pub async fn run(client: Client, resync_interval: Duration) {
+    let template_writer = Writer::<RecordTemplate>::default();
+    let namespace_writer = Writer::<Namespace>::default();
+    let node_writer = Writer::<Node>::default();
+    let (templates, namespaces, nodes) = (template_writer.as_reader(),
+                                          namespace_writer.as_reader(),
+                                          node_writer.as_reader());
+    let changed = Arc::new(AtomicBool::new(true));
+    // Whether each watcher listed every object, in the order of the watchers. Records are only
+    // generated once every object was listed, as the Records of objects missing from the stores
+    // would otherwise be removed.
+    let listed: Arc<Vec<AtomicBool>> = Arc::new((0..3).map(|_| AtomicBool::new(false)).collect());
+
+    let (watch_changed, watch_listed) = (changed.clone(), listed.clone());
+    let params = controller::watch_params;
+    let watch = stream::select_all(vec![
+        reflector(template_writer, controller::delay_on_error(
+            watcher(Api::<RecordTemplate>::all(client.clone()), params())))
+            .map_ok(|event| (0, is_listing(&event)))
+            .boxed(),
+        reflector(namespace_writer, controller::delay_on_error(
+            watcher(Api::<Namespace>::all(client.clone()), params())))
+            .map_ok(|event| (1, is_listing(&event)))
+            .boxed(),
+        reflector(node_writer, controller::delay_on_error(
+            watcher(Api::<Node>::all(client.clone()), params())))
+            .map_ok(|event| (2, is_listing(&event)))
+            .boxed(),
+    ]).for_each(move |event| {
+        match event {
+            Ok((index, listing)) => {
+                if listing {
+                    watch_listed[index].store(true, Ordering::SeqCst);
+                }
+                watch_changed.store(true, Ordering::SeqCst);
+            },
+            Err(e) => warn!("Error watching RecordTemplates: {}", e),
+        }
+        future::ready(())
+    });
+
+    let sync = async move {
+        let mut last_sync = std::time::Instant::now();
+        loop {
+            tokio::time::delay_for(SYNC_DELAY).await;
+            if !listed.iter().all(|x| x.load(Ordering::SeqCst)) {
+                continue
+            }
+            if !changed.swap(false, Ordering::SeqCst) && last_sync.elapsed() < resync_interval {
+                continue
+            }
+            last_sync = std::time::Instant::now();
+            if let Err(e) = sync_templates(&client, &templates, &namespaces, &nodes)
+                    .await {
+                warn!("Unable to generate Records from RecordTemplates: {}", e);
+                changed.store(true, Ordering::SeqCst);
+            }
+        }
+    };
+
+    future::join(watch, sync).await;
+}
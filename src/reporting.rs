@@ -0,0 +1,44 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Reporting of errors to Sentry, for teams that do not watch the logs of ARES closely. Panics
+//! are reported along with Records that keep failing to sync, once they have failed a number of
+//! times in a row.
+
+// {{{ imports
+use sentry::{protocol::Value, ClientInitGuard, ClientOptions, Level};
+
+use crate::cli::Opts;
+use crate::error::AresError;
+use crate::record_spec::Record;
+// }}}
+
+/// Start reporting errors to the Sentry DSN given on the command line, returning a guard that
+/// sends the remaining reports once dropped. Nothing is reported if no DSN is given.
+pub fn init(opts: &Opts) -> Option<ClientInitGuard> {
+    let dsn = opts.sentry_dsn.as_ref()?;
+    Some(sentry::init((dsn.as_str(), ClientOptions {
+        release: sentry::release_name!(),
+        environment: opts.cluster_id.clone().map(Into::into),
+        ..ClientOptions::default()
+    })))
+}
+
+/// Report a failed sync of a Record once it has failed `threshold` times in a row, so that each
+/// streak of failures is only reported once. Errors that retrying won't fix, such as invalid
+/// credentials, are reported at the first failure instead.
+pub fn record_failure(record: &Record, failures: u32, threshold: u32, error: &AresError) {
+    let threshold = if error.is_retryable() { threshold } else { 1 };
+    if failures != threshold {
+        return
+    }
+    sentry::with_scope(|scope| {
+        scope.set_tag("record", &record.spec.fqdn);
+        scope.set_extra("kind", Value::from(record.kind.clone()));
+        scope.set_extra("namespace", Value::from(record.metadata.namespace.clone()));
+        scope.set_extra("name", Value::from(record.metadata.name.clone()));
+        scope.set_extra("failures", Value::from(failures));
+    }, || {
+        sentry::capture_message(&format!("Unable to sync {} {} times in a row: {:#}",
+                                         record.spec.fqdn, failures, error), Level::Error)
+    });
+}
@@ -0,0 +1,109 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! References to keys of Secrets in the `providerOptions` of the configuration, so that
+//! credentials can be kept in Secrets of their own, with tighter RBAC than the configuration.
+//! Any option can be given as `<option>SecretRef`, such as `apiTokenSecretRef`, and is replaced
+//! by the value of the key of the Secret when the configuration is loaded.
+
+// {{{ imports
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{api::Api, Client};
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+
+use crate::program_config::{self, AresConfig};
+// }}}
+
+/// Suffix of the name of an option given as a reference to a key of a Secret.
+const SUFFIX: &str = "SecretRef";
+
+/// A key of a Secret, in the namespace of the ARES Secret unless given a namespace.
+#[derive(Clone, Deserialize, Serialize, Debug)]
+pub struct SecretRef {
+    pub name: String,
+    pub key: String,
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+/// Secrets read while resolving references, by namespace and name.
+pub type ReferencedSecrets = BTreeMap<(String, String), Secret>;
+
+/// Parse a configuration file, expanding environment variables with
+/// `program_config::expand_env()` and then replacing every reference to a Secret in the
+/// `providerOptions` of each element and of its standby providers. Secrets are read from
+/// `namespace` unless a reference gives a namespace, and are returned along with the
+/// configuration, so that changes to them can be detected. A configuration without references
+/// is parsed without a Client.
+pub async fn parse(client: Option<&Client>, content: &str, namespace: &str) ->
+        Result<(Vec<AresConfig>, ReferencedSecrets)> {
+    let mut elements: Vec<Value> = serde_yaml::from_str(content)?;
+    let mut secrets = ReferencedSecrets::new();
+    for element in elements.iter_mut() {
+        program_config::expand_env(element)?;
+        for options in provider_options(element) {
+            resolve(client, options, namespace, &mut secrets).await?;
+        }
+    }
+    Ok((program_config::from_elements(elements)?, secrets))
+}
+
+/// Get the options of the provider and of every standby provider of an element of the
+/// configuration.
+fn provider_options(element: &mut Value) -> Vec<&mut Map<String, Value>> {
+    let mut options = vec![];
+    for (field, value) in element.as_object_mut().into_iter().flatten() {
+        match (field.as_str(), value) {
+            ("providerOptions", Value::Object(provider_options)) => options.push(provider_options),
+            ("standby", Value::Array(standby)) => {
+                options.extend(standby
+                    .iter_mut()
+                    .filter_map(|provider| provider.get_mut("providerOptions"))
+                    .filter_map(Value::as_object_mut));
+            },
+            _ => {},
+        }
+    }
+    options
+}
+
+/// Replace every reference to a Secret in the options of a provider by the value of its key.
+async fn resolve(client: Option<&Client>, options: &mut Map<String, Value>, namespace: &str,
+                 secrets: &mut ReferencedSecrets) -> Result<()> {
+    let references: Vec<String> = options
+        .keys()
+        .filter(|option| option.len() > SUFFIX.len() && option.ends_with(SUFFIX))
+        .cloned()
+        .collect();
+    for reference in references {
+        let option = reference[..reference.len() - SUFFIX.len()].to_string();
+        if options.contains_key(&option) {
+            return Err(anyhow!("Both {} and {} given", option, reference))
+        }
+        let secret_ref: SecretRef = serde_json::from_value(options.remove(&reference).unwrap())
+            .map_err(|e| anyhow!("Invalid {}: {}", reference, e))?;
+        let client = client.ok_or(anyhow!("Unable to read {} without a cluster", reference))?;
+        let secret_namespace = secret_ref.namespace.clone().unwrap_or(namespace.to_string());
+        let key = (secret_namespace.clone(), secret_ref.name.clone());
+        if !secrets.contains_key(&key) {
+            let api: Api<Secret> = Api::namespaced(client.clone(), &secret_namespace);
+            let secret = api
+                .get(&secret_ref.name)
+                .await
+                .map_err(|e| anyhow!("Unable to read Secret {}/{} for {}: {}",
+                                     secret_namespace, secret_ref.name, reference, e))?;
+            secrets.insert(key.clone(), secret);
+        }
+        let value = secrets[&key]
+            .data
+            .as_ref()
+            .and_then(|data| data.get(&secret_ref.key))
+            .ok_or(anyhow!("Unable to get key {} from Secret {}/{} for {}",
+                           secret_ref.key, secret_namespace, secret_ref.name, reference))?;
+        options.insert(option, Value::String(String::from_utf8(value.0.clone())?));
+    }
+    Ok(())
+}
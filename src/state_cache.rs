@@ -0,0 +1,168 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Cache of the records last synced to the provider of each AresConfig, persisted to a ConfigMap
+//! or a file. A restarted ARES compares Records against the cache rather than querying every
+//! zone at once, and only syncs the Records whose values have changed since the last sync.
+
+// {{{ imports
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::{
+    Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{anyhow, Result};
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{
+    api::{Api, ObjectMeta, PatchParams, PostParams},
+    Client,
+};
+
+use crate::cli::Opts;
+use crate::providers::dry_run;
+use crate::providers::util::{
+    FullDomainName, Record, RecordBuilder, RecordType, ZoneDomainName,
+};
+// }}}
+
+/// Key of the ConfigMap holding the cache.
+static CONFIGMAP_KEY: &str = "state.json";
+
+/// Records of each zone, by the name of the AresConfig the zone is hosted by.
+type State = BTreeMap<String, BTreeMap<ZoneDomainName, Vec<Record>>>;
+
+/// Where the cache is persisted.
+enum Backend {
+    ConfigMap(Api<ConfigMap>, String),
+    File(PathBuf),
+}
+
+/// The records last synced to the provider of each AresConfig.
+pub struct StateCache {
+    backend: Backend,
+    state: Mutex<State>,
+    dirty: AtomicBool,
+}
+
+impl StateCache {
+    /// Load the cache from the ConfigMap or file given on the command line. `None` is returned
+    /// if the cache is disabled.
+    pub async fn load(client: &Client, opts: &Opts) -> Result<Option<StateCache>> {
+        let backend = match (&opts.state_cache_configmap, &opts.state_cache_file) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow!("Only one of a state cache ConfigMap or file may be given"))
+            },
+            (Some(name), None) => {
+                let api = Api::namespaced(client.clone(), &opts.secret_namespace);
+                Backend::ConfigMap(api, name.clone())
+            },
+            (None, Some(path)) => Backend::File(PathBuf::from(path)),
+            (None, None) => return Ok(None),
+        };
+        let content = match &backend {
+            Backend::ConfigMap(api, name) => match api.get(name).await {
+                Ok(configmap) => configmap.data.and_then(|mut data| data.remove(CONFIGMAP_KEY)),
+                Err(kube::Error::Api(e)) if e.code == 404 => None,
+                Err(e) => return Err(e.into()),
+            },
+            Backend::File(path) => match tokio::fs::read_to_string(path).await {
+                Ok(content) => Some(content),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e.into()),
+            },
+        };
+        let state = match content {
+            Some(content) => serde_json::from_str(&content)?,
+            None => State::default(),
+        };
+        Ok(Some(StateCache {
+            backend: backend,
+            state: Mutex::new(state),
+            dirty: AtomicBool::new(false),
+        }))
+    }
+
+    /// Record the values last synced for a domain name in the zone of a RecordBuilder. Nothing
+    /// is recorded in dry runs, as the values were never synced.
+    pub fn update(&self, name: &str, record_builder: &RecordBuilder, values: &[String]) {
+        if dry_run::enabled() {
+            return
+        }
+        let mut state = self.state.lock().unwrap();
+        let records = state
+            .entry(name.to_string())
+            .or_default()
+            .entry(record_builder.zone.clone())
+            .or_default();
+        records.retain(|x| {
+            x.fqdn != record_builder.fqdn || x.record_type != record_builder.record_type
+        });
+        records.extend(values.iter().map(|value| {
+            Record::new(record_builder.zone.clone(), record_builder.fqdn.clone(), 1,
+                        record_builder.record_type.clone(), value.clone())
+        }));
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Get the values last synced for a domain name. `None` is returned if no cached zone of
+    /// the AresConfig contains the domain name.
+    pub fn values(&self, name: &str, fqdn: &FullDomainName, record_type: &RecordType) ->
+            Option<Vec<String>> {
+        let state = self.state.lock().unwrap();
+        let (_, records) = state
+            .get(name)?
+            .iter()
+            .filter(|(zone, _)| *fqdn == **zone || fqdn.ends_with(&format!(".{}", zone)))
+            .max_by_key(|(zone, _)| zone.len())?;
+        Some(records
+            .iter()
+            .filter(|x| x.fqdn == *fqdn && x.record_type == *record_type)
+            .map(|x| x.value.clone())
+            .collect())
+    }
+
+    /// Persist the cache if it changed since it was last persisted.
+    pub async fn flush(&self) -> Result<()> {
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(())
+        }
+        let content = serde_json::to_string(&*self.state.lock().unwrap())?;
+        let written = match &self.backend {
+            Backend::ConfigMap(api, name) => write_configmap(api, name, content).await,
+            Backend::File(path) => tokio::fs::write(path, content).await.map_err(|e| e.into()),
+        };
+        // The cache is persisted again on the next flush if it could not be written.
+        if written.is_err() {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        written
+    }
+}
+
+/// Write the cache to a ConfigMap, creating the ConfigMap if it does not exist.
+async fn write_configmap(api: &Api<ConfigMap>, name: &str, content: String) -> Result<()> {
+    let patch = serde_json::json!({
+        "data": {
+            CONFIGMAP_KEY: &content,
+        },
+    });
+    match api.patch(name, &PatchParams::default(), serde_json::to_vec(&patch)?).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(e)) if e.code == 404 => {
+            let mut data = BTreeMap::new();
+            data.insert(CONFIGMAP_KEY.to_string(), content);
+            let configmap = ConfigMap {
+                metadata: ObjectMeta {
+                    name: Some(name.to_string()),
+                    ..ObjectMeta::default()
+                },
+                data: Some(data),
+                ..ConfigMap::default()
+            };
+            api.create(&PostParams::default(), &configmap).await?;
+            Ok(())
+        },
+        Err(e) => Err(e.into()),
+    }
+}
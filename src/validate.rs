@@ -0,0 +1,155 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Validation of the configuration of ARES for `ares validate`, printing a report of every
+//! problem found so that a configuration can be checked before it is rolled out.
+
+// {{{ imports
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+
+use crate::program_config::AresConfig;
+use crate::providers::ProviderConfig;
+use crate::providers::util::ProviderBackend;
+// }}}
+
+/// A single result of the validation of an AresConfig.
+pub enum Finding {
+    Ok(String),
+    Warning(String),
+    Error(String),
+}
+
+/// Get the name of the kind of a provider, such as "cloudflare", without printing its options,
+/// which hold credentials.
+pub fn provider_kind(provider: &ProviderConfig) -> String {
+    provider.name().to_string()
+}
+
+/// Check an AresConfig on its own and against every other AresConfig, without making any
+/// requests.
+fn check_config(config: &[Arc<AresConfig>], index: usize, ares: &AresConfig) -> Vec<Finding> {
+    let mut findings = vec![];
+    let name = ares.display_name(index);
+    for (other_index, other) in config.iter().enumerate() {
+        if other_index == index {
+            continue
+        }
+        if other.display_name(other_index) == name {
+            findings.push(Finding::Error(format!(
+                "Name is shared with AresConfig #{}, so providerRefs can't tell them apart",
+                other_index)));
+        }
+        if other_index < index {
+            for selector in ares.selector.iter().filter(|x| other.selector.contains(x)) {
+                findings.push(Finding::Warning(format!(
+                    "Selector {} is also used by {}; matching Records are synced to both",
+                    selector, other.display_name(other_index))));
+            }
+        }
+    }
+    if ares.selector.is_empty() {
+        findings.push(Finding::Error("No selectors, so no Records match".to_string()));
+    } else {
+        findings.push(Finding::Ok(format!("Selectors: {}", ares.selector.join(", "))));
+    }
+    if ares.default_ttl == Some(0) {
+        findings.push(Finding::Error("defaultTtl must be at least 1".to_string()));
+    }
+    let mut clusters: HashMap<&str, usize> = HashMap::new();
+    for cluster in ares.clusters.iter() {
+        *clusters.entry(&cluster.name).or_default() += 1;
+        if cluster.secret_name.is_empty() {
+            findings.push(Finding::Error(format!("Cluster {} has no secretName", cluster.name)));
+        }
+    }
+    for (cluster, _) in clusters.into_iter().filter(|(_, count)| *count > 1) {
+        findings.push(Finding::Error(format!("Cluster {} is given more than once", cluster)));
+    }
+    for (position, provider) in ares.providers().enumerate() {
+        let provider_name = AresConfig::provider_name(position);
+        let backend: &dyn ProviderBackend = provider.deref();
+        match backend.validate_options() {
+            Ok(()) => findings.push(Finding::Ok(format!("Provider {} ({}) options are valid",
+                                                        provider_name, provider_kind(provider)))),
+            Err(e) => findings.push(Finding::Error(format!("Provider {} ({}) options: {}",
+                                                           provider_name, provider_kind(provider),
+                                                           e))),
+        }
+    }
+    findings
+}
+
+/// Check the credentials of every provider of an AresConfig by finding the zone of every
+/// Selector at the provider.
+pub async fn check_credentials(ares: &AresConfig) -> Vec<Finding> {
+    let mut findings = vec![];
+    for (position, provider) in ares.providers().enumerate() {
+        let provider_name = AresConfig::provider_name(position);
+        let backend: &dyn ProviderBackend = provider.deref();
+        for selector in ares.selector.iter() {
+            let domain = selector.trim_start_matches("*.").trim_start_matches('.').to_string();
+            match backend.get_zone(&domain).await {
+                Ok(zone) => findings.push(Finding::Ok(format!(
+                    "Provider {} hosts {} in zone {}", provider_name, domain, zone))),
+                Err(e) => findings.push(Finding::Error(format!(
+                    "Provider {} is unable to find the zone of {}: {}",
+                    provider_name, domain, e))),
+            }
+        }
+    }
+    findings
+}
+
+/// Counts of the problems printed in a report.
+#[derive(Default)]
+pub struct Report {
+    pub errors: usize,
+    pub warnings: usize,
+}
+
+impl Report {
+    /// Print a heading followed by every finding under it, counting the problems found.
+    pub fn print(&mut self, heading: &str, findings: Vec<Finding>) {
+        println!("{}", heading);
+        for finding in findings {
+            match finding {
+                Finding::Ok(message) => println!("  ok: {}", message),
+                Finding::Warning(message) => {
+                    self.warnings += 1;
+                    println!("  warning: {}", message);
+                },
+                Finding::Error(message) => {
+                    self.errors += 1;
+                    println!("  error: {}", message);
+                },
+            }
+        }
+    }
+}
+
+/// Validate every AresConfig, printing a report to stdout. Credentials are only checked, with
+/// requests to each provider, when `credentials` is set. An error is returned if any problem
+/// was found, while warnings are only reported.
+pub async fn run(config: &[Arc<AresConfig>], credentials: bool) -> Result<()> {
+    if config.is_empty() {
+        return Err(anyhow!("No AresConfigs found"))
+    }
+    let mut report = Report::default();
+    for (index, ares) in config.iter().enumerate() {
+        let mut findings = check_config(config, index, ares);
+        if credentials {
+            findings.extend(check_credentials(ares).await);
+        }
+        report.print(&format!("{} ({})", ares.display_name(index), provider_kind(&ares.provider)),
+                     findings);
+    }
+    println!("{} AresConfigs checked: {} errors, {} warnings", config.len(), report.errors,
+             report.warnings);
+    if report.errors > 0 {
+        return Err(anyhow!("Configuration is invalid"))
+    }
+    Ok(())
+}
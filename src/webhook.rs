@@ -0,0 +1,226 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Mutating admission webhook filling in the defaults of Records as they are created or updated,
+//! so that manifests only need to give what can't be inferred: the domain name is normalized,
+//! the type is inferred from the values, and the TTL is taken from the Zone or AresConfig.
+
+// {{{ imports
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use futures::{future, StreamExt};
+use hyper::{
+    service::service_fn,
+    server::conn::Http,
+    Body, Method, Request, Response, StatusCode,
+};
+use kube::{api::Api, Client};
+use kube_runtime::{
+    reflector::{reflector, store::Writer, Store},
+    watcher,
+};
+use native_tls::{Identity, TlsAcceptor};
+use serde_json::Value;
+use tracing::{debug, info, warn};
+use tracing_futures::Instrument;
+use tokio::net::TcpListener;
+
+use crate::cli::Opts;
+use crate::controller;
+use crate::program_config::AresConfig;
+use crate::providers::util::normalize_domain;
+use crate::zone_spec::{find_zone, Zone};
+// }}}
+
+/// State shared by every request to the webhook.
+struct Defaults {
+    config: Vec<Arc<AresConfig>>,
+    zones: Store<Zone>,
+}
+
+impl Defaults {
+    /// Get the default TTL of a domain name: the default TTL of its Zone, or otherwise of the
+    /// first AresConfig syncing it, being the AresConfig named by the `providerRef` of the
+    /// Record if given.
+    fn ttl(&self, fqdn: &str, record_provider_ref: Option<&str>) -> Option<u32> {
+        let zones = self.zones.state();
+        let zone = find_zone(&zones, &fqdn.to_string());
+        if let Some(ttl) = zone.and_then(|zone| zone.spec.default_ttl) {
+            return Some(ttl)
+        }
+        let provider_ref = record_provider_ref
+            .map(str::to_string)
+            .or_else(|| zone.and_then(|zone| zone.spec.provider_ref.clone()));
+        self.config
+            .iter()
+            .enumerate()
+            .filter(|(_, ares)| record_provider_ref.is_some() || ares.matches_selector(fqdn))
+            .find(|(index, ares)| match &provider_ref {
+                Some(provider_ref) => ares.display_name(*index) == *provider_ref,
+                None => true,
+            })
+            .and_then(|(_, ares)| ares.default_ttl)
+    }
+}
+
+/// Infer the type of a Record from its values or the source of its values.
+fn infer_type(spec: &Value) -> Option<&'static str> {
+    match spec.get("valueFrom").and_then(Value::as_object) {
+        Some(value_from) if value_from.contains_key("podSelector") => return Some("A"),
+        Some(value_from) if value_from.contains_key("hostKeys") => return Some("SSHFP"),
+        Some(value_from) if value_from.contains_key("delegation") => return Some("NS"),
+        _ => {},
+    }
+    let values: Vec<&str> = spec
+        .get("value")
+        .and_then(Value::as_array)?
+        .iter()
+        .filter_map(Value::as_str)
+        .collect();
+    if values.is_empty() {
+        None
+    } else if values.iter().all(|value| value.parse::<Ipv4Addr>().is_ok()) {
+        Some("A")
+    } else if values.iter().all(|value| value.parse::<Ipv6Addr>().is_ok()) {
+        Some("AAAA")
+    } else if values.len() == 1 && values[0].contains('.') && !values[0].contains(' ') {
+        // A single domain name can only be an alias for another domain name.
+        Some("CNAME")
+    } else {
+        None
+    }
+}
+
+/// Build the JSON Patch filling in the defaults of the spec of a Record.
+fn patch(defaults: &Defaults, spec: &Value) -> Vec<Value> {
+    let mut patch = vec![];
+    let fqdn = spec.get("fqdn").and_then(Value::as_str).map(normalize_domain);
+    if let Some(Ok(fqdn)) = &fqdn {
+        if spec.get("fqdn").and_then(Value::as_str) != Some(fqdn.as_str()) {
+            patch.push(serde_json::json!({"op": "add", "path": "/spec/fqdn", "value": fqdn}));
+        }
+        if spec.get("ttl").map(Value::is_null).unwrap_or(true) {
+            let provider_ref = spec.get("providerRef").and_then(Value::as_str);
+            if let Some(ttl) = defaults.ttl(fqdn, provider_ref) {
+                patch.push(serde_json::json!({"op": "add", "path": "/spec/ttl", "value": ttl}));
+            }
+        }
+    }
+    if spec.get("type").map(Value::is_null).unwrap_or(true) {
+        if let Some(type_) = infer_type(spec) {
+            patch.push(serde_json::json!({"op": "add", "path": "/spec/type", "value": type_}));
+        }
+    }
+    patch
+}
+
+/// Build the AdmissionReview responding to an AdmissionReview, allowing the Record with the
+/// defaults filled in. Records are never denied by the webhook; invalid Records are rejected by
+/// the schema of the CRD.
+fn review(defaults: &Defaults, review: Value) -> Result<Value> {
+    let request = review.get("request").ok_or(anyhow!("Missing AdmissionReview.request"))?;
+    let uid = request.get("uid").cloned().ok_or(anyhow!("Missing request.uid"))?;
+    let patch = request
+        .get("object")
+        .and_then(|object| object.get("spec"))
+        .map(|spec| patch(defaults, spec))
+        .unwrap_or_default();
+    let mut response = serde_json::json!({
+        "uid": uid,
+        "allowed": true,
+    });
+    if !patch.is_empty() {
+        response["patchType"] = Value::from("JSONPatch");
+        response["patch"] = Value::from(base64::encode(serde_json::to_vec(&patch)?));
+    }
+    let api_version = review
+        .get("apiVersion")
+        .cloned()
+        .unwrap_or_else(|| Value::from("admission.k8s.io/v1"));
+    Ok(serde_json::json!({
+        "apiVersion": api_version,
+        "kind": "AdmissionReview",
+        "response": response,
+    }))
+}
+
+/// Respond to a request made to the webhook by the API server.
+async fn handle(defaults: Arc<Defaults>, request: Request<Body>) ->
+        Result<Response<Body>, hyper::Error> {
+    if request.method() != Method::POST {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        return Ok(response)
+    }
+    let body = hyper::body::to_bytes(request.into_body()).await?;
+    let result = serde_json::from_slice::<Value>(&body)
+        .map_err(anyhow::Error::from)
+        .and_then(|body| review(&defaults, body))
+        .and_then(|body| serde_json::to_vec(&body).map_err(anyhow::Error::from));
+    match result {
+        Ok(body) => Ok(Response::new(Body::from(body))),
+        Err(e) => {
+            warn!("Unable to handle AdmissionReview: {}", e);
+            let mut response = Response::new(Body::from(e.to_string()));
+            *response.status_mut() = StatusCode::BAD_REQUEST;
+            Ok(response)
+        },
+    }
+}
+
+/// Start serving the webhook on the address given on the command line. Nothing is served if no
+/// address is given.
+pub async fn spawn(client: Client, config: Vec<Arc<AresConfig>>, opts: &Opts) ->
+        Result<()> {
+    let address: SocketAddr = match &opts.webhook_address {
+        Some(address) => address.parse()?,
+        None => return Ok(()),
+    };
+    let (cert, key) = match (&opts.webhook_cert, &opts.webhook_key) {
+        (Some(cert), Some(key)) => (tokio::fs::read(cert).await?, tokio::fs::read(key).await?),
+        _ => return Err(anyhow!("A certificate and key are required to serve the webhook")),
+    };
+    let acceptor = tokio_tls::TlsAcceptor::from(
+        TlsAcceptor::new(Identity::from_pkcs8(&cert, &key)?)?);
+    let mut listener = TcpListener::bind(address).await?;
+
+    let zone_writer = Writer::<Zone>::default();
+    let defaults = Arc::new(Defaults {
+        config: config,
+        zones: zone_writer.as_reader(),
+    });
+    let zones = reflector(zone_writer, controller::delay_on_error(
+        watcher(Api::<Zone>::all(client), controller::watch_params())));
+    tokio::spawn(zones.for_each(|_| future::ready(())));
+
+    info!(address = %address, "Serving defaulting webhook");
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Unable to accept webhook connection: {}", e);
+                    continue
+                },
+            };
+            let (acceptor, defaults) = (acceptor.clone(), defaults.clone());
+            // Connections are handled separately so that a slow TLS handshake does not hold up
+            // other connections.
+            tokio::spawn(async move {
+                let stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        debug!(peer = %peer, "Unable to accept TLS connection: {}", e);
+                        return
+                    },
+                };
+                let service = service_fn(|request| handle(defaults.clone(), request));
+                if let Err(e) = Http::new().serve_connection(stream, service).await {
+                    debug!(peer = %peer, "Error serving webhook connection: {}", e);
+                }
+            }.in_current_span());
+        }
+    }.in_current_span());
+    Ok(())
+}
@@ -0,0 +1,76 @@
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+//! Cache of the zones of domain names at each provider, shared by every reconciliation, so that
+//! the zone of a domain name is looked up once per expiry rather than by every sync of every
+//! Record. Lookups that fail are not cached.
+
+// {{{ imports
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use crate::error::ProviderError;
+use crate::program_config::AresConfig;
+use crate::providers::ProviderConfig;
+use crate::providers::util::{FullDomainName, ProviderBackend, ZoneDomainName};
+// }}}
+
+/// Cache of the zones of domain names at each provider.
+pub struct ZoneCache {
+    ttl: Duration,
+    // Providers are told apart by address, as the configuration of ARES is fixed for as long as
+    // the cache lives; ARES restarts when its configuration changes.
+    entries: Mutex<HashMap<(usize, FullDomainName), (ZoneDomainName, Instant)>>,
+}
+
+impl ZoneCache {
+    /// Build a cache keeping zones for a time to live. Nothing is cached if the time to live is
+    /// zero.
+    pub fn new(ttl: Duration) -> ZoneCache {
+        ZoneCache {
+            ttl: ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the zone of a domain name at a provider, looking it up if it is not cached or has
+    /// expired.
+    pub async fn get_zone(&self, provider: &ProviderConfig, domain: &FullDomainName) ->
+            Result<ZoneDomainName> {
+        let key = (provider as *const ProviderConfig as usize, domain.clone());
+        if let Some((zone, cached_at)) = self.entries.lock().unwrap().get(&key) {
+            if cached_at.elapsed() < self.ttl {
+                return Ok(zone.clone())
+            }
+        }
+        let backend: &dyn ProviderBackend = provider.deref();
+        let zone = backend.get_zone(domain).await?;
+        if self.ttl > Duration::from_secs(0) {
+            let mut entries = self.entries.lock().unwrap();
+            // expired entries are dropped whenever a zone is looked up, so that the cache does
+            // not grow forever
+            let ttl = self.ttl;
+            entries.retain(|_, (_, cached_at)| cached_at.elapsed() < ttl);
+            entries.insert(key, (zone.clone(), Instant::now()));
+        }
+        Ok(zone)
+    }
+
+    /// Get the zone of a domain name from the first provider in the failover chain of an
+    /// AresConfig that is able to find it.
+    pub async fn get_config_zone(&self, ares: &AresConfig, domain: &FullDomainName) ->
+            Result<ZoneDomainName> {
+        let message = format!("Unable to find DNS Zone for: {}", domain);
+        let mut last_error: anyhow::Error = ProviderError::NotFound(message).into();
+        for provider in ares.providers() {
+            match self.get_zone(provider, domain).await {
+                Ok(zone) => return Ok(zone),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+}
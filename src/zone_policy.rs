@@ -0,0 +1,110 @@
+//! CRD and Code for zonepolicies.syntixi.io/v1alpha1
+
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+// {{{ imports
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use k8s_openapi::{
+    api::core::v1::Namespace,
+    apimachinery::pkg::apis::meta::v1::{LabelSelector, LabelSelectorRequirement},
+};
+use kube_derive::CustomResource;
+use serde::{Serialize, Deserialize};
+
+use crate::program_config::selector_matches;
+use crate::providers::util::{normalize_domain, FullDomainName};
+// }}}
+
+/// Authorizes the namespaces matching the policy to create Records under a set of domain names.
+/// Once a domain name is covered by any ZonePolicy, Records for the domain name may only be
+/// created in the namespaces of the ZonePolicies covering it. Domain names that no ZonePolicy
+/// covers may be used from any namespace.
+#[derive(CustomResource, Clone, Deserialize, Serialize, Debug)]
+#[kube(group="syntixi.io", version="v1alpha1")]
+pub struct ZonePolicySpec {
+    /// Names of the namespaces the policy applies to.
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+    /// Selector of the labels of the namespaces the policy applies to.
+    #[serde(rename = "namespaceSelector")]
+    pub namespace_selector: Option<LabelSelector>,
+    /// Domain names that Records may be created under, in the syntax of the Selectors of an
+    /// AresConfig, such as ".team.syntixi.io".
+    pub domains: Vec<String>,
+}
+
+/// Check whether a set of labels matches a single requirement of a LabelSelector.
+fn matches_requirement(requirement: &LabelSelectorRequirement,
+                       labels: &BTreeMap<String, String>) -> bool {
+    let value = labels.get(&requirement.key);
+    let values = requirement.values.clone().unwrap_or_default();
+    match requirement.operator.as_str() {
+        "In" => value.map(|value| values.contains(value)).unwrap_or(false),
+        "NotIn" => value.map(|value| !values.contains(value)).unwrap_or(false),
+        "Exists" => value.is_some(),
+        "DoesNotExist" => value.is_none(),
+        _ => false,
+    }
+}
+
+/// Check whether a set of labels matches every value of matchLabels and every expression of
+/// matchExpressions of a LabelSelector.
+pub fn matches_labels(selector: &LabelSelector, labels: &BTreeMap<String, String>) -> bool {
+    let labels_match = selector
+        .match_labels
+        .as_ref()
+        .map(|match_labels| match_labels
+            .iter()
+            .all(|(label, value)| labels.get(label) == Some(value)))
+        .unwrap_or(true);
+    let expressions_match = selector
+        .match_expressions
+        .as_ref()
+        .map(|match_expressions| match_expressions
+            .iter()
+            .all(|requirement| matches_requirement(requirement, labels)))
+        .unwrap_or(true);
+    labels_match && expressions_match
+}
+
+impl ZonePolicy {
+    /// Check whether the policy applies to a namespace, by name or by labels.
+    pub fn applies_to(&self, name: &str, namespace: Option<&Namespace>) -> bool {
+        if self.spec.namespaces.iter().any(|x| x == name) {
+            return true
+        }
+        let labels = namespace
+            .and_then(|namespace| namespace.metadata.labels.clone())
+            .unwrap_or_default();
+        self.spec
+            .namespace_selector
+            .as_ref()
+            .map(|selector| matches_labels(selector, &labels))
+            .unwrap_or(false)
+    }
+
+    /// Check whether a domain name is under one of the domain names of the policy.
+    pub fn covers(&self, fqdn: &FullDomainName) -> bool {
+        self.spec
+            .domains
+            .iter()
+            .filter_map(|domain| normalize_domain(domain).ok())
+            .any(|domain| selector_matches(&domain, fqdn))
+    }
+}
+
+/// Check whether Records for a domain name may be created in a namespace.
+pub fn authorize(policies: &[ZonePolicy], name: &str, namespace: Option<&Namespace>,
+                 fqdn: &FullDomainName) -> Result<()> {
+    let mut covering = policies.iter().filter(|policy| policy.covers(fqdn)).peekable();
+    if covering.peek().is_none() {
+        return Ok(())
+    }
+    if covering.any(|policy| policy.applies_to(name, namespace)) {
+        Ok(())
+    } else {
+        Err(anyhow!("Namespace {} is not allowed to create Records for {}", name, fqdn))
+    }
+}
@@ -0,0 +1,62 @@
+//! CRD and Code for zones.syntixi.io/v1alpha1
+
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+// {{{ imports
+use anyhow::{anyhow, Result};
+use kube_derive::CustomResource;
+use serde::{Serialize, Deserialize};
+
+use crate::providers::util::{normalize_domain, FullDomainName, RecordType};
+use crate::record_spec::Record;
+// }}}
+
+/// A zone managed by ARES, with defaults and restrictions for the Records in the zone.
+#[derive(CustomResource, Clone, Deserialize, Serialize, Debug)]
+#[kube(group="syntixi.io", version="v1alpha1")]
+pub struct ZoneSpec {
+    pub zone: FullDomainName,
+    /// Name of the AresConfig hosting the zone. Records in the zone are only synced with the
+    /// AresConfig of this name, rather than with every AresConfig with a matching Selector.
+    #[serde(rename = "providerRef")]
+    pub provider_ref: Option<String>,
+    /// TTL of Records in the zone that do not set a TTL.
+    #[serde(rename = "defaultTtl")]
+    pub default_ttl: Option<u32>,
+    /// Types of Records allowed in the zone; every type is allowed if not given.
+    #[serde(rename = "allowedTypes")]
+    pub allowed_types: Option<Vec<RecordType>>,
+}
+
+impl Zone {
+    /// Check whether a domain name is the zone or a subdomain of the zone.
+    pub fn contains(&self, fqdn: &FullDomainName) -> bool {
+        match normalize_domain(&self.spec.zone) {
+            Ok(zone) => *fqdn == zone || fqdn.ends_with(&format!(".{}", zone)),
+            Err(_) => false,
+        }
+    }
+
+    /// Validate a Record against the zone, filling in the defaults of the zone.
+    pub fn apply(&self, record: &Record) -> Result<Record> {
+        if let Some(allowed_types) = &self.spec.allowed_types {
+            if !allowed_types.contains(&record.spec.type_) {
+                return Err(anyhow!("Records of type {:?} are not allowed in zone {}",
+                                   record.spec.type_, self.spec.zone));
+            }
+        }
+        let mut record = record.clone();
+        if record.spec.ttl.is_none() {
+            record.spec.ttl = self.spec.default_ttl;
+        }
+        Ok(record)
+    }
+}
+
+/// Find the Zone of a domain name, being the most specific Zone containing the domain name.
+pub fn find_zone<'a>(zones: &'a [Zone], fqdn: &FullDomainName) -> Option<&'a Zone> {
+    zones
+        .iter()
+        .filter(|zone| zone.contains(fqdn))
+        .max_by_key(|zone| zone.spec.zone.len())
+}
@@ -0,0 +1,366 @@
+//! CRD and Code for zonesyncs.syntixi.io/v1alpha1
+
+// vim:set et sw=4 ts=4 foldmethod=marker:
+
+// {{{ imports
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use futures::TryStreamExt;
+use k8s_openapi::{
+    Resource,
+    apimachinery::pkg::apis::meta::v1::OwnerReference,
+};
+use kube::{
+    api::{Api, DeleteParams, ListParams, Meta, PatchParams, PostParams},
+    Client,
+};
+use kube_derive::CustomResource;
+use serde::{Serialize, Deserialize};
+use tracing::{debug, info, info_span, warn};
+use tracing_futures::Instrument;
+
+use crate::controller::IGNORE_ANNOTATION;
+use crate::program_config::{AresConfig, DomainFilter};
+use crate::providers::util::{
+    normalize_domain, tracked_domain, tracking_domain, FullDomainName, Heritage,
+    ProviderBackend, Record as RecordObject, RecordType, ZoneDomainName,
+};
+use crate::record_spec::{Record, RecordSpec};
+// }}}
+
+/// Label added to every Record mirrored by a ZoneSync, set to the name of the ZoneSync.
+pub static ZONE_SYNC_LABEL: &str = "ares.syntixi.io/zone-sync";
+
+/// Time between checks for ZoneSyncs that are due to be synced.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What a ZoneSync does with the records it imports.
+#[derive(Clone, Copy, Deserialize, Serialize, Debug, PartialEq)]
+pub enum ZoneSyncMode {
+    /// Mirror the records as Records that ARES ignores, kept up to date with the provider and
+    /// removed along with the records.
+    #[serde(rename = "readOnly")]
+    ReadOnly,
+    /// Take ownership of the records, creating a tracking record for each domain name and a
+    /// Record managed by ARES like any other. Adopted Records are left alone by the ZoneSync.
+    #[serde(rename = "adopt")]
+    Adopt,
+}
+
+impl Default for ZoneSyncMode {
+    fn default() -> Self {
+        ZoneSyncMode::ReadOnly
+    }
+}
+
+/// Imports the records of a zone hosted by a provider as Records in the namespace of the
+/// ZoneSync, giving visibility into every record at the provider. Domain names already owned by
+/// ARES are skipped, as they are managed by Records already.
+#[derive(CustomResource, Clone, Deserialize, Serialize, Debug)]
+#[kube(group="syntixi.io", version="v1alpha1", namespaced, status="ZoneSyncStatus")]
+pub struct ZoneSyncSpec {
+    pub zone: FullDomainName,
+    /// Name of the AresConfig of the provider hosting the zone.
+    #[serde(rename = "providerRef")]
+    pub provider_ref: String,
+    #[serde(default)]
+    pub mode: ZoneSyncMode,
+    /// Seconds between imports of the zone; defaults to 300.
+    pub interval: Option<u64>,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, Default)]
+pub struct ZoneSyncStatus {
+    /// Number of Records mirrored or adopted by the last import.
+    pub records: Option<usize>,
+    /// The error raised by the last import, if it failed.
+    pub error: Option<String>,
+    /// Time of the last import, in RFC 3339 format.
+    #[serde(rename = "lastSynced")]
+    pub last_synced: Option<String>,
+}
+
+/// Name of the Record mirroring the records of a type of a domain name, such as
+/// "a.www.syntixi.io". Characters that can't be part of the name of an object are replaced.
+fn record_name(fqdn: &str, record_type: &RecordType) -> String {
+    format!("{:?}.{}", record_type, fqdn)
+        .to_lowercase()
+        .replace("*", "wildcard")
+        .replace("_", "-")
+}
+
+/// Group the records of a zone into the spec of a Record for each type of each domain name,
+/// synced with the AresConfig named by `provider_ref`. Tracking records, the SOA record, the NS
+/// records of the zone itself, and domain names that are already owned by ARES are skipped.
+fn group_records(provider_ref: &str, zone: &str, records: Vec<RecordObject>) ->
+        BTreeMap<String, RecordSpec> {
+    let tracked: Vec<FullDomainName> = records
+        .iter()
+        .filter_map(|record| tracked_domain(&record.fqdn))
+        .collect();
+    let mut specs: BTreeMap<String, RecordSpec> = BTreeMap::new();
+    for record in records {
+        if tracked_domain(&record.fqdn).is_some()
+                || tracked.contains(&record.fqdn)
+                || record.record_type == RecordType::SOA
+                || (record.record_type == RecordType::NS && record.fqdn == zone) {
+            continue
+        }
+        let spec = specs
+            .entry(record_name(&record.fqdn, &record.record_type))
+            .or_insert_with(|| RecordSpec {
+                fqdn: record.fqdn.clone(),
+                ttl: u32::try_from(record.ttl).ok(),
+                type_: record.record_type.clone(),
+                value: Some(vec![]),
+                value_from: None,
+                reverse: false,
+                provider_ref: Some(provider_ref.to_string()),
+                policy: None,
+                proxied: record.proxied,
+            });
+        if let Some(values) = spec.value.as_mut() {
+            values.push(record.value);
+        }
+    }
+    specs
+}
+
+/// Build a Record mirrored or adopted by a ZoneSync.
+fn build_record(sync: &ZoneSync, name: &str, spec: RecordSpec) -> Record {
+    let mut record = Record::new(name, spec);
+    record.metadata.namespace = Meta::namespace(sync);
+    if sync.spec.mode == ZoneSyncMode::ReadOnly {
+        let mut labels = BTreeMap::new();
+        labels.insert(ZONE_SYNC_LABEL.to_string(), Meta::name(sync));
+        record.metadata.labels = Some(labels);
+        let mut annotations = BTreeMap::new();
+        annotations.insert(IGNORE_ANNOTATION.to_string(), "true".to_string());
+        record.metadata.annotations = Some(annotations);
+        // Mirrored Records are deleted along with the ZoneSync. As the Records are ignored,
+        // deleting them does not remove any records at the provider.
+        record.metadata.owner_references = Some(vec![OwnerReference {
+            api_version: ZoneSync::API_VERSION.to_string(),
+            kind: ZoneSync::KIND.to_string(),
+            name: Meta::name(sync),
+            uid: sync.metadata.uid.clone().unwrap_or_default(),
+            ..OwnerReference::default()
+        }]);
+    }
+    record
+}
+
+/// Find the provider of the AresConfig named by `provider_ref`.
+fn find_provider<'a>(config: &'a [Arc<AresConfig>], provider_ref: &str) ->
+        Result<&'a dyn ProviderBackend> {
+    config
+        .iter()
+        .enumerate()
+        .find(|(index, ares)| ares.display_name(*index) == provider_ref)
+        .map(|(_, ares)| ares.provider.deref())
+        .ok_or(anyhow!("Unable to find provider: {}", provider_ref))
+}
+
+/// Get the spec of a Record for each type of each domain name in a zone at the provider of the
+/// AresConfig named by `provider_ref` that the domain filter allows, keyed by the name of the
+/// Record. The name of the zone is returned alongside the specs.
+pub async fn import_zone(config: &[Arc<AresConfig>], filter: &DomainFilter, provider_ref: &str,
+                         zone: &str) -> Result<(ZoneDomainName, BTreeMap<String, RecordSpec>)> {
+    let provider = find_provider(config, provider_ref)?;
+    let zone = provider.get_zone(&normalize_domain(zone)?).await?;
+    let records = provider
+        .get_all_records(&zone)
+        .map_ok(RecordObject::normalized)
+        .try_collect()
+        .await?;
+    let mut specs = group_records(provider_ref, &zone, records);
+    specs.retain(|_, spec| filter.allows(&spec.fqdn));
+    Ok((zone, specs))
+}
+
+/// Adopt the records of a Record by creating the Record, along with a tracking record for its
+/// domain name with the owner and cluster of `owner`, so that the records are managed by ARES
+/// from then on. The tracking record is written first, so that the controller never finds the
+/// records untracked; it is therefore written without the UID of the Record, which is only known
+/// once the Record is created. A Record that exists already, such as one created by an adoption
+/// that was interrupted, is left as is, so that adopting again resumes the adoption.
+pub async fn adopt(client: &Client, config: &[Arc<AresConfig>], owner: &Heritage,
+                   zone: &ZoneDomainName, record: &Record) -> Result<()> {
+    let namespace = Meta::namespace(record).ok_or(anyhow!("Missing record.meta.namespace"))?;
+    let provider_ref = record.spec.provider_ref.clone().unwrap_or_default();
+    let provider = find_provider(config, &provider_ref)?;
+    let heritage = Heritage {
+        resource: Some(format!("{}/{}", namespace, Meta::name(record))),
+        uid: None,
+        ..owner.clone()
+    };
+    // Another type of the domain name may have been adopted already.
+    let tracking = tracking_domain(&record.spec.fqdn);
+    if heritage.find_owned(&provider.get_records(zone, &tracking).await?).is_none() {
+        let tracking_record = RecordObject::builder(tracking, zone.clone(), RecordType::TXT)
+            .value(heritage.to_string())
+            .ttl(1)
+            .try_build()?;
+        provider._add_record(zone, &tracking_record).await?;
+    }
+    let api: Api<Record> = Api::namespaced(client.clone(), &namespace);
+    match api.create(&PostParams::default(), record).await {
+        Ok(_) => Ok(()),
+        Err(kube::Error::Api(e)) if e.code == 409 => {
+            debug!(record = %Meta::name(record), "Record exists already");
+            Ok(())
+        },
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Import the records of a zone as Records in a namespace, for `ares import`. The Records are
+/// printed as YAML, ready to be applied with `kubectl apply -f -`, unless `apply` is set, in which
+/// case the Records are created and their records adopted with the owner and cluster of `owner`.
+pub async fn import(client: &Client, config: &[Arc<AresConfig>], filter: &DomainFilter,
+                    owner: &Heritage, provider_ref: &str, zone: &str, namespace: &str,
+                    apply: bool) -> Result<()> {
+    let (zone, specs) = import_zone(config, filter, provider_ref, zone).await?;
+    for (name, spec) in specs {
+        let mut record = Record::new(&name, spec);
+        record.metadata.namespace = Some(namespace.to_string());
+        if apply {
+            adopt(client, config, owner, &zone, &record).await?;
+            println!("record.syntixi.io/{} adopted", name);
+            continue
+        }
+        let mut spec = serde_json::to_value(&record.spec)?;
+        if let Some(fields) = spec.as_object_mut() {
+            fields.retain(|_, value| !value.is_null());
+        }
+        let document = serde_json::json!({
+            "apiVersion": Record::API_VERSION,
+            "kind": Record::KIND,
+            "metadata": {
+                "name": name,
+                "namespace": namespace,
+            },
+            "spec": spec,
+        });
+        println!("{}", serde_yaml::to_string(&document)?);
+    }
+    Ok(())
+}
+
+/// Import the records of the zone of a ZoneSync, returning the number of Records mirrored or
+/// adopted.
+async fn sync_zone(client: &Client, config: &[Arc<AresConfig>],
+                   filter: &DomainFilter, owner: &Heritage, sync: &ZoneSync) -> Result<usize> {
+    let namespace = Meta::namespace(sync).ok_or(anyhow!("Missing zonesync.meta.namespace"))?;
+    let (zone, mut wanted) = import_zone(config, filter, &sync.spec.provider_ref,
+                                         &sync.spec.zone).await?;
+    let count = wanted.len();
+    let api: Api<Record> = Api::namespaced(client.clone(), &namespace);
+
+    if sync.spec.mode == ZoneSyncMode::Adopt {
+        // A domain name that can't be adopted doesn't hold back the others, and is adopted
+        // again on the next import.
+        let mut failed = 0;
+        for (name, spec) in wanted {
+            info!(record = %spec.fqdn, "Adopting records");
+            let record = build_record(sync, &name, spec);
+            if let Err(e) = adopt(client, config, owner, &zone, &record).await {
+                warn!(record = %record.spec.fqdn, "Unable to adopt records: {}", e);
+                failed += 1;
+            }
+        }
+        if failed > 0 {
+            return Err(anyhow!("Unable to adopt {} of {} Records", failed, count))
+        }
+        return Ok(count)
+    }
+
+    let selector = format!("{}={}", ZONE_SYNC_LABEL, Meta::name(sync));
+    let existing = api.list(&ListParams::default().labels(&selector)).await?.items;
+    for record in existing.iter() {
+        let name = Meta::name(record);
+        match wanted.remove(&name) {
+            Some(spec) => {
+                if serde_json::to_value(&spec)? != serde_json::to_value(&record.spec)? {
+                    debug!(record = %name, "Updating mirrored Record");
+                    let patch = serde_json::json!({
+                        "spec": spec,
+                    });
+                    api.patch(&name, &PatchParams::default(), serde_json::to_vec(&patch)?)
+                        .await?;
+                }
+            },
+            None => {
+                debug!(record = %name, "Removing mirrored Record");
+                api.delete(&name, &DeleteParams::default()).await?;
+            },
+        }
+    }
+    for (name, spec) in wanted {
+        debug!(record = %name, "Creating mirrored Record");
+        api.create(&PostParams::default(), &build_record(sync, &name, spec)).await?;
+    }
+    Ok(count)
+}
+
+/// Record the result of an import in the status of a ZoneSync.
+async fn patch_status(client: &Client, sync: &ZoneSync, result: &Result<usize>) -> Result<()> {
+    let namespace = Meta::namespace(sync).ok_or(anyhow!("Missing zonesync.meta.namespace"))?;
+    let api: Api<ZoneSync> = Api::namespaced(client.clone(), &namespace);
+    let patch = serde_json::json!({
+        "status": ZoneSyncStatus {
+            records: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            last_synced: Some(Utc::now().to_rfc3339()),
+        },
+    });
+    api.patch_status(&Meta::name(sync), &PatchParams::default(), serde_json::to_vec(&patch)?)
+        .await?;
+    Ok(())
+}
+
+/// Import the zone of every ZoneSync once its interval has passed since its last import. The
+/// owner and cluster of the Heritage are used for the tracking records of adopted records.
+pub async fn run(client: Client, config: Vec<Arc<AresConfig>>,
+                 filter: DomainFilter, owner: Heritage) {
+    let api: Api<ZoneSync> = Api::all(client.clone());
+    let mut last_synced: HashMap<String, Instant> = HashMap::new();
+    loop {
+        let syncs = match api.list(&ListParams::default()).await {
+            Ok(list) => list.items,
+            Err(kube::Error::Api(e)) if e.code == 404 => {
+                debug!("ZoneSync CRD is not installed");
+                return
+            },
+            Err(e) => {
+                warn!("Unable to list ZoneSyncs: {}", e);
+                vec![]
+            },
+        };
+        for sync in syncs.iter() {
+            let uid = sync.metadata.uid.clone().unwrap_or_default();
+            let interval = Duration::from_secs(sync.spec.interval.unwrap_or(300));
+            if last_synced.get(&uid).map(|x| x.elapsed() < interval).unwrap_or(false) {
+                continue
+            }
+            last_synced.insert(uid, Instant::now());
+            async {
+                let result = sync_zone(&client, &config, &filter, &owner, sync).await;
+                match &result {
+                    Ok(count) => info!(records = count, "Imported zone"),
+                    Err(e) => warn!("Unable to import zone: {}", e),
+                }
+                if let Err(e) = patch_status(&client, sync, &result).await {
+                    warn!("Unable to update ZoneSync status: {}", e);
+                }
+            }.instrument(info_span!("zonesync", zonesync = %Meta::name(sync))).await;
+        }
+        tokio::time::delay_for(CHECK_INTERVAL).await;
+    }
+}